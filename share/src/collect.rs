@@ -0,0 +1,205 @@
+//! Turns the paths given on the command line into the files to share.
+
+use std::{
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// Which of the collected files are shared.
+#[derive(Default)]
+pub(crate) struct Filters {
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    /// Skips what `.gitignore` files ignore when walking directories.
+    pub(crate) gitignore: bool,
+}
+
+impl Filters {
+    pub(crate) fn new<'a>(
+        include: impl IntoIterator<Item = &'a String>,
+        exclude: impl IntoIterator<Item = &'a String>,
+        gitignore: bool,
+    ) -> Result<Self, globset::Error> {
+        Ok(Self {
+            include: glob_set(include)?,
+            exclude: glob_set(exclude)?,
+            gitignore,
+        })
+    }
+
+    /// A pattern matches the path below the directory it was found in, or the path as given.
+    fn accepts(&self, path: &Path) -> bool {
+        self.include.as_ref().is_none_or(|set| set.is_match(path))
+            && !self.exclude.as_ref().is_some_and(|set| set.is_match(path))
+    }
+}
+
+fn glob_set<'a>(
+    patterns: impl IntoIterator<Item = &'a String>,
+) -> Result<Option<GlobSet>, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    let mut empty = true;
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+        empty = false;
+    }
+    if empty {
+        return Ok(None);
+    }
+    builder.build().map(Some)
+}
+
+/// Reads one path per line from the file, or from stdin for `-`, empty lines are skipped.
+pub(crate) fn read_files_from(source: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let reader: Box<dyn BufRead> = if source == Path::new("-") {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(std::io::BufReader::new(std::fs::File::open(source)?))
+    };
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if !line.trim().is_empty() {
+            paths.push(PathBuf::from(line));
+        }
+    }
+    Ok(paths)
+}
+
+/// Collects the files of `args` in order without duplicates. A path which does not exist is
+/// expanded as a glob pattern, the same way on every shell, and directories are walked. Files
+/// sharing a file name are refused.
+pub(crate) fn collect(args: &[PathBuf], filters: &Filters) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for arg in args {
+        let matched = if arg.exists() {
+            vec![arg.clone()]
+        } else {
+            expand_glob(arg)?
+        };
+        for path in matched {
+            if path.is_dir() {
+                walk_dir(&path, filters, &mut files)?;
+            } else if path.is_file() && filters.accepts(&path) {
+                files.push(path);
+            }
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    files.retain(|f| seen.insert(f.clone()));
+    // The receiver only gets the file name, a second file of the same name would overwrite the
+    // first one.
+    let mut names = std::collections::HashMap::new();
+    for file in &files {
+        if let Some(other) = names.insert(file.file_name(), file) {
+            return Err(format!(
+                "`{}` and `{}` have the same file name",
+                other.display(),
+                file.display()
+            ));
+        }
+    }
+    Ok(files)
+}
+
+fn expand_glob(pattern: &Path) -> Result<Vec<PathBuf>, String> {
+    let Some(pattern_str) = pattern.to_str().filter(|p| p.contains(['*', '?', '['])) else {
+        return Err(format!("`{}` does not exist", pattern.display()));
+    };
+    let options = glob::MatchOptions {
+        require_literal_leading_dot: true,
+        ..Default::default()
+    };
+    let matched: Vec<_> = glob::glob_with(pattern_str, options)
+        .map_err(|e| format!("Invalid pattern `{}`: {}", pattern_str, e))?
+        .filter_map(Result::ok)
+        .collect();
+    if matched.is_empty() {
+        return Err(format!("`{}` matches no files", pattern_str));
+    }
+    Ok(matched)
+}
+
+fn walk_dir(dir: &Path, filters: &Filters, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let walker = ignore::WalkBuilder::new(dir)
+        .standard_filters(false)
+        .hidden(true)
+        .git_ignore(filters.gitignore)
+        .git_exclude(filters.gitignore)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+    for entry in walker {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let path = entry.path();
+        let relative = path.strip_prefix(dir).unwrap_or(path);
+        if entry.file_type().is_some_and(|t| t.is_file()) && filters.accepts(relative) {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod collect_tests {
+    use std::path::PathBuf;
+
+    use super::{collect, Filters};
+
+    #[test]
+    fn collect_test() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().to_path_buf();
+        for file in [
+            "a.txt",
+            "b.log",
+            "sub/c.txt",
+            "sub/skip/d.txt",
+            ".hidden.txt",
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+        std::fs::write(root.join(".gitignore"), "skip/\n").unwrap();
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| f.strip_prefix(&root).unwrap().display().to_string())
+                .collect()
+        };
+
+        let all = collect(std::slice::from_ref(&root), &Filters::default()).unwrap();
+        assert_eq!(
+            names(all),
+            ["a.txt", "b.log", "sub/c.txt", "sub/skip/d.txt"]
+        );
+        let txt = Filters::new(&["*.txt".into()], &["sub/skip/*".into()], false).unwrap();
+        assert_eq!(
+            names(collect(std::slice::from_ref(&root), &txt).unwrap()),
+            ["a.txt", "sub/c.txt"]
+        );
+        let ignored = Filters {
+            gitignore: true,
+            ..Filters::default()
+        };
+        assert_eq!(
+            names(collect(std::slice::from_ref(&root), &ignored).unwrap()),
+            ["a.txt", "b.log", "sub/c.txt"]
+        );
+        let globbed = collect(
+            &[root.join("*.txt"), root.join("a.txt")],
+            &Filters::default(),
+        );
+        assert_eq!(names(globbed.unwrap()), ["a.txt"]);
+        assert!(collect(&[root.join("*.none")], &Filters::default()).is_err());
+
+        // Files are received by their name only.
+        std::fs::write(root.join("sub/a.txt"), "a").unwrap();
+        let clash = collect(&[root.join("a.txt"), root.join("sub")], &Filters::default());
+        assert!(clash.is_err());
+    }
+}
@@ -1,90 +1,848 @@
 #![allow(unused)]
-use std::{net::SocketAddr, ops::Deref, path::PathBuf};
+use std::{
+    io::IsTerminal,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use clap::{value_parser, Arg, ArgAction, Command};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use fshare_client::{blocking, ShareUpdate};
+use fshare_proto::{
+    common::{LocalResponse, Response},
+    consts, HostAddr, Hostname,
+};
+use serde_json::json;
 use smol_str::SmolStr;
 
-#[derive(Debug, Clone)]
-struct Hostname(SmolStr);
-
-impl Deref for Hostname {
-    type Target = SmolStr;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl std::fmt::Display for Hostname {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
-    }
-}
-
-impl std::str::FromStr for Hostname {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.is_empty() || s.len() > fshare_server::consts::FILE_NAME_LENGTH_LIMIT {
-            return Err(anyhow::anyhow!(
-                "The length of the host name must be in range  1..={}!",
-                fshare_server::consts::FILE_NAME_LENGTH_LIMIT
-            ));
-        }
-        Ok(Self(s.into()))
-    }
-}
+mod collect;
+mod output;
 
 mod id {
     pub const HOSTNAME: &str = "hostname";
     pub const PATH: &str = "PATH";
     pub const ADDRESS: &str = "address";
     pub const LOCAL_ONLY: &str = "local_only";
+    pub const REG_PEER: &str = "reg";
+    pub const CODE: &str = "code";
+    pub const APPROVE: &str = "approve";
+    pub const REJECT: &str = "reject";
+    pub const NEW_NAME: &str = "new_name";
+    pub const JSON: &str = "json";
+    pub const FILES_FROM: &str = "files_from";
+    pub const INCLUDE: &str = "include";
+    pub const EXCLUDE: &str = "exclude";
+    pub const GITIGNORE: &str = "gitignore";
+    pub const YES: &str = "yes";
+    pub const DIR: &str = "DIR";
+    pub const DELETE: &str = "delete";
 }
 
 fn main() {
     let matches = Command::new(env!("CARGO_CRATE_NAME"))
+        .arg(Arg::new(id::JSON).long(id::JSON).global(true).action(ArgAction::SetTrue).help("Print newline-delimited JSON objects instead of text, each names its kind in `event`. \nFailures are `error` objects with the `code` of the daemon response."))
         .arg(Arg::new(id::HOSTNAME).short('n').long(id::HOSTNAME).required(true).value_parser(value_parser!(Hostname)).help(color_print::cstr!("A hostname refers to a registered host (a network address), \nthe host should been registered on the remote side. \nThose hosts already registered could be found in a config file.\n Default config path is <bold>$HOME/.tinyfileshare/.config.toml</bold>.\n<bold>NOTE: It can not be empty, its length can not be out of 20 bytes</bold>")))
         .arg(
             Arg::new("PATH")
-                .num_args(1..=4)
-                .required(true)
+                .num_args(1..)
+                .required_unless_present(id::FILES_FROM)
                 .value_parser(value_parser!(PathBuf))
-                .action(ArgAction::Append).help("The paths of the files shared to the remote host (with the given hostname). \nThe number range of paths is 1..=4."),
+                .action(ArgAction::Append).help("The files shared to the remote host (with the given hostname). \nDirectories are shared recursively, a path which does not exist is expanded as a glob pattern such as 'docs/*.pdf'."),
         )
+        .arg(Arg::new(id::FILES_FROM).long("files-from").value_name("FILE").value_parser(value_parser!(PathBuf)).help("Read more paths from the file, one per line, or from stdin with `-`."))
+        .arg(Arg::new(id::INCLUDE).long(id::INCLUDE).value_name("GLOB").action(ArgAction::Append).help("Share only the files matching one of the patterns, directory contents are matched by their path below the directory."))
+        .arg(Arg::new(id::EXCLUDE).long(id::EXCLUDE).value_name("GLOB").action(ArgAction::Append).help("Skip the files matching one of the patterns."))
+        .arg(Arg::new(id::GITIGNORE).long(id::GITIGNORE).action(ArgAction::SetTrue).help("Skip what `.gitignore` files ignore when walking directories."))
+        .arg(Arg::new(id::YES).short('y').long(id::YES).action(ArgAction::SetTrue).help("Share many or large files without asking for confirmation."))
         .subcommand(
             Command::new("reg").short_flag('r')
                 .about("Register a host with hostname")
                 .arg(Arg::new(id::HOSTNAME).short('n').long(id::HOSTNAME).required(true).value_parser(value_parser!(Hostname)).help(color_print::cstr!("An unique hostname used as ID(at least on this machine) for the host. \n<bold>NOTE: It can not be empty, its length can not be out of 20 bytes</bold>")))
-                .arg(Arg::new(id::ADDRESS).short('a').long(id::ADDRESS).required(true).value_parser(value_parser!(SocketAddr)).help("The network address within port of the host. Such as 192.168.1.2:20"))
+                .arg(Arg::new(id::ADDRESS).short('a').long(id::ADDRESS).required(true).value_parser(value_parser!(HostAddr)).help("The network address within port of the host. Such as 192.168.1.2:10020 or pc.lan:10020"))
                 .arg(Arg::new(id::LOCAL_ONLY).short('l').long("local").action(ArgAction::SetTrue).value_parser(value_parser!(bool)).help("Register the given to local only. \nWhich actually means writing hostname and address to local configuration file only.")),
-        ).args_conflicts_with_subcommands(true).get_matches();
-    match matches.subcommand() {
+        )
+        .subcommand(
+            Command::new("peers")
+                .about("List the daemons discovered on the LAN")
+                .arg(Arg::new(id::REG_PEER).long(id::REG_PEER).value_parser(value_parser!(Hostname)).help("Register the discovered daemon with the given name instead of listing.")),
+        )
+        .subcommand(
+            Command::new("pair")
+                .about("Pair with another daemon using a one-time code, registering each other on both sides")
+                .long_about("Without arguments a pairing code is shown and awaited, type it on the other machine with `--code` and `--address`.")
+                .arg(Arg::new(id::CODE).short('c').long(id::CODE).requires(id::ADDRESS).help("The pairing code shown by the other daemon."))
+                .arg(Arg::new(id::ADDRESS).short('a').long(id::ADDRESS).requires(id::CODE).value_parser(value_parser!(SocketAddr)).help("The network address within port of the other daemon.")),
+        )
+        .subcommand(
+            Command::new("requests")
+                .about("List the registration requests of other daemons waiting for approval")
+                .arg(Arg::new(id::APPROVE).long(id::APPROVE).conflicts_with(id::REJECT).value_parser(value_parser!(Hostname)).help("Approve the request of the given host, registering it on both sides."))
+                .arg(Arg::new(id::REJECT).long(id::REJECT).value_parser(value_parser!(Hostname)).help("Reject the request of the given host.")),
+        )
+        .subcommand(
+            Command::new("hosts")
+                .about("List the registered hosts, or change one of them")
+                .subcommand(Command::new("rm").about("Unregister a host").arg(Arg::new(id::HOSTNAME).required(true).value_parser(value_parser!(Hostname))))
+                .subcommand(Command::new("rename").about("Rename a registered host, its host groups follow").arg(Arg::new(id::HOSTNAME).required(true).value_parser(value_parser!(Hostname))).arg(Arg::new(id::NEW_NAME).required(true).value_parser(value_parser!(Hostname))))
+                .subcommand(Command::new("set-addr").about("Replace the addresses of a registered host, they are tried in order").arg(Arg::new(id::HOSTNAME).required(true).value_parser(value_parser!(Hostname))).arg(Arg::new(id::ADDRESS).required(true).num_args(1..).value_parser(value_parser!(HostAddr)).help("Socket addresses or DNS names with port, such as 192.168.1.2:10020 or pc.lan:10020"))),
+        )
+        .subcommand(
+            Command::new("sync")
+                .about("Mirror a directory to a registered host, only new or changed files are sent")
+                .arg(Arg::new(id::HOSTNAME).short('n').long(id::HOSTNAME).required(true).value_parser(value_parser!(Hostname)).help("The registered host the directory is mirrored to."))
+                .arg(Arg::new(id::DIR).required(true).value_parser(value_parser!(PathBuf)).help("The directory, it is mirrored below the mirror directory of the host's save directory."))
+                .arg(Arg::new(id::DELETE).long(id::DELETE).action(ArgAction::SetTrue).help("Delete the files on the host which were removed from the directory.")),
+        )
+        .subcommand(Command::new("status").about("Show the state of the daemon and its transfers"))
+        .subcommand(Command::new("events").about("Print the events of the daemon as they happen, one per line"))
+        .args_conflicts_with_subcommands(true).get_matches();
+    output::set_json(matches.get_flag(id::JSON));
+    let client = match blocking::Client::new() {
+        Ok(client) => client,
+        Err(e) => std::process::exit(report(&e)),
+    };
+    let res = match matches.subcommand() {
+        Some(("peers", sub_matches)) => match sub_matches.get_one::<Hostname>(id::REG_PEER) {
+            Some(name) => reg_peer(&client, name),
+            None => list_peers(&client),
+        },
+        Some(("pair", sub_matches)) => {
+            let code = sub_matches.get_one::<String>(id::CODE);
+            let address = sub_matches.get_one::<SocketAddr>(id::ADDRESS);
+            match (code, address) {
+                (Some(code), Some(address)) => pair_with(&client, code, *address),
+                _ => offer_pairing(&client),
+            }
+        }
+        Some(("requests", sub_matches)) => match (
+            sub_matches.get_one::<Hostname>(id::APPROVE),
+            sub_matches.get_one::<Hostname>(id::REJECT),
+        ) {
+            (Some(name), _) => decide_reg_request(&client, name, true),
+            (_, Some(name)) => decide_reg_request(&client, name, false),
+            _ => list_reg_requests(&client),
+        },
+        Some(("sync", sub_matches)) => {
+            exit_with(sync_dir(
+                &client,
+                sub_matches.get_one::<Hostname>(id::HOSTNAME).unwrap(),
+                sub_matches.get_one::<PathBuf>(id::DIR).unwrap(),
+                sub_matches.get_flag(id::DELETE),
+            ));
+            Ok(())
+        }
+        Some(("status", _)) => show_status(&client),
+        Some(("events", _)) => watch_events(&client),
+        Some(("hosts", sub_matches)) => match sub_matches.subcommand() {
+            Some((sub_command, m)) => {
+                let name = m.get_one::<Hostname>(id::HOSTNAME).unwrap().clone();
+                let res = match sub_command {
+                    "rm" => client.unregister(name.clone()),
+                    "rename" => client.rename(
+                        name.clone(),
+                        m.get_one::<Hostname>(id::NEW_NAME).unwrap().clone(),
+                    ),
+                    _ => client.set_addr(
+                        name.clone(),
+                        m.get_many::<HostAddr>(id::ADDRESS)
+                            .unwrap()
+                            .cloned()
+                            .collect(),
+                    ),
+                };
+                res.map(|()| {
+                    if output::json() {
+                        output::emit("host_updated", json!({ "name": name }));
+                    }
+                })
+            }
+            None => list_hosts(&client),
+        },
         Some((_, sub_matches)) => {
             let hostname = sub_matches.get_one::<Hostname>(id::HOSTNAME).unwrap();
-            let address = sub_matches.get_one::<SocketAddr>(id::ADDRESS).unwrap();
+            let address = sub_matches.get_one::<HostAddr>(id::ADDRESS).unwrap();
             let local_only = sub_matches.get_flag(id::LOCAL_ONLY);
-            println!("hostname = {}, address = {}, local_only = {}", hostname, address, local_only);
+            reg_host(&client, hostname.clone(), address.clone(), local_only)
         }
         None => {
             let hostname = matches.get_one::<Hostname>(id::HOSTNAME).unwrap();
-            println!("No subcommand, hostname = {}", hostname);
-            for (idx, p) in matches.get_many::<PathBuf>(id::PATH).unwrap().enumerate() {
-                println!("The {}th path: {}", idx, p.to_string_lossy());
+            let files = match collect_files(&matches) {
+                Ok(files) => files,
+                Err(message) => {
+                    output::error(LocalResponse::AnyPathInvalid.to_str_unchecked(), &message);
+                    std::process::exit(exit_code::INVALID_PATH);
+                }
+            };
+            if !matches.get_flag(id::YES) && !confirm_share(hostname, &files) {
+                std::process::exit(exit_code::FAILURE);
+            }
+            exit_with(share_files(&client, hostname, &files));
+            Ok(())
+        }
+    };
+    if let Err(e) = res {
+        std::process::exit(report(&e));
+    }
+}
+
+/// Exit codes telling why a share or registration did not fully succeed, 2 is taken by usage
+/// errors.
+mod exit_code {
+    pub const FAILURE: i32 = 1;
+    pub const UNREGISTERED_HOST: i32 = 3;
+    pub const UNREACHABLE_HOST: i32 = 4;
+    pub const INVALID_PATH: i32 = 5;
+    pub const PARTIAL_SUCCESS: i32 = 6;
+}
+
+fn exit_with(code: i32) {
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+fn failure_code(resp: &Response) -> i32 {
+    match resp {
+        Response::InvalidHostname
+        | Response::Local(
+            LocalResponse::UnregisteredHostname
+            | LocalResponse::RemoteUnregistered
+            | LocalResponse::RelayUnknownTarget,
+        ) => exit_code::UNREGISTERED_HOST,
+        Response::Local(
+            LocalResponse::UnreachableAddress(_) | LocalResponse::RelayTargetUnreachable,
+        ) => exit_code::UNREACHABLE_HOST,
+        Response::Local(LocalResponse::AnyPathInvalid) => exit_code::INVALID_PATH,
+        Response::Local(LocalResponse::FilesSucceeded(_)) => exit_code::PARTIAL_SUCCESS,
+        Response::Local(LocalResponse::Host(_, resp)) => {
+            failure_code(&Response::Local((**resp).clone()))
+        }
+        _ => exit_code::FAILURE,
+    }
+}
+
+/// The description of a response with the details it carries.
+fn response_message(resp: &Response) -> String {
+    match resp {
+        Response::Local(LocalResponse::UnreachableAddress(addr)) => {
+            format!("{} at {}", resp.description(), addr)
+        }
+        Response::Local(LocalResponse::FilesSucceeded(count)) => {
+            format!("Only {} of the files were received", count)
+        }
+        Response::Local(LocalResponse::Synced(sent, deleted)) => {
+            format!(
+                "{}, {} files sent and {} deleted",
+                resp.description(),
+                sent,
+                deleted
+            )
+        }
+        Response::Local(
+            LocalResponse::RemoteQuotaExceeded(count)
+            | LocalResponse::RemoteChecksumMismatch(count),
+        ) => format!(
+            "{}, {} of the files were received",
+            resp.description(),
+            count
+        ),
+        _ => resp.description().into(),
+    }
+}
+
+/// Prints the error and returns the exit code for it.
+fn report(e: &fshare_client::Error) -> i32 {
+    match e {
+        fshare_client::Error::Daemon(resp) => {
+            output::error(&output::response_code(resp), &response_message(resp));
+            failure_code(resp)
+        }
+        e => {
+            let code = match e {
+                fshare_client::Error::Connect { .. } => "CONNECT_FAILED",
+                fshare_client::Error::InvalidValue(_) => "INVALID_VALUE",
+                fshare_client::Error::Protocol(_) => "PROTOCOL_VIOLATION",
+                fshare_client::Error::Closed => "CONNECTION_CLOSED",
+                _ => "IO_ERROR",
+            };
+            output::error(code, &e.to_string());
+            exit_code::FAILURE
+        }
+    }
+}
+
+fn list_peers(client: &blocking::Client) -> fshare_client::Result<()> {
+    for peer in client.peers()? {
+        if output::json() {
+            output::emit(
+                "peer",
+                json!({ "name": peer.name, "addr": peer.addr, "fingerprint": peer.fingerprint }),
+            );
+        } else {
+            println!("{:<20} {:<40} {}", peer.name, peer.addr, peer.fingerprint);
+        }
+    }
+    Ok(())
+}
+
+fn reg_peer(client: &blocking::Client, name: &Hostname) -> fshare_client::Result<()> {
+    let replaced = client.register_peer(name.clone())?;
+    print_registered(name, None, replaced);
+    Ok(())
+}
+
+fn since(unix_secs: Option<u64>) -> String {
+    let Some(secs) = unix_secs else {
+        return "never".into();
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    match now.saturating_sub(secs) {
+        s if s < 60 => format!("{}s ago", s),
+        s if s < 3600 => format!("{}m ago", s / 60),
+        s if s < 86400 => format!("{}h ago", s / 3600),
+        s => format!("{}d ago", s / 86400),
+    }
+}
+
+fn show_status(client: &blocking::Client) -> fshare_client::Result<()> {
+    let status = client.status()?;
+    if output::json() {
+        let values: serde_json::Map<_, _> = status
+            .values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.as_str().into()))
+            .collect();
+        output::emit(
+            "status",
+            json!({ "values": values, "transfers": status.transfers }),
+        );
+        return Ok(());
+    }
+    for (key, value) in &status.values {
+        match key.as_str() {
+            "uptime" => println!("{:<20} {}", key, since_duration(value)),
+            _ => println!("{:<20} {}", key, value),
+        }
+    }
+    if status.transfers.is_empty() {
+        println!("No transfers in progress");
+        return Ok(());
+    }
+    println!(
+        "\n{:<6} {:<8} {:<7} {:<24} {:>8} FILE",
+        "ID", "KIND", "STATE", "HOST", "PROGRESS"
+    );
+    for t in &status.transfers {
+        let progress = match t.total {
+            Some(total) if total > 0 => format!("{:.1}%", t.done as f64 * 100.0 / total as f64),
+            _ => format!("{}B", t.done),
+        };
+        println!(
+            "{:<6} {:<8} {:<7} {:<24} {:>8} {}",
+            t.id,
+            t.direction.as_str(),
+            if t.active { "active" } else { "queued" },
+            t.host,
+            progress,
+            t.file
+        );
+    }
+    Ok(())
+}
+
+fn watch_events(client: &blocking::Client) -> fshare_client::Result<()> {
+    for event in client.events()? {
+        let event = event?;
+        if output::json() {
+            let mut fields = serde_json::to_value(&event).unwrap_or_default();
+            if let Some(fields) = fields.as_object_mut() {
+                fields.remove("kind");
             }
+            output::emit(event.kind(), fields);
+        } else {
+            println!("{}", event);
         }
     }
+    Ok(())
+}
+
+fn since_duration(secs: &str) -> String {
+    let secs = secs.parse::<u64>().unwrap_or_default();
+    format!(
+        "{}d {:02}:{:02}:{:02}",
+        secs / 86400,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
 
+fn list_hosts(client: &blocking::Client) -> fshare_client::Result<()> {
+    let hosts = client.hosts()?;
+    if output::json() {
+        for host in hosts {
+            output::emit("host", serde_json::to_value(host).unwrap_or_default());
+        }
+        return Ok(());
+    }
+    println!(
+        "{:<20} {:<40} {:<16} {:<6} {:<10} {:<10} DESCRIPTION",
+        "NAME", "ADDRESSES", "KEY", "ACCEPT", "SEEN", "TRANSFER"
+    );
+    for host in hosts {
+        let addrs: Vec<_> = host.addrs.iter().map(ToString::to_string).collect();
+        println!(
+            "{:<20} {:<40} {:<16} {:<6} {:<10} {:<10} {}",
+            host.name,
+            addrs.join(","),
+            host.fingerprint.as_deref().unwrap_or("-"),
+            if host.auto_accept { "accept" } else { "deny" },
+            since(host.last_seen),
+            since(host.last_transfer),
+            host.description
+        );
+    }
+    Ok(())
 }
 
-// fn connect_daemon() -> anyhow::Result<>
+fn list_reg_requests(client: &blocking::Client) -> fshare_client::Result<()> {
+    for (name, addr) in client.registration_requests()? {
+        if output::json() {
+            output::emit("reg_request", json!({ "name": name, "addr": addr }));
+        } else {
+            println!("{:<20} {}", name, addr);
+        }
+    }
+    Ok(())
+}
 
-fn share_files() -> anyhow::Result<()> {
-    todo!()
+fn decide_reg_request(
+    client: &blocking::Client,
+    name: &Hostname,
+    approve: bool,
+) -> fshare_client::Result<()> {
+    if approve {
+        let replaced = client.approve_registration(name.clone())?;
+        print_registered(name, None, replaced);
+    } else {
+        client.reject_registration(name.clone())?;
+        if output::json() {
+            output::emit("reg_rejected", json!({ "name": name }));
+        } else {
+            println!("Rejected the request of `{}`", name);
+        }
+    }
+    Ok(())
 }
 
-fn reg_host(local: bool) -> anyhow::Result<()> {
-    todo!()
+fn offer_pairing(client: &blocking::Client) -> fshare_client::Result<()> {
+    let offer = client.pair_offer()?;
+    if output::json() {
+        output::emit("pair_code", json!({ "code": offer.code() }));
+    } else {
+        println!(
+            "Pairing code: {}\nWaiting for the other side...",
+            offer.code()
+        );
+    }
+    print_paired(offer.wait()?);
+    Ok(())
+}
+
+fn pair_with(
+    client: &blocking::Client,
+    code: &str,
+    address: SocketAddr,
+) -> fshare_client::Result<()> {
+    print_paired(client.pair(code, address)?);
+    Ok(())
+}
+
+fn print_paired(paired: fshare_client::Paired) {
+    if output::json() {
+        output::emit(
+            "paired",
+            json!({ "name": paired.name, "addr": paired.addr }),
+        );
+    } else {
+        println!("Paired with {} {}", paired.name, paired.addr);
+    }
+}
+
+fn print_registered(name: &Hostname, addr: Option<&HostAddr>, replaced: Option<HostAddr>) {
+    if output::json() {
+        output::emit(
+            "registered",
+            json!({ "name": name, "addr": addr, "replaced": replaced }),
+        );
+        return;
+    }
+    match (addr, replaced) {
+        (Some(addr), Some(replaced)) => {
+            println!("Registered `{}` at {}, replacing {}", name, addr, replaced)
+        }
+        (Some(addr), None) => println!("Registered `{}` at {}", name, addr),
+        (None, Some(replaced)) => println!("Registered `{}`, replacing {}", name, replaced),
+        (None, None) => println!("Registered `{}`", name),
+    }
+}
+
+/// The files of the PATH arguments and of `--files-from`, filtered and with directories walked.
+fn collect_files(matches: &ArgMatches) -> Result<Vec<PathBuf>, String> {
+    let filters = collect::Filters::new(
+        matches
+            .get_many::<String>(id::INCLUDE)
+            .into_iter()
+            .flatten(),
+        matches
+            .get_many::<String>(id::EXCLUDE)
+            .into_iter()
+            .flatten(),
+        matches.get_flag(id::GITIGNORE),
+    )
+    .map_err(|e| e.to_string())?;
+    let mut args: Vec<PathBuf> = matches
+        .get_many::<PathBuf>("PATH")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    if let Some(source) = matches.get_one::<PathBuf>(id::FILES_FROM) {
+        let paths = collect::read_files_from(source)
+            .map_err(|e| format!("Reading `{}` failed: {}", source.display(), e))?;
+        args.extend(paths);
+    }
+    let files = collect::collect(&args, &filters)?;
+    if files.is_empty() {
+        return Err("No files to share".to_owned());
+    }
+    Ok(files)
+}
+
+/// Asks before sending many or large files, a share which cannot be confirmed needs `--yes`.
+fn confirm_share(hostname: &Hostname, files: &[PathBuf]) -> bool {
+    const FILES_LIMIT: usize = 20;
+    const SIZE_LIMIT: u64 = 1 << 30;
+    let size: u64 = files
+        .iter()
+        .filter_map(|f| f.metadata().ok())
+        .map(|m| m.len())
+        .sum();
+    if files.len() <= FILES_LIMIT && size <= SIZE_LIMIT {
+        return true;
+    }
+    let summary = format!("{} files, {}", files.len(), human_size(size));
+    if output::json() || !std::io::stdin().is_terminal() {
+        output::error(
+            "CONFIRMATION_REQUIRED",
+            &format!("Sharing {} needs confirmation, pass --yes", summary),
+        );
+        return false;
+    }
+    eprint!("Share {} with `{}`? [y/N] ", summary, hostname);
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if size < 1024 {
+        return format!("{} bytes", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Sends the files, as many per request as the protocol allows, and reports the result of every
+/// host. The exit code tells the worst outcome, a request failing on every host stops the rest.
+fn share_files(client: &blocking::Client, hostname: &Hostname, files: &[PathBuf]) -> i32 {
+    if let Some(path) = files.iter().find(|p| !p.is_file()) {
+        output::error(
+            LocalResponse::AnyPathInvalid.to_str_unchecked(),
+            &format!("`{}` is not a file", path.display()),
+        );
+        return exit_code::INVALID_PATH;
+    }
+    let mut codes = Vec::new();
+    let mut sent = 0;
+    // Every host with whether all of its requests succeeded.
+    let mut hosts: Vec<(SmolStr, bool)> = Vec::new();
+    for batch in files.chunks(consts::NUMBER_PATHS_PER_REQUEST) {
+        let outcomes = match share_batch(client, hostname, batch) {
+            Ok(outcomes) => outcomes,
+            Err(code) => return code,
+        };
+        for (host, succeeded) in outcomes.hosts {
+            match hosts.iter_mut().find(|(h, _)| *h == host) {
+                Some((_, all)) => *all &= succeeded,
+                None => hosts.push((host, succeeded)),
+            }
+        }
+        codes.push(outcomes.code);
+        if outcomes.code != 0 && outcomes.code != exit_code::PARTIAL_SUCCESS {
+            break;
+        }
+        sent += batch.len();
+    }
+    if sent < files.len() && !output::json() {
+        eprintln!("Stopped, {} files were not sent", files.len() - sent);
+    }
+    let code = combined_exit_code(&codes);
+    if output::json() {
+        output::emit(
+            "summary",
+            json!({
+                "files": files.len(),
+                "hosts": hosts.len(),
+                "succeeded": hosts.iter().filter(|(_, all)| *all).count(),
+                "exit_code": code,
+            }),
+        );
+    }
+    code
+}
+
+/// The exit code of a share made of several requests, a failure after a success is partial.
+fn combined_exit_code(codes: &[i32]) -> i32 {
+    match codes.iter().find(|&&c| c != 0) {
+        None => 0,
+        Some(&first) if codes.len() == 1 => first,
+        Some(_)
+            if codes
+                .iter()
+                .any(|&c| c == 0 || c == exit_code::PARTIAL_SUCCESS) =>
+        {
+            exit_code::PARTIAL_SUCCESS
+        }
+        Some(&first) => first,
+    }
+}
+
+/// Mirrors the directory, reported like a share to a single host.
+fn sync_dir(client: &blocking::Client, hostname: &Hostname, dir: &Path, delete: bool) -> i32 {
+    if !dir.is_dir() {
+        output::error(
+            LocalResponse::AnyPathInvalid.to_str_unchecked(),
+            &format!("`{}` is not a directory", dir.display()),
+        );
+        return exit_code::INVALID_PATH;
+    }
+    let progress = match client.sync(hostname.clone(), dir, delete) {
+        Ok(progress) => progress,
+        Err(e) => return report(&e),
+    };
+    let outcome = match report_progress(hostname, progress) {
+        Ok(outcome) => outcome,
+        Err(code) => return code,
+    };
+    if output::json() {
+        output::emit(
+            "summary",
+            json!({
+                "hosts": outcome.hosts.len(),
+                "succeeded": outcome.hosts.iter().filter(|(_, ok)| *ok).count(),
+                "exit_code": outcome.code,
+            }),
+        );
+    }
+    outcome.code
+}
+
+/// The hosts of one request with whether they got every file, and its exit code.
+struct BatchOutcome {
+    hosts: Vec<(SmolStr, bool)>,
+    code: i32,
+}
+
+/// Sends up to [`consts::NUMBER_PATHS_PER_REQUEST`] files, `Err` carries the exit code of a
+/// share which cannot go on.
+fn share_batch(
+    client: &blocking::Client,
+    hostname: &Hostname,
+    paths: &[PathBuf],
+) -> Result<BatchOutcome, i32> {
+    match client.share([hostname.as_str()], paths) {
+        Ok(progress) => report_progress(hostname, progress),
+        Err(e) => Err(report(&e)),
+    }
+}
+
+/// Prints the updates of a share until it finished.
+fn report_progress(
+    hostname: &Hostname,
+    mut progress: blocking::ShareProgress<'_>,
+) -> Result<BatchOutcome, i32> {
+    let show_bars = !output::json() && std::io::stdout().is_terminal();
+    let mut pending = None;
+    let mut file = SmolStr::default();
+    let mut files_failed = false;
+    while let Some(update) = pending.take().or_else(|| progress.next()) {
+        match update {
+            Ok(ShareUpdate::Queued { transfer }) => {
+                if output::json() {
+                    output::emit("transfer", json!({ "transfer": transfer }));
+                } else {
+                    println!("Sending to `{}`, transfer {}", hostname, transfer);
+                }
+            }
+            Ok(ShareUpdate::FileStarted { file, .. }) if show_bars => {
+                pending = render_file_progress(&file, &mut progress)
+            }
+            Ok(ShareUpdate::FileStarted { file: name, size }) => {
+                if output::json() {
+                    output::emit("file_started", json!({ "file": name, "size": size }));
+                } else if let Some(size) = size {
+                    println!("Sending {} ({} bytes)", name, size);
+                } else {
+                    println!("Sending {}", name);
+                }
+                file = name;
+            }
+            Ok(ShareUpdate::Progress(fraction)) => {
+                if output::json() {
+                    output::emit("progress", json!({ "file": file, "fraction": fraction }));
+                }
+            }
+            Ok(ShareUpdate::FileSent { host, file }) => {
+                if output::json() {
+                    output::emit("file_sent", json!({ "host": host, "file": file }));
+                } else {
+                    println!("{}: sent {}", host, file);
+                }
+            }
+            Ok(ShareUpdate::FileFailed { host, file }) => {
+                files_failed = true;
+                if output::json() {
+                    output::emit("file_failed", json!({ "host": host, "file": file }));
+                } else if let Some(host) = host {
+                    eprintln!("{}: sending {} failed", host, file);
+                } else {
+                    eprintln!("Skipped {}, it is unreadable or too large", file);
+                }
+            }
+            Ok(ShareUpdate::Finished(outcomes)) => {
+                for outcome in &outcomes {
+                    let message = response_message(&outcome.response);
+                    if output::json() {
+                        output::emit(
+                            "result",
+                            json!({
+                                "host": outcome.host,
+                                "succeeded": outcome.succeeded(),
+                                "code": output::response_code(&outcome.response),
+                                "message": message,
+                            }),
+                        );
+                    } else if outcome.succeeded() {
+                        println!("{}: {}", outcome.host, message);
+                    } else {
+                        eprintln!("{}: {}", outcome.host, message);
+                    }
+                }
+                let succeeded = outcomes.iter().filter(|o| o.succeeded()).count();
+                let code = match outcomes.iter().find(|o| !o.succeeded()) {
+                    None if !files_failed => 0,
+                    None => exit_code::PARTIAL_SUCCESS,
+                    Some(_) if succeeded > 0 => exit_code::PARTIAL_SUCCESS,
+                    Some(failed) => failure_code(&failed.response),
+                };
+                let hosts = outcomes
+                    .into_iter()
+                    .map(|o| {
+                        let succeeded = o.succeeded() && !files_failed;
+                        (o.host, succeeded)
+                    })
+                    .collect();
+                return Ok(BatchOutcome { hosts, code });
+            }
+            Ok(ShareUpdate::Cancelled) => {
+                let resp = LocalResponse::Cancelled;
+                output::error(resp.clone().to_str_unchecked(), resp.description());
+                return Err(exit_code::FAILURE);
+            }
+            Err(e) => return Err(report(&e)),
+        }
+    }
+    Err(report(&fshare_client::Error::Closed))
+}
+
+/// Draws the progress of one file until an update of something else arrives, which is returned.
+fn render_file_progress(
+    file: &str,
+    progress: &mut impl Iterator<Item = fshare_client::Result<ShareUpdate>>,
+) -> Option<fshare_client::Result<ShareUpdate>> {
+    const TITLE_LIMIT: usize = 24;
+    let title = if file.chars().count() > TITLE_LIMIT {
+        let head: String = file.chars().take(TITLE_LIMIT - 3).collect();
+        format!("{}...", head)
+    } else {
+        file.to_owned()
+    };
+    let mut next = None;
+    let mut fraction = 0.0;
+    // A fraction over 1 ends the bar, a file sent completely is shown full before.
+    let _ = termrender::progress_bar_inline(
+        || -> Result<f64, std::convert::Infallible> {
+            if next.is_some() {
+                return Ok(2.0);
+            }
+            match progress.next() {
+                Some(Ok(ShareUpdate::Progress(p))) => {
+                    fraction = p.unwrap_or(fraction).min(1.0);
+                    Ok(fraction)
+                }
+                update => {
+                    let completed = matches!(
+                        update,
+                        Some(Ok(ShareUpdate::FileStarted { .. }
+                            | ShareUpdate::FileSent { .. }
+                            | ShareUpdate::Finished(_)))
+                    );
+                    next = Some(update);
+                    Ok(if completed { 1.0 } else { 2.0 })
+                }
+            }
+        },
+        true,
+        0,
+        Duration::ZERO,
+        Some(title),
+        None,
+    );
+    next.flatten()
+}
+
+/// Registers the host on this side, and unless `local` asks it to register this host too.
+fn reg_host(
+    client: &blocking::Client,
+    name: Hostname,
+    addr: HostAddr,
+    local: bool,
+) -> fshare_client::Result<()> {
+    if !local {
+        if output::json() {
+            output::emit(
+                "registration_pending",
+                json!({ "name": name, "addr": addr }),
+            );
+        } else {
+            println!("Waiting for `{}` at {} to confirm", name, addr);
+        }
+    }
+    let replaced = client.register(name.clone(), addr.clone(), !local)?;
+    print_registered(&name, Some(&addr), replaced);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -97,6 +855,70 @@ mod tests {
         println!("CARGO_CRATE_NMAE = {}", env!("CARGO_CRATE_NAME"));
     }
 
+    #[test]
+    fn failure_code_test() {
+        use fshare_proto::common::{LocalResponse, Response};
+
+        use super::{exit_code, failure_code};
+
+        for (line, code) in [
+            ("UNREG_HOSTNAME", exit_code::UNREGISTERED_HOST),
+            ("UNREACHABLE 10.0.0.2:10020", exit_code::UNREACHABLE_HOST),
+            (
+                "HOST nas UNREACHABLE 10.0.0.2:10020",
+                exit_code::UNREACHABLE_HOST,
+            ),
+            ("ANY_PATH_INVALID", exit_code::INVALID_PATH),
+            ("FILES_SUCCEEDED 1", exit_code::PARTIAL_SUCCESS),
+            ("PAIR_FAILED", exit_code::FAILURE),
+        ] {
+            assert_eq!(
+                failure_code(&line.parse::<Response>().unwrap()),
+                code,
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            failure_code(&Response::Local(LocalResponse::RemoteUnregistered)),
+            exit_code::UNREGISTERED_HOST
+        );
+    }
+
+    #[test]
+    fn combined_exit_code_test() {
+        use super::{combined_exit_code, exit_code};
+
+        assert_eq!(combined_exit_code(&[0, 0]), 0);
+        assert_eq!(
+            combined_exit_code(&[exit_code::UNREACHABLE_HOST]),
+            exit_code::UNREACHABLE_HOST
+        );
+        assert_eq!(
+            combined_exit_code(&[0, exit_code::UNREACHABLE_HOST]),
+            exit_code::PARTIAL_SUCCESS
+        );
+        assert_eq!(
+            combined_exit_code(&[exit_code::INVALID_PATH, exit_code::FAILURE]),
+            exit_code::INVALID_PATH
+        );
+    }
+
+    #[test]
+    fn response_code_test() {
+        use fshare_proto::common::Response;
+
+        use crate::output::response_code;
+
+        for (line, code) in [
+            ("FILES_SUCCEEDED 1", "FILES_SUCCEEDED"),
+            ("HOST nas UNREACHABLE 10.0.0.2:10020", "UNREACHABLE"),
+            ("INVALID_HOSTNAME", "INVALID_HOSTNAME"),
+        ] {
+            assert_eq!(response_code(&line.parse::<Response>().unwrap()), code);
+        }
+    }
+
     #[test]
     fn socket_parse_test() {
         let addr_str = "192.168.3.40:10020";
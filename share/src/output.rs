@@ -0,0 +1,45 @@
+//! What the CLI prints, text for people or with `--json` one JSON object per line.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use fshare_proto::common::{LocalResponse, Response};
+use serde_json::{json, Map, Value};
+use smol_str::{SmolStr, ToSmolStr};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+pub(crate) fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Prints `{"event": <event>, ...}` with the members of `fields`, which is an object.
+pub(crate) fn emit(event: &str, fields: Value) {
+    let mut object = Map::new();
+    object.insert("event".into(), event.into());
+    if let Value::Object(fields) = fields {
+        object.extend(fields);
+    }
+    println!("{}", Value::Object(object));
+}
+
+/// Reports a failure on stderr, or as an `error` object with `--json`.
+pub(crate) fn error(code: &str, message: &str) {
+    if json() {
+        emit("error", json!({ "code": code, "message": message }));
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+/// The tag of the response line, the inner one for the line of a host.
+pub(crate) fn response_code(resp: &Response) -> SmolStr {
+    let line = match resp {
+        Response::Local(LocalResponse::Host(_, inner)) => inner.to_smolstr(),
+        resp => resp.to_smolstr(),
+    };
+    line.split(' ').next().unwrap_or_default().into()
+}
@@ -0,0 +1,186 @@
+//! A blocking wrapper of [`crate::Client`] for programs without an async runtime.
+
+use std::{net::SocketAddr, path::Path};
+
+use fshare_proto::{common::HostInfo, Event, HostAddr, Hostname};
+use smol_str::SmolStr;
+use tokio::runtime::Runtime;
+
+use crate::{Paired, Peer, Result, ShareUpdate, Status};
+
+/// Runs the requests of the async client on its own single-threaded runtime.
+pub struct Client {
+    inner: crate::Client,
+    runtime: Runtime,
+}
+
+impl Client {
+    /// A client of the daemon started with the default config file.
+    pub fn new() -> Result<Self> {
+        Self::from_async(crate::Client::new())
+    }
+
+    pub fn with_socket_name(socket_name: impl Into<SmolStr>) -> Result<Self> {
+        Self::from_async(crate::Client::with_socket_name(socket_name))
+    }
+
+    fn from_async(inner: crate::Client) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+        Ok(Self { inner, runtime })
+    }
+
+    pub fn socket_name(&self) -> &str {
+        self.inner.socket_name()
+    }
+
+    /// See [`crate::Client::share`], the updates are read as the iterator advances.
+    pub fn share<H, P>(&self, hosts: H, paths: P) -> Result<ShareProgress<'_>>
+    where
+        H: IntoIterator,
+        H::Item: Into<SmolStr>,
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        let inner = self.runtime.block_on(self.inner.share(hosts, paths))?;
+        Ok(ShareProgress {
+            inner,
+            runtime: &self.runtime,
+        })
+    }
+
+    pub fn sync(
+        &self,
+        host: Hostname,
+        dir: impl AsRef<Path>,
+        delete: bool,
+    ) -> Result<ShareProgress<'_>> {
+        let inner = self.runtime.block_on(self.inner.sync(host, dir, delete))?;
+        Ok(ShareProgress {
+            inner,
+            runtime: &self.runtime,
+        })
+    }
+
+    pub fn register(
+        &self,
+        name: Hostname,
+        addr: HostAddr,
+        remote: bool,
+    ) -> Result<Option<HostAddr>> {
+        self.runtime
+            .block_on(self.inner.register(name, addr, remote))
+    }
+
+    pub fn status(&self) -> Result<Status> {
+        self.runtime.block_on(self.inner.status())
+    }
+
+    pub fn cancel(&self, transfer: u64) -> Result<()> {
+        self.runtime.block_on(self.inner.cancel(transfer))
+    }
+
+    pub fn hosts(&self) -> Result<Vec<HostInfo>> {
+        self.runtime.block_on(self.inner.hosts())
+    }
+
+    pub fn peers(&self) -> Result<Vec<Peer>> {
+        self.runtime.block_on(self.inner.peers())
+    }
+
+    pub fn register_peer(&self, name: Hostname) -> Result<Option<HostAddr>> {
+        self.runtime.block_on(self.inner.register_peer(name))
+    }
+
+    pub fn pair_offer(&self) -> Result<PairOffer<'_>> {
+        let inner = self.runtime.block_on(self.inner.pair_offer())?;
+        Ok(PairOffer {
+            inner,
+            runtime: &self.runtime,
+        })
+    }
+
+    pub fn pair(&self, code: impl Into<SmolStr>, addr: SocketAddr) -> Result<Paired> {
+        self.runtime.block_on(self.inner.pair(code, addr))
+    }
+
+    pub fn registration_requests(&self) -> Result<Vec<(SmolStr, SocketAddr)>> {
+        self.runtime.block_on(self.inner.registration_requests())
+    }
+
+    pub fn approve_registration(&self, name: Hostname) -> Result<Option<HostAddr>> {
+        self.runtime.block_on(self.inner.approve_registration(name))
+    }
+
+    pub fn reject_registration(&self, name: Hostname) -> Result<()> {
+        self.runtime.block_on(self.inner.reject_registration(name))
+    }
+
+    pub fn unregister(&self, name: Hostname) -> Result<()> {
+        self.runtime.block_on(self.inner.unregister(name))
+    }
+
+    pub fn rename(&self, name: Hostname, new_name: Hostname) -> Result<()> {
+        self.runtime.block_on(self.inner.rename(name, new_name))
+    }
+
+    pub fn set_addr(&self, name: Hostname, addrs: Vec<HostAddr>) -> Result<()> {
+        self.runtime.block_on(self.inner.set_addr(name, addrs))
+    }
+
+    pub fn events(&self) -> Result<Events<'_>> {
+        let inner = self.runtime.block_on(self.inner.events())?;
+        Ok(Events {
+            inner,
+            runtime: &self.runtime,
+        })
+    }
+}
+
+pub struct ShareProgress<'a> {
+    inner: crate::ShareProgress,
+    runtime: &'a Runtime,
+}
+
+impl ShareProgress<'_> {
+    pub fn transfer(&self) -> Option<u64> {
+        self.inner.transfer()
+    }
+}
+
+impl Iterator for ShareProgress<'_> {
+    type Item = Result<ShareUpdate>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.inner.next())
+    }
+}
+
+pub struct PairOffer<'a> {
+    inner: crate::PairOffer,
+    runtime: &'a Runtime,
+}
+
+impl PairOffer<'_> {
+    pub fn code(&self) -> &str {
+        self.inner.code()
+    }
+
+    pub fn wait(self) -> Result<Paired> {
+        self.runtime.block_on(self.inner.wait())
+    }
+}
+
+pub struct Events<'a> {
+    inner: crate::Events,
+    runtime: &'a Runtime,
+}
+
+impl Iterator for Events<'_> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.inner.next())
+    }
+}
@@ -0,0 +1,36 @@
+use fshare_proto::{
+    common::{LocalResponse, Response},
+    Event,
+};
+
+use crate::{parse_response, Connection, Error, Result};
+
+/// The events of a subscription, they end when the daemon shuts down.
+pub struct Events {
+    conn: Connection,
+}
+
+impl Events {
+    pub(crate) fn new(conn: Connection) -> Self {
+        Self { conn }
+    }
+
+    pub async fn next(&mut self) -> Option<Result<Event>> {
+        let line = match self.conn.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(match parse_response(line) {
+            Ok(Response::Local(LocalResponse::Event(event))) => Ok(event),
+            Ok(resp) => Err(Error::Daemon(resp)),
+            Err(e) => Err(e),
+        })
+    }
+
+    pub fn into_stream(self) -> impl futures::Stream<Item = Result<Event>> {
+        futures::stream::unfold(self, |mut events| async move {
+            events.next().await.map(|event| (event, events))
+        })
+    }
+}
@@ -0,0 +1,153 @@
+use std::net::SocketAddr;
+
+use fshare_proto::{
+    common::{LocalResponse, Response},
+    HostAddr, Hostname, LocalRequest,
+};
+use smol_str::SmolStr;
+
+use crate::{Client, Connection, Error, Result};
+
+/// A daemon discovered on the LAN.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub name: SmolStr,
+    pub addr: SocketAddr,
+    pub fingerprint: SmolStr,
+}
+
+/// The host this one was paired with, registered on both sides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paired {
+    pub name: SmolStr,
+    pub addr: SocketAddr,
+}
+
+/// A pairing code waiting to be typed on the other machine.
+pub struct PairOffer {
+    code: SmolStr,
+    conn: Connection,
+}
+
+impl PairOffer {
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Waits until the other side paired with the code or the offer expired.
+    pub async fn wait(mut self) -> Result<Paired> {
+        paired(self.conn.next_response().await?)
+    }
+}
+
+impl Client {
+    pub async fn peers(&self) -> Result<Vec<Peer>> {
+        let mut conn = self.request(&LocalRequest::Peers).await?;
+        let mut peers = Vec::new();
+        loop {
+            match conn.next_response().await? {
+                Response::Local(LocalResponse::Peer(name, addr, fingerprint)) => peers.push(Peer {
+                    name,
+                    addr,
+                    fingerprint,
+                }),
+                Response::Local(LocalResponse::ListEnd) => return Ok(peers),
+                resp => return Err(Error::Daemon(resp)),
+            }
+        }
+    }
+
+    /// Registers a discovered daemon under its announced name, returns the address it replaced.
+    pub async fn register_peer(&self, name: Hostname) -> Result<Option<HostAddr>> {
+        let mut conn = self.request(&LocalRequest::RegisterPeer(name)).await?;
+        registered(conn.next_response().await?)
+    }
+
+    pub async fn pair_offer(&self) -> Result<PairOffer> {
+        let mut conn = self.request(&LocalRequest::PairOffer).await?;
+        match conn.next_response().await? {
+            Response::Local(LocalResponse::PairCode(code)) => Ok(PairOffer { code, conn }),
+            resp => Err(Error::Daemon(resp)),
+        }
+    }
+
+    pub async fn pair(&self, code: impl Into<SmolStr>, addr: SocketAddr) -> Result<Paired> {
+        let request = LocalRequest::Pair {
+            code: code.into(),
+            addr,
+        };
+        let mut conn = self.request(&request).await?;
+        paired(conn.next_response().await?)
+    }
+
+    /// The hosts waiting for their registration to be approved.
+    pub async fn registration_requests(&self) -> Result<Vec<(SmolStr, SocketAddr)>> {
+        let mut conn = self.request(&LocalRequest::RegistrationRequests).await?;
+        let mut requests = Vec::new();
+        loop {
+            match conn.next_response().await? {
+                Response::Local(LocalResponse::RegRequest(name, addr)) => {
+                    requests.push((name, addr))
+                }
+                Response::Local(LocalResponse::ListEnd) => return Ok(requests),
+                resp => return Err(Error::Daemon(resp)),
+            }
+        }
+    }
+
+    /// Registers the requesting host on both sides, returns the address it replaced.
+    pub async fn approve_registration(&self, name: Hostname) -> Result<Option<HostAddr>> {
+        let mut conn = self
+            .request(&LocalRequest::ApproveRegistration(name))
+            .await?;
+        registered(conn.next_response().await?)
+    }
+
+    pub async fn reject_registration(&self, name: Hostname) -> Result<()> {
+        let mut conn = self
+            .request(&LocalRequest::RejectRegistration(name))
+            .await?;
+        match conn.next_response().await? {
+            Response::Local(LocalResponse::RegRequestRejected) => Ok(()),
+            resp => Err(Error::Daemon(resp)),
+        }
+    }
+
+    pub async fn unregister(&self, name: Hostname) -> Result<()> {
+        self.update_host(LocalRequest::Unregister(name)).await
+    }
+
+    /// Renames a registered host, its host groups follow.
+    pub async fn rename(&self, name: Hostname, new_name: Hostname) -> Result<()> {
+        self.update_host(LocalRequest::Rename(name, new_name)).await
+    }
+
+    /// Replaces the addresses of a registered host, they are tried in order.
+    pub async fn set_addr(&self, name: Hostname, addrs: Vec<HostAddr>) -> Result<()> {
+        self.update_host(LocalRequest::SetAddr(name, addrs)).await
+    }
+
+    async fn update_host(&self, request: LocalRequest) -> Result<()> {
+        let mut conn = self.request(&request).await?;
+        match conn.next_response().await? {
+            Response::Local(LocalResponse::HostUpdated) => Ok(()),
+            resp => Err(Error::Daemon(resp)),
+        }
+    }
+}
+
+/// The answer of a registration on this side, with the address it replaced.
+pub(crate) fn registered(resp: Response) -> Result<Option<HostAddr>> {
+    match resp {
+        Response::RegisterSucceeded => Ok(None),
+        Response::Local(LocalResponse::ReplacedAddress(addr)) => Ok(Some(addr)),
+        resp => Err(Error::Daemon(resp)),
+    }
+}
+
+fn paired(resp: Response) -> Result<Paired> {
+    match resp {
+        Response::Local(LocalResponse::Paired(name, addr)) => Ok(Paired { name, addr }),
+        resp => Err(Error::Daemon(resp)),
+    }
+}
@@ -0,0 +1,272 @@
+//! A client of the share daemon, speaking the line protocol over its IPC socket.
+//!
+//! Every request opens its own connection, so a [`Client`] is cheap to clone and share.
+
+use std::path::Path;
+
+use fshare_proto::{
+    common::{HostInfo, LocalResponse, Response},
+    consts, HostAddr, Hostname, LocalRequest, TransferInfo, ValueError,
+};
+use interprocess::local_socket::{
+    tokio::{prelude::*, Stream},
+    GenericNamespaced,
+};
+use smol_str::SmolStr;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+pub mod blocking;
+mod events;
+mod hosts;
+mod share;
+
+pub use events::Events;
+pub use hosts::{PairOffer, Paired, Peer};
+pub use share::{HostOutcome, ShareProgress, ShareUpdate};
+
+/// The errors of the client library.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The daemon is not running or its socket is not accessible.
+    #[error("Connect to the share daemon at `{name}` failed: {source}")]
+    Connect {
+        name: SmolStr,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The request was not sent, a value in it is not valid.
+    #[error(transparent)]
+    InvalidValue(#[from] ValueError),
+    /// The daemon refused the request or reported a failure, with the response it gave.
+    #[error("{}", .0.description())]
+    Daemon(Response),
+    /// The daemon answered with a line the protocol does not allow here.
+    #[error("Protocol violation: unexpected `{0}`")]
+    Protocol(SmolStr),
+    #[error("The daemon closed the connection before answering")]
+    Closed,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The IPC socket name in the default config file, so that local clients reach the daemon
+/// started with it. Falls back to the default name.
+pub fn configured_ipc_socket_name() -> SmolStr {
+    let Some(mut path) = dirs::home_dir() else {
+        return consts::DEFAULT_IPC_SOCK_NAME.into();
+    };
+    path.push(consts::DEFAULT_CONFIG_DIR_NAME);
+    path.push(consts::DEFAULT_CONFIG_FILE_NAME);
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| content.parse::<toml::Table>().ok())
+        .and_then(|table| {
+            table
+                .get("ipc_socket_name")
+                .and_then(|v| v.as_str())
+                .map(SmolStr::from)
+        })
+        .unwrap_or_else(|| consts::DEFAULT_IPC_SOCK_NAME.into())
+}
+
+/// The daemon's answers to a `STATUS` request.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    /// The `STATUS <key> <value>` pairs in the order the daemon sent them.
+    pub values: Vec<(SmolStr, SmolStr)>,
+    pub transfers: Vec<TransferInfo>,
+}
+
+impl Status {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    socket_name: SmolStr,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    /// A client of the daemon started with the default config file.
+    pub fn new() -> Self {
+        Self::with_socket_name(configured_ipc_socket_name())
+    }
+
+    pub fn with_socket_name(socket_name: impl Into<SmolStr>) -> Self {
+        Self {
+            socket_name: socket_name.into(),
+        }
+    }
+
+    pub fn socket_name(&self) -> &str {
+        &self.socket_name
+    }
+
+    /// Sends the files to the hosts, relative paths are resolved against the current directory
+    /// since the daemon has its own.
+    pub async fn share<H, P>(&self, hosts: H, paths: P) -> Result<ShareProgress>
+    where
+        H: IntoIterator,
+        H::Item: Into<SmolStr>,
+        P: IntoIterator,
+        P::Item: AsRef<Path>,
+    {
+        let hosts: Vec<SmolStr> = hosts.into_iter().map(Into::into).collect();
+        let paths = paths
+            .into_iter()
+            .map(std::path::absolute)
+            .collect::<std::io::Result<_>>()?;
+        let joined = hosts.join(&consts::HOSTS_SEP.to_string());
+        let conn = self.request(&LocalRequest::Share { hosts, paths }).await?;
+        Ok(ShareProgress::new(conn, joined.into()))
+    }
+
+    /// Mirrors the directory to a registered host, only new or changed files are sent. With
+    /// `delete` the files removed from the directory are removed on the host as well. The share
+    /// finishes with [`LocalResponse::Synced`].
+    pub async fn sync(
+        &self,
+        host: Hostname,
+        dir: impl AsRef<Path>,
+        delete: bool,
+    ) -> Result<ShareProgress> {
+        let dir = std::path::absolute(dir)?;
+        let hosts = SmolStr::from(host.as_str());
+        let conn = self
+            .request(&LocalRequest::Sync { host, dir, delete })
+            .await?;
+        Ok(ShareProgress::new(conn, hosts))
+    }
+
+    /// Registers a host and returns the address it replaced. A remote registration also asks the
+    /// host to register this one and waits until its user decided.
+    pub async fn register(
+        &self,
+        name: Hostname,
+        addr: HostAddr,
+        remote: bool,
+    ) -> Result<Option<HostAddr>> {
+        let mut conn = self
+            .request(&LocalRequest::Register { name, addr, remote })
+            .await?;
+        loop {
+            match conn.next_response().await? {
+                Response::Local(LocalResponse::RemoteRegPending) => continue,
+                resp => return hosts::registered(resp),
+            }
+        }
+    }
+
+    pub async fn status(&self) -> Result<Status> {
+        let mut conn = self.request(&LocalRequest::Status).await?;
+        let mut status = Status::default();
+        loop {
+            match conn.next_response().await? {
+                Response::Local(LocalResponse::Status(key, value)) => {
+                    status.values.push((key, value))
+                }
+                Response::Local(LocalResponse::Transfer(info)) => status.transfers.push(info),
+                Response::Local(LocalResponse::ListEnd) => return Ok(status),
+                resp => return Err(Error::Daemon(resp)),
+            }
+        }
+    }
+
+    /// Stops a transfer in progress, the id is the one of [`ShareUpdate::Queued`] or of
+    /// [`Status::transfers`].
+    pub async fn cancel(&self, transfer: u64) -> Result<()> {
+        let mut conn = self.request(&LocalRequest::Cancel(transfer)).await?;
+        match conn.next_response().await? {
+            Response::Local(LocalResponse::Cancelled) => Ok(()),
+            resp => Err(Error::Daemon(resp)),
+        }
+    }
+
+    pub async fn hosts(&self) -> Result<Vec<HostInfo>> {
+        let mut conn = self.request(&LocalRequest::Hosts).await?;
+        let mut hosts = Vec::new();
+        loop {
+            match conn.next_response().await? {
+                Response::Local(LocalResponse::RegHost(host)) => hosts.push(*host),
+                Response::Local(LocalResponse::ListEnd) => return Ok(hosts),
+                resp => return Err(Error::Daemon(resp)),
+            }
+        }
+    }
+
+    /// Subscribes to what happens in the daemon from now on.
+    pub async fn events(&self) -> Result<Events> {
+        let mut conn = self.request(&LocalRequest::Subscribe).await?;
+        match conn.next_response().await? {
+            Response::Local(LocalResponse::Subscribed) => Ok(Events::new(conn)),
+            resp => Err(Error::Daemon(resp)),
+        }
+    }
+
+    async fn request(&self, request: &LocalRequest) -> Result<Connection> {
+        request.check()?;
+        let mut conn = Connection::open(&self.socket_name).await?;
+        let stream = conn.reader.get_mut();
+        stream.write_all(request.encode().as_bytes()).await?;
+        stream.flush().await?;
+        Ok(conn)
+    }
+}
+
+/// A connection carrying one request, read line by line.
+struct Connection {
+    reader: BufReader<Stream>,
+    line: String,
+}
+
+impl Connection {
+    async fn open(socket_name: &SmolStr) -> Result<Self> {
+        let connect_err = |source| Error::Connect {
+            name: socket_name.clone(),
+            source,
+        };
+        let name = socket_name
+            .as_str()
+            .to_ns_name::<GenericNamespaced>()
+            .map_err(connect_err)?;
+        let stream = Stream::connect(name).await.map_err(connect_err)?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+            line: String::new(),
+        })
+    }
+
+    /// The next line without its separator, `None` once the daemon closed the connection.
+    async fn next_line(&mut self) -> Result<Option<&str>> {
+        self.line.clear();
+        if self.reader.read_line(&mut self.line).await? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.line.trim_end_matches(['\r', '\n'])))
+    }
+
+    async fn next_response(&mut self) -> Result<Response> {
+        match self.next_line().await? {
+            Some(line) => parse_response(line),
+            None => Err(Error::Closed),
+        }
+    }
+}
+
+fn parse_response(line: &str) -> Result<Response> {
+    line.parse().map_err(|_| Error::Protocol(line.into()))
+}
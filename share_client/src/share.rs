@@ -0,0 +1,235 @@
+use fshare_proto::{
+    common::{LocalResponse, Response},
+    request_tag::send_flag,
+};
+use smol_str::SmolStr;
+
+use crate::{parse_response, Connection, Error, Result};
+
+/// What happened to a share so far.
+#[derive(Debug, Clone)]
+pub enum ShareUpdate {
+    /// The daemon accepted the share, the id can be given to [`crate::Client::cancel`].
+    Queued {
+        transfer: u64,
+    },
+    /// A file is about to be sent, `size` is `None` if it is unknown.
+    FileStarted {
+        file: SmolStr,
+        size: Option<u64>,
+    },
+    /// How much of the current file is sent, `None` if its size is unknown.
+    Progress(Option<f64>),
+    FileSent {
+        host: SmolStr,
+        file: SmolStr,
+    },
+    /// A file was skipped, for all hosts if `host` is `None`.
+    FileFailed {
+        host: Option<SmolStr>,
+        file: SmolStr,
+    },
+    /// The share is over, with the result of every host.
+    Finished(Vec<HostOutcome>),
+    Cancelled,
+}
+
+/// How a share ended for one host.
+#[derive(Debug, Clone)]
+pub struct HostOutcome {
+    pub host: SmolStr,
+    pub response: Response,
+}
+
+impl HostOutcome {
+    pub fn succeeded(&self) -> bool {
+        matches!(
+            self.response,
+            Response::Local(LocalResponse::AllFilesSucceeded | LocalResponse::Synced(..))
+        )
+    }
+}
+
+/// The updates of a share in progress, ending with [`ShareUpdate::Finished`],
+/// [`ShareUpdate::Cancelled`] or an error.
+pub struct ShareProgress {
+    conn: Connection,
+    state: ShareState,
+    done: bool,
+}
+
+struct ShareState {
+    hosts: SmolStr,
+    transfer: Option<u64>,
+    outcomes: Vec<HostOutcome>,
+}
+
+impl ShareProgress {
+    pub(crate) fn new(conn: Connection, hosts: SmolStr) -> Self {
+        Self {
+            conn,
+            state: ShareState::new(hosts),
+            done: false,
+        }
+    }
+
+    /// The id of the transfer once the daemon queued it.
+    pub fn transfer(&self) -> Option<u64> {
+        self.state.transfer
+    }
+
+    pub async fn next(&mut self) -> Option<Result<ShareUpdate>> {
+        if self.done {
+            return None;
+        }
+        let update = self.read_update().await;
+        self.done = !matches!(
+            update,
+            Ok(ShareUpdate::Queued { .. }
+                | ShareUpdate::FileStarted { .. }
+                | ShareUpdate::Progress(_)
+                | ShareUpdate::FileSent { .. }
+                | ShareUpdate::FileFailed { .. })
+        );
+        Some(update)
+    }
+
+    pub fn into_stream(self) -> impl futures::Stream<Item = Result<ShareUpdate>> {
+        futures::stream::unfold(self, |mut progress| async move {
+            progress.next().await.map(|update| (update, progress))
+        })
+    }
+
+    async fn read_update(&mut self) -> Result<ShareUpdate> {
+        loop {
+            let resp = match self.conn.next_line().await? {
+                None => return Err(Error::Closed),
+                Some("" | send_flag::SEND_START | send_flag::SEND_END) => continue,
+                Some(line) => parse_response(line)?,
+            };
+            if let Some(update) = self.state.fold(resp)? {
+                return Ok(update);
+            }
+        }
+    }
+}
+
+impl ShareState {
+    fn new(hosts: SmolStr) -> Self {
+        Self {
+            hosts,
+            transfer: None,
+            outcomes: Vec::new(),
+        }
+    }
+
+    /// Folds a response line into the share, the results of single hosts are collected until
+    /// the list of a fan-out share ends.
+    fn fold(&mut self, resp: Response) -> Result<Option<ShareUpdate>> {
+        let update = match resp {
+            Response::Local(LocalResponse::TransferId(transfer)) => {
+                self.transfer = Some(transfer);
+                ShareUpdate::Queued { transfer }
+            }
+            Response::Local(LocalResponse::FileInfo(file, size)) => {
+                ShareUpdate::FileStarted { file, size }
+            }
+            Response::Local(LocalResponse::Progress(p)) => {
+                ShareUpdate::Progress(Some(p).filter(|p| *p >= 0.0))
+            }
+            Response::Local(LocalResponse::FileFailed(file)) => {
+                ShareUpdate::FileFailed { host: None, file }
+            }
+            Response::Local(LocalResponse::Host(host, inner)) => match *inner {
+                LocalResponse::FileSent(file) => ShareUpdate::FileSent { host, file },
+                LocalResponse::FileFailed(file) => ShareUpdate::FileFailed {
+                    host: Some(host),
+                    file,
+                },
+                resp => {
+                    self.outcomes.push(HostOutcome {
+                        host,
+                        response: Response::Local(resp),
+                    });
+                    return Ok(None);
+                }
+            },
+            Response::Local(LocalResponse::ListEnd) => {
+                ShareUpdate::Finished(std::mem::take(&mut self.outcomes))
+            }
+            Response::Local(LocalResponse::Cancelled) => ShareUpdate::Cancelled,
+            response if self.transfer.is_some() => ShareUpdate::Finished(vec![HostOutcome {
+                host: self.hosts.clone(),
+                response,
+            }]),
+            resp => return Err(Error::Daemon(resp)),
+        };
+        Ok(Some(update))
+    }
+}
+
+#[cfg(test)]
+mod share_tests {
+    use fshare_proto::common::{LocalResponse, Response};
+
+    use super::{ShareState, ShareUpdate};
+
+    /// Feeds the lines to the state of a share and collects the updates.
+    fn updates(hosts: &str, lines: &[&str]) -> Vec<Result<ShareUpdate, ()>> {
+        let mut state = ShareState::new(hosts.into());
+        lines
+            .iter()
+            .filter_map(|line| {
+                let resp = line.parse::<Response>().unwrap();
+                state.fold(resp).map_err(|_| ()).transpose()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fold_test() {
+        let single = updates(
+            "pc",
+            &[
+                "TRANSFER_ID 3",
+                "FILE_INFO a b.txt:10",
+                "PROGRESS 0.5",
+                "ALL_FILES_SUCCEEDED",
+            ],
+        );
+        assert!(matches!(single[0], Ok(ShareUpdate::Queued { transfer: 3 })));
+        assert!(matches!(
+            &single[1],
+            Ok(ShareUpdate::FileStarted { file, size: Some(10) }) if file == "a b.txt"
+        ));
+        assert!(matches!(single[2], Ok(ShareUpdate::Progress(Some(p))) if p == 0.5));
+        let Ok(ShareUpdate::Finished(outcomes)) = &single[3] else {
+            panic!("share not finished");
+        };
+        assert_eq!(outcomes[0].host, "pc");
+        assert!(outcomes[0].succeeded());
+
+        let fan_out = updates(
+            "pc,nas",
+            &[
+                "TRANSFER_ID 4",
+                "HOST nas UNREACHABLE 10.0.0.2:10020",
+                "HOST pc FILE_SENT a.txt",
+                "HOST pc ALL_FILES_SUCCEEDED",
+                "LIST_END",
+            ],
+        );
+        assert!(matches!(&fan_out[1], Ok(ShareUpdate::FileSent { host, .. }) if host == "pc"));
+        let Ok(ShareUpdate::Finished(outcomes)) = &fan_out[2] else {
+            panic!("share not finished");
+        };
+        assert_eq!(outcomes.len(), 2);
+        assert!(matches!(
+            outcomes[0].response,
+            Response::Local(LocalResponse::UnreachableAddress(_))
+        ));
+        assert!(outcomes[1].succeeded());
+
+        assert!(matches!(updates("pc", &["UNREG_HOSTNAME"])[..], [Err(())]));
+    }
+}
@@ -1,277 +1,1025 @@
-use std::{
-    collections::HashMap,
-    fs::File,
-    io::{Read, Write},
-    net::{IpAddr, SocketAddr},
-    ops::{Deref, DerefMut},
-    path::{Path, PathBuf},
-    sync::OnceLock,
-    time::SystemTime,
-};
-
-use smol_str::SmolStr;
-
-use crate::consts;
-pub(crate) const GET_HOME_DIR_FAILED: &str =
-    "Unexpected: get home dir failed! Maybe you are in an unsupported platform!";
-
-pub(crate) const MAX_WORKERS: u8 = 120;
-
-#[derive(Debug)]
-pub(crate) struct ConfigStore {
-    current_config: Config,
-    config_path: PathBuf,
-    last_modified: LastModified,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub(crate) enum LastModified {
-    LastModTime(SystemTime),
-    Unsupported,
-    Unsaved,
-}
-
-impl ConfigStore {
-    pub(crate) fn default() -> Self {
-        Self {
-            current_config: Config::default(),
-            config_path: Config::default_config_path(),
-            last_modified: LastModified::Unsaved,
-        }
-    }
-
-    pub(crate) fn set_config(&mut self, config: Config) {
-        self.current_config = config;
-    }
-
-    pub(crate) fn update_to_file(&mut self) -> anyhow::Result<()> {
-        self.last_modified = self.write_to_file(&self.config_path)?;
-        Ok(())
-    }
-
-    pub(crate) fn try_update_from_file(&mut self) -> anyhow::Result<()> {
-        let f = Config::open_config_file_readonly(&self.config_path)?;
-        match self.last_modified {
-            LastModified::LastModTime(last_mod_time) => {
-                if let Ok(mod_time) = f.metadata()?.modified() {
-                    if mod_time == last_mod_time {
-                        return Ok(());
-                    }
-                }
-            }
-            LastModified::Unsaved => {
-                self.last_modified = self.current_config.write_to_file(&self.config_path)?;
-                return Ok(());
-            }
-            _ => (),
-        }
-        self.last_modified = self.current_config.update_from(&self.config_path)?;
-        Ok(())
-    }
-}
-
-impl Deref for ConfigStore {
-    type Target = Config;
-
-    fn deref(&self) -> &Self::Target {
-        &self.current_config
-    }
-}
-
-impl DerefMut for ConfigStore {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.current_config
-    }
-}
-
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
-pub struct Config {
-    listener_addr: SocketAddr,
-    num_workers: u8,
-    save_dir: PathBuf,
-    ipc_socket_name: SmolStr,
-    reg_hosts: HashMap<SmolStr, SocketAddr>,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            listener_addr: consts::DEFAULT_LISTENER_ADDR,
-            num_workers: consts::DEFAULT_NUM_WORKERS,
-            save_dir: Self::default_save_dir().to_owned(),
-            ipc_socket_name: consts::DEFAULT_IPC_SOCK_NAME.into(),
-            reg_hosts: HashMap::new(),
-        }
-    }
-}
-
-impl Config {
-    // immutable self
-
-    pub(crate) fn write_to_file(&self, p: &Path) -> anyhow::Result<LastModified> {
-        let mut f = File::create(p)?;
-        f.write_all(toml::to_string(&self)?.as_bytes())?;
-        f.flush()?;
-        if let Ok(last_modified) = f.metadata()?.modified() {
-            return Ok(LastModified::LastModTime(last_modified));
-        }
-        Ok(LastModified::Unsupported)
-    }
-
-    pub(crate) fn ipc_socket_name(&self) -> &str {
-        &self.ipc_socket_name
-    }
-
-    pub(crate) fn check_addr_registered(&self, addr: SocketAddr) -> bool {
-        self.reg_hosts.values().any(|reg_ip| *reg_ip == addr)
-    }
-
-    pub(crate) fn get_addr_by_name(&self, hostname: &str) -> Option<&SocketAddr> {
-        self.reg_hosts.get(hostname)
-    }
-
-    pub(crate) fn listener_addr(&self) -> SocketAddr {
-        self.listener_addr
-    }
-
-    pub(crate) fn receive_dir(&self) -> &Path {
-        &self.save_dir
-    }
-
-    pub(crate) fn num_workers(&self) -> u8 {
-        self.num_workers
-    }
-
-    // mutable self
-
-    pub(crate) fn update_from<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<LastModified> {
-        let p = path.as_ref();
-        let (c, t) = Config::from_file(p)?;
-        let (mut config_ok, mut config) = c.checked();
-        config_ok = config_ok && config.listener_addr == self.listener_addr;
-        Ok(if !config_ok {
-            config.listener_addr = self.listener_addr;
-            *self = config;
-            self.write_to_file(p)?
-        } else {
-            t
-        })
-    }
-
-    pub(crate) fn register_host(
-        &mut self,
-        hostname: &str,
-        socket_addr: SocketAddr,
-    ) -> Option<SocketAddr> {
-        self.reg_hosts.insert(hostname.into(), socket_addr)
-    }
-
-    pub(crate) fn set_save_dir<P: Into<PathBuf>>(&mut self, files_save_dir: P) {
-        self.save_dir = Self::check_files_save_dir(files_save_dir.into()).1;
-    }
-
-    pub(crate) fn set_num_workers(&mut self, n: u8) {
-        self.num_workers = Self::check_num_workers(n).1;
-    }
-
-    pub(crate) fn set_listener_addr(&mut self, addr: SocketAddr) {
-        self.listener_addr = addr;
-    }
-
-    pub(crate) fn set_listener_port(&mut self, port: u16) {
-        self.listener_addr.set_port(port);
-    }
-
-    pub(crate) fn set_ipc_socket_name(&mut self, name: SmolStr) {
-        self.ipc_socket_name = name;
-    }
-
-    pub(crate) fn set_listener_ip(&mut self, ip: IpAddr) {
-        self.listener_addr.set_ip(ip)
-    }
-
-    // static
-
-    pub(crate) fn default_config_path() -> PathBuf {
-        let mut path = dirs::home_dir().expect(GET_HOME_DIR_FAILED);
-        path.push(consts::DEFAULT_CONFIG_DIR_NAME);
-        if !path.exists() {
-            std::fs::create_dir_all(&path).unwrap();
-        }
-        path.push(consts::DEFAULT_CONFIG_FILE_NAME);
-        path
-    }
-
-    pub(crate) fn open_config_file_readonly<P: AsRef<Path>>(
-        config_path: P,
-    ) -> std::io::Result<File> {
-        File::open(config_path)
-    }
-
-    pub(crate) fn from_file<P: AsRef<Path>>(p: P) -> anyhow::Result<(Self, LastModified)> {
-        let mut f = File::open(p.as_ref())?;
-        let mut content = String::new();
-        if f.read_to_string(&mut content)? > 0 {
-            let (ok, config) = toml::from_str::<Config>(content.trim())?.checked();
-            let modified = if !ok {
-                config.write_to_file(p.as_ref())?
-            } else if let Ok(last_modified) = f.metadata()?.modified() {
-                LastModified::LastModTime(last_modified)
-            } else {
-                LastModified::Unsupported
-            };
-
-            Ok((config, modified))
-        } else {
-            Err(anyhow::anyhow!("Config file is empty!"))
-        }
-    }
-
-    fn check_num_workers(num: u8) -> (bool, u8) {
-        if num == 0 || num > MAX_WORKERS {
-            (false, consts::DEFAULT_NUM_WORKERS)
-        } else {
-            (true, num)
-        }
-    }
-
-    fn default_save_dir() -> &'static Path {
-        static RECV_DIR: OnceLock<PathBuf> = OnceLock::new();
-        let d = RECV_DIR.get_or_init(|| dirs::download_dir().expect(GET_HOME_DIR_FAILED));
-        if !d.exists() {
-            std::fs::create_dir_all(d)
-                .expect("Unexpected: create default receive(Download) directory failed!");
-        }
-        d
-    }
-
-    fn check_files_save_dir(path: PathBuf) -> (bool, PathBuf) {
-        if !path.is_dir() {
-            log::warn!("Invalid files save directory! using default instead.");
-            return (false, Self::default_save_dir().to_owned());
-        }
-        (true, path)
-    }
-
-    #[inline(always)]
-    pub(crate) fn check_hostname_valid(hostname: &str) -> bool {
-        let len = hostname.len();
-        len > 0 && len <= consts::HOST_NAME_LENGTH_LIMIT
-    }
-
-    fn check_addr_valid(addr: SocketAddr) -> bool {
-        std::net::TcpStream::connect(addr).is_ok()
-    }
-
-    pub(crate) fn checked(mut self) -> (bool, Self) {
-        let (num_workers_ok, num_workers) = Self::check_num_workers(self.num_workers);
-        let (recv_dir_ok, recv_dir) = Self::check_files_save_dir(self.save_dir);
-        let hosts_count = self.reg_hosts.len();
-        self.reg_hosts
-            .retain(|name, addr| Self::check_hostname_valid(name) && Self::check_addr_valid(*addr));
-        let checked_ok = num_workers_ok && recv_dir_ok && self.reg_hosts.len() == hosts_count;
-        self.num_workers = num_workers;
-        self.save_dir = recv_dir;
-        (checked_ok, self)
-    }
-}
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    ops::{Deref, DerefMut},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
+
+use smol_str::{SmolStr, ToSmolStr};
+
+pub use fshare_proto::HostAddr;
+
+use crate::{
+    consts,
+    error::{self, Error},
+    resolve,
+};
+pub(crate) const GET_HOME_DIR_FAILED: &str =
+    "Unexpected: get home dir failed! Maybe you are in an unsupported platform!";
+
+pub(crate) const MAX_WORKERS: u8 = 120;
+
+#[derive(Debug)]
+pub(crate) struct ConfigStore {
+    current_config: Config,
+    config_path: PathBuf,
+    last_modified: LastModified,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LastModified {
+    LastModTime(SystemTime),
+    Unsupported,
+    Unsaved,
+}
+
+impl ConfigStore {
+    pub(crate) fn default() -> Self {
+        Self {
+            current_config: Config::default(),
+            config_path: Config::default_config_path(),
+            last_modified: LastModified::Unsaved,
+        }
+    }
+
+    /// A store with a minimal config saved in `dir`, which is its save directory as well.
+    #[cfg(test)]
+    pub(crate) fn in_dir(dir: PathBuf) -> Self {
+        let config = toml::from_str::<Config>(&format!(
+            r#"
+listener_addr = "127.0.0.1:0"
+num_workers = 1
+save_dir = {:?}
+ipc_socket_name = "test.sock"
+
+[reg_hosts]
+"#,
+            dir
+        ))
+        .unwrap();
+        let mut store = Self {
+            current_config: config,
+            config_path: dir.join(consts::DEFAULT_CONFIG_FILE_NAME),
+            last_modified: LastModified::Unsaved,
+        };
+        store.update_to_file().unwrap();
+        store
+    }
+
+    pub(crate) fn set_config(&mut self, config: Config) {
+        self.current_config = config;
+    }
+
+    pub(crate) fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    pub(crate) fn set_config_path(&mut self, path: PathBuf) {
+        self.config_path = path;
+    }
+
+    pub(crate) fn update_to_file(&mut self) -> error::Result<()> {
+        self.last_modified = self.write_to_file(&self.config_path)?;
+        Ok(())
+    }
+
+    /// Reloads the config if the file changed since it was read or written, returns whether it
+    /// was reloaded.
+    pub(crate) fn try_update_from_file(&mut self) -> error::Result<bool> {
+        let f = Config::open_config_file_readonly(&self.config_path)
+            .map_err(Error::config_io(&self.config_path))?;
+        match self.last_modified {
+            LastModified::LastModTime(last_mod_time) => {
+                let metadata = f.metadata().map_err(Error::config_io(&self.config_path))?;
+                if let Ok(mod_time) = metadata.modified() {
+                    if mod_time == last_mod_time {
+                        return Ok(false);
+                    }
+                }
+            }
+            LastModified::Unsaved => {
+                self.last_modified = self.current_config.write_to_file(&self.config_path)?;
+                return Ok(false);
+            }
+            _ => (),
+        }
+        self.last_modified = self.current_config.update_from(&self.config_path)?;
+        Ok(true)
+    }
+}
+
+impl Deref for ConfigStore {
+    type Target = Config;
+
+    fn deref(&self) -> &Self::Target {
+        &self.current_config
+    }
+}
+
+impl DerefMut for ConfigStore {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.current_config
+    }
+}
+
+/// Placeholders accepted by receive path templates.
+pub mod template_key {
+    pub const SAVE_DIR: &str = "{save_dir}";
+    pub const HOST: &str = "{host}";
+    pub const DATE: &str = "{date}";
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    listener_addr: SocketAddr,
+    num_workers: u8,
+    save_dir: PathBuf,
+    ipc_socket_name: SmolStr,
+    #[serde(default = "Config::default_receive_path")]
+    receive_path: SmolStr,
+    /// The subdirectory of `save_dir` synced directories are mirrored to.
+    #[serde(default = "Config::default_mirror_dir")]
+    mirror_dir: PathBuf,
+    /// A directory of files named by their content hash, relative to `save_dir`. A file found
+    /// there is not sent again, received files are added to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_store: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_hosts")]
+    reg_hosts: HashMap<SmolStr, HostRecord>,
+    /// Only read from old config files, moved into the host records.
+    #[serde(default, skip_serializing)]
+    host_receive_paths: HashMap<SmolStr, SmolStr>,
+    /// Only read from old config files, moved into the host records.
+    #[serde(default, skip_serializing)]
+    host_keys: HashMap<SmolStr, SmolStr>,
+    /// Named lists of registered hosts which can be shared to at once.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    host_groups: HashMap<SmolStr, Vec<SmolStr>>,
+    #[serde(default)]
+    hooks: HooksConfig,
+    #[serde(default = "Config::default_host_name")]
+    host_name: SmolStr,
+    #[serde(default)]
+    discovery: DiscoveryConfig,
+    /// Outbox folders whose files are shared automatically, read when the daemon starts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    watch_folders: Vec<WatchFolder>,
+}
+
+/// A registered host.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostRecord {
+    /// The addresses the host is reached at, tried in order.
+    pub addrs: Vec<HostAddr>,
+    /// The hex encoded public key the host paired with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<SmolStr>,
+    #[serde(default, skip_serializing_if = "SmolStr::is_empty")]
+    pub description: SmolStr,
+    #[serde(default)]
+    pub policy: HostPolicy,
+    /// Unix time of the last connection with the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    /// Unix time of the last batch sent to or received from the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_transfer: Option<u64>,
+}
+
+impl HostRecord {
+    pub fn new(addr: HostAddr) -> Self {
+        Self {
+            addrs: vec![addr],
+            public_key: None,
+            description: SmolStr::default(),
+            policy: HostPolicy::default(),
+            last_seen: None,
+            last_transfer: None,
+        }
+    }
+
+    pub fn has_ip(&self, ip: IpAddr) -> bool {
+        self.addrs.iter().any(|addr| resolve::matches_ip(addr, ip))
+    }
+}
+
+/// What a registered host is allowed to do.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostPolicy {
+    /// Batches from a host which is not auto accepted are refused.
+    #[serde(default = "HostPolicy::default_auto_accept")]
+    pub auto_accept: bool,
+    /// Overrides the receive path template for the host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receive_path: Option<SmolStr>,
+    /// Limits the rate data from the host is received at, in bytes per second.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth_limit: Option<u64>,
+    /// The number of bytes the host may send per day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_quota: Option<u64>,
+}
+
+impl Default for HostPolicy {
+    fn default() -> Self {
+        Self {
+            auto_accept: Self::default_auto_accept(),
+            receive_path: None,
+            bandwidth_limit: None,
+            daily_quota: None,
+        }
+    }
+}
+
+impl HostPolicy {
+    fn default_auto_accept() -> bool {
+        true
+    }
+}
+
+/// Reads `reg_hosts`, where old config files map a name to a bare address.
+fn deserialize_hosts<'de, D>(deserializer: D) -> Result<HashMap<SmolStr, HostRecord>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum MaybeLegacy {
+        Addr(HostAddr),
+        Record(HostRecord),
+    }
+    let hosts = <HashMap<SmolStr, MaybeLegacy> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(hosts
+        .into_iter()
+        .map(|(name, host)| match host {
+            MaybeLegacy::Addr(addr) => (name, HostRecord::new(addr)),
+            MaybeLegacy::Record(record) => (name, record),
+        })
+        .collect())
+}
+
+/// LAN peer discovery through periodic multicast (or broadcast) announcements.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// The multicast group (or broadcast address) announcements are sent to and received from.
+    #[serde(default = "DiscoveryConfig::default_group")]
+    pub group: SocketAddr,
+    /// The local interface used for the multicast group, unspecified lets the OS choose.
+    #[serde(default = "DiscoveryConfig::default_interface")]
+    pub interface: IpAddr,
+    #[serde(default = "DiscoveryConfig::default_interval_secs")]
+    pub interval_secs: u64,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            group: Self::default_group(),
+            interface: Self::default_interface(),
+            interval_secs: Self::default_interval_secs(),
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    fn default_group() -> SocketAddr {
+        consts::DEFAULT_DISCOVERY_GROUP
+    }
+
+    fn default_interface() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    }
+
+    fn default_interval_secs() -> u64 {
+        5
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+}
+
+/// A folder whose files are shared to `host` once they stopped changing.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WatchFolder {
+    pub path: PathBuf,
+    /// A registered host, or a `target@relay` pair.
+    pub host: SmolStr,
+    #[serde(default)]
+    pub after_sent: AfterSent,
+    /// How long a file has to stay unchanged before it is shared.
+    #[serde(default = "WatchFolder::default_settle_secs")]
+    pub settle_secs: u64,
+}
+
+impl WatchFolder {
+    pub fn new(path: impl Into<PathBuf>, host: impl Into<SmolStr>) -> Self {
+        Self {
+            path: path.into(),
+            host: host.into(),
+            after_sent: AfterSent::default(),
+            settle_secs: Self::default_settle_secs(),
+        }
+    }
+
+    fn default_settle_secs() -> u64 {
+        2
+    }
+
+    pub(crate) fn settle(&self) -> Duration {
+        Duration::from_secs(self.settle_secs)
+    }
+}
+
+/// What happens to a file of a watch folder once it was sent.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AfterSent {
+    /// Moved to the `sent` subfolder.
+    #[default]
+    Move,
+    Delete,
+}
+
+/// Shell commands run after files are received.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HooksConfig {
+    /// Run after each received file.
+    #[serde(default)]
+    pub on_file: Vec<SmolStr>,
+    /// Run after each completed batch, once the `on_file` hooks of the batch finished.
+    #[serde(default)]
+    pub on_batch: Vec<SmolStr>,
+    #[serde(default = "HooksConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "HooksConfig::default_max_concurrent")]
+    pub max_concurrent: u8,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_file: vec![],
+            on_batch: vec![],
+            timeout_secs: Self::default_timeout_secs(),
+            max_concurrent: Self::default_max_concurrent(),
+        }
+    }
+}
+
+impl HooksConfig {
+    fn default_timeout_secs() -> u64 {
+        60
+    }
+
+    fn default_max_concurrent() -> u8 {
+        2
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.on_file.is_empty() && self.on_batch.is_empty()
+    }
+
+    pub(crate) fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listener_addr: consts::DEFAULT_LISTENER_ADDR,
+            num_workers: consts::DEFAULT_NUM_WORKERS,
+            save_dir: Self::default_save_dir().to_owned(),
+            ipc_socket_name: consts::DEFAULT_IPC_SOCK_NAME.into(),
+            receive_path: Self::default_receive_path(),
+            mirror_dir: Self::default_mirror_dir(),
+            content_store: None,
+            reg_hosts: HashMap::new(),
+            host_receive_paths: HashMap::new(),
+            host_keys: HashMap::new(),
+            host_groups: HashMap::new(),
+            hooks: HooksConfig::default(),
+            host_name: Self::default_host_name(),
+            discovery: DiscoveryConfig::default(),
+            watch_folders: vec![],
+        }
+    }
+}
+
+impl Config {
+    // immutable self
+
+    pub(crate) fn write_to_file(&self, p: &Path) -> error::Result<LastModified> {
+        let content = toml::to_string(&self)?;
+        let write = || {
+            let mut f = File::create(p)?;
+            f.write_all(content.as_bytes())?;
+            f.flush()?;
+            f.metadata()
+        };
+        if let Ok(last_modified) = write().map_err(Error::config_io(p))?.modified() {
+            return Ok(LastModified::LastModTime(last_modified));
+        }
+        Ok(LastModified::Unsupported)
+    }
+
+    pub(crate) fn ipc_socket_name(&self) -> &str {
+        &self.ipc_socket_name
+    }
+
+    pub(crate) fn get_addrs_by_name(&self, hostname: &str) -> Option<&[HostAddr]> {
+        self.get_host(hostname).map(|host| host.addrs.as_slice())
+    }
+
+    /// The DNS names of all registered hosts, to be resolved before matching peers.
+    pub(crate) fn dns_addrs(&self) -> Vec<HostAddr> {
+        self.reg_hosts
+            .values()
+            .flat_map(|host| &host.addrs)
+            .filter(|addr| matches!(addr, HostAddr::Dns(..)))
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn get_name_by_ip(&self, ip: IpAddr) -> Option<&str> {
+        self.get_host_by_ip(ip).map(|(name, _)| name)
+    }
+
+    pub(crate) fn get_host(&self, hostname: &str) -> Option<&HostRecord> {
+        self.reg_hosts.get(hostname)
+    }
+
+    /// All registered hosts, sorted by name.
+    pub(crate) fn reg_hosts(&self) -> Vec<(&str, &HostRecord)> {
+        let mut hosts: Vec<_> = self
+            .reg_hosts
+            .iter()
+            .map(|(name, host)| (name.as_str(), host))
+            .collect();
+        hosts.sort_unstable_by_key(|(name, _)| *name);
+        hosts
+    }
+
+    pub(crate) fn get_host_by_ip(&self, ip: IpAddr) -> Option<(&str, &HostRecord)> {
+        self.reg_hosts
+            .iter()
+            .find(|(_, host)| host.has_ip(ip))
+            .map(|(name, host)| (name.as_str(), host))
+    }
+
+    pub(crate) fn listener_addr(&self) -> SocketAddr {
+        self.listener_addr
+    }
+
+    pub(crate) fn receive_dir(&self) -> &Path {
+        &self.save_dir
+    }
+
+    /// Renders the receive path template for files sent by `send_host_ip`.
+    ///
+    /// The per-host template (if any) takes precedence over the global one, a relative result is
+    /// resolved against `save_dir`. Hosts that can not be resolved to a registered name are
+    /// rendered by their IP address.
+    pub(crate) fn receive_dir_for(&self, send_host_ip: IpAddr) -> PathBuf {
+        let registered = self.get_host_by_ip(send_host_ip);
+        let template = registered
+            .and_then(|(_, host)| host.policy.receive_path.as_ref())
+            .unwrap_or(&self.receive_path);
+        let host = registered
+            .map(|(name, _)| SmolStr::from(name))
+            .unwrap_or_else(|| send_host_ip.to_smolstr());
+        self.receive_dir().join(render_receive_path(
+            template,
+            &self.save_dir,
+            &host,
+            SystemTime::now(),
+        ))
+    }
+
+    /// Where the directories synced by `sender` are mirrored, one subdirectory per directory.
+    pub(crate) fn mirror_root(&self, sender: &str) -> PathBuf {
+        self.save_dir
+            .join(&self.mirror_dir)
+            .join(fshare_proto::FileName::lossy(sender).as_str())
+    }
+
+    pub(crate) fn content_store(&self) -> Option<PathBuf> {
+        self.content_store
+            .as_ref()
+            .map(|dir| self.save_dir.join(dir))
+    }
+
+    pub(crate) fn hooks(&self) -> &HooksConfig {
+        &self.hooks
+    }
+
+    /// The name this daemon announces itself with.
+    pub(crate) fn host_name(&self) -> &str {
+        &self.host_name
+    }
+
+    pub(crate) fn host_group(&self, group: &str) -> Option<&[SmolStr]> {
+        self.host_groups.get(group).map(Vec::as_slice)
+    }
+
+    pub(crate) fn discovery(&self) -> &DiscoveryConfig {
+        &self.discovery
+    }
+
+    pub(crate) fn watch_folders(&self) -> &[WatchFolder] {
+        &self.watch_folders
+    }
+
+    pub(crate) fn num_workers(&self) -> u8 {
+        self.num_workers
+    }
+
+    // mutable self
+
+    pub(crate) fn update_from<P: AsRef<Path>>(&mut self, path: P) -> error::Result<LastModified> {
+        let p = path.as_ref();
+        let (c, t) = Config::from_file(p)?;
+        let (mut config_ok, mut config) = c.checked();
+        config_ok = config_ok && config.listener_addr == self.listener_addr;
+        config.listener_addr = self.listener_addr;
+        *self = config;
+        Ok(if !config_ok {
+            self.write_to_file(p)?
+        } else {
+            t
+        })
+    }
+
+    /// Registers `hostname` at `socket_addr`, an already registered host gets it as its first
+    /// address. Returns the replaced first address.
+    pub(crate) fn register_host(&mut self, hostname: &str, addr: HostAddr) -> Option<HostAddr> {
+        let Some(host) = self.reg_hosts.get_mut(hostname) else {
+            self.reg_hosts
+                .insert(hostname.into(), HostRecord::new(addr));
+            return None;
+        };
+        let replaced = host.addrs.first().filter(|a| **a != addr).cloned();
+        host.addrs.retain(|a| *a != addr);
+        host.addrs.insert(0, addr);
+        replaced
+    }
+
+    pub(crate) fn set_host_record(&mut self, hostname: &str, record: HostRecord) {
+        self.reg_hosts.insert(hostname.into(), record);
+    }
+
+    /// Updates when the host at `ip` was last seen, and last transferred with if `transferred`.
+    /// Returns whether a registered host was found.
+    pub(crate) fn touch_host(&mut self, ip: IpAddr, transferred: bool) -> bool {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .ok();
+        let Some(host) = self.reg_hosts.values_mut().find(|host| host.has_ip(ip)) else {
+            return false;
+        };
+        host.last_seen = now;
+        if transferred {
+            host.last_transfer = now;
+        }
+        true
+    }
+
+    pub(crate) fn set_receive_path(&mut self, template: SmolStr) {
+        self.receive_path = template;
+    }
+
+    pub(crate) fn set_mirror_dir(&mut self, dir: PathBuf) {
+        self.mirror_dir = Self::check_mirror_dir(dir).1;
+    }
+
+    pub(crate) fn set_content_store(&mut self, dir: Option<PathBuf>) {
+        self.content_store = dir;
+    }
+
+    pub(crate) fn set_host_receive_path(&mut self, hostname: &str, template: Option<SmolStr>) {
+        if let Some(host) = self.reg_hosts.get_mut(hostname) {
+            host.policy.receive_path = template;
+        }
+    }
+
+    /// Sets (or removes) the hex encoded public key a registered host paired with.
+    pub(crate) fn set_host_key(&mut self, hostname: &str, key: Option<SmolStr>) {
+        if let Some(host) = self.reg_hosts.get_mut(hostname) {
+            host.public_key = key;
+        }
+    }
+
+    /// Sets (or removes) a host group, members which are not registered are dropped.
+    pub(crate) fn set_host_group(&mut self, group: &str, members: Option<Vec<SmolStr>>) {
+        match members {
+            Some(mut m) if Self::check_hostname_valid(group) => {
+                let mut seen = HashSet::new();
+                m.retain(|name| self.reg_hosts.contains_key(name) && seen.insert(name.clone()));
+                self.host_groups.insert(group.into(), m);
+            }
+            Some(_) => log::warn!("Invalid host group name `{}`.", group),
+            None => {
+                self.host_groups.remove(group);
+            }
+        }
+    }
+
+    /// Removes a registered host, also from the host groups.
+    pub(crate) fn unregister_host(&mut self, hostname: &str) -> Option<HostRecord> {
+        let removed = self.reg_hosts.remove(hostname)?;
+        for members in self.host_groups.values_mut() {
+            members.retain(|name| name != hostname);
+        }
+        Some(removed)
+    }
+
+    /// Renames a registered host, the host groups follow. Nothing happens if `hostname` is not
+    /// registered or `new_name` already is.
+    pub(crate) fn rename_host(&mut self, hostname: &str, new_name: &str) -> bool {
+        if !Self::check_hostname_valid(new_name) || self.reg_hosts.contains_key(new_name) {
+            return false;
+        }
+        let Some(host) = self.reg_hosts.remove(hostname) else {
+            return false;
+        };
+        self.reg_hosts.insert(new_name.into(), host);
+        for name in self.host_groups.values_mut().flatten() {
+            if name == hostname {
+                *name = new_name.into();
+            }
+        }
+        true
+    }
+
+    /// Replaces the addresses of a registered host, returns whether it is registered.
+    pub(crate) fn set_host_addrs(&mut self, hostname: &str, addrs: Vec<HostAddr>) -> bool {
+        match self.reg_hosts.get_mut(hostname) {
+            Some(host) if !addrs.is_empty() => {
+                host.addrs = addrs;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn set_hooks(&mut self, hooks: HooksConfig) {
+        self.hooks = hooks;
+    }
+
+    pub(crate) fn set_host_name(&mut self, name: SmolStr) {
+        if Self::check_hostname_valid(&name) {
+            self.host_name = name;
+        } else {
+            log::warn!(
+                "Invalid host name `{}`, keeping `{}`.",
+                name,
+                self.host_name
+            );
+        }
+    }
+
+    pub(crate) fn set_discovery(&mut self, discovery: DiscoveryConfig) {
+        self.discovery = discovery;
+    }
+
+    pub(crate) fn set_watch_folders(&mut self, folders: Vec<WatchFolder>) {
+        self.watch_folders = folders;
+    }
+
+    pub(crate) fn set_save_dir<P: Into<PathBuf>>(&mut self, files_save_dir: P) {
+        self.save_dir = Self::check_files_save_dir(files_save_dir.into()).1;
+    }
+
+    pub(crate) fn set_num_workers(&mut self, n: u8) {
+        self.num_workers = Self::check_num_workers(n).1;
+    }
+
+    pub(crate) fn set_listener_addr(&mut self, addr: SocketAddr) {
+        self.listener_addr = addr;
+    }
+
+    pub(crate) fn set_listener_port(&mut self, port: u16) {
+        self.listener_addr.set_port(port);
+    }
+
+    pub(crate) fn set_ipc_socket_name(&mut self, name: SmolStr) {
+        self.ipc_socket_name = name;
+    }
+
+    pub(crate) fn set_listener_ip(&mut self, ip: IpAddr) {
+        self.listener_addr.set_ip(ip)
+    }
+
+    // static
+
+    pub(crate) fn default_config_path() -> PathBuf {
+        let mut path = dirs::home_dir().expect(GET_HOME_DIR_FAILED);
+        path.push(consts::DEFAULT_CONFIG_DIR_NAME);
+        if !path.exists() {
+            std::fs::create_dir_all(&path).unwrap();
+        }
+        path.push(consts::DEFAULT_CONFIG_FILE_NAME);
+        path
+    }
+
+    pub(crate) fn open_config_file_readonly<P: AsRef<Path>>(
+        config_path: P,
+    ) -> std::io::Result<File> {
+        File::open(config_path)
+    }
+
+    pub(crate) fn from_file<P: AsRef<Path>>(p: P) -> error::Result<(Self, LastModified)> {
+        let p = p.as_ref();
+        let mut content = String::new();
+        let mut f = File::open(p).map_err(Error::config_io(p))?;
+        if f.read_to_string(&mut content)
+            .map_err(Error::config_io(p))?
+            > 0
+        {
+            let (ok, config) = toml::from_str::<Config>(content.trim())?.checked();
+            let modified = if !ok {
+                config.write_to_file(p)?
+            } else if let Ok(last_modified) = f.metadata().map_err(Error::config_io(p))?.modified()
+            {
+                LastModified::LastModTime(last_modified)
+            } else {
+                LastModified::Unsupported
+            };
+
+            Ok((config, modified))
+        } else {
+            Err(Error::InvalidConfig("Config file is empty!".into()))
+        }
+    }
+
+    fn check_num_workers(num: u8) -> (bool, u8) {
+        if num == 0 || num > MAX_WORKERS {
+            (false, consts::DEFAULT_NUM_WORKERS)
+        } else {
+            (true, num)
+        }
+    }
+
+    fn default_save_dir() -> &'static Path {
+        static RECV_DIR: OnceLock<PathBuf> = OnceLock::new();
+        let d = RECV_DIR.get_or_init(|| dirs::download_dir().expect(GET_HOME_DIR_FAILED));
+        if !d.exists() {
+            std::fs::create_dir_all(d)
+                .expect("Unexpected: create default receive(Download) directory failed!");
+        }
+        d
+    }
+
+    fn default_host_name() -> SmolStr {
+        let name = gethostname::gethostname();
+        let name = name.to_string_lossy();
+        let mut end = name.len().min(consts::HOST_NAME_LENGTH_LIMIT);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            return "share".into();
+        }
+        name[..end].into()
+    }
+
+    fn default_receive_path() -> SmolStr {
+        template_key::SAVE_DIR.into()
+    }
+
+    fn default_mirror_dir() -> PathBuf {
+        PathBuf::from("mirror")
+    }
+
+    /// The mirror directory has to stay inside `save_dir`.
+    fn check_mirror_dir(dir: PathBuf) -> (bool, PathBuf) {
+        let inside = dir.components().next().is_some()
+            && dir
+                .components()
+                .all(|c| matches!(c, std::path::Component::Normal(_)));
+        if !inside {
+            log::warn!("Invalid mirror directory, it has to be a subdirectory of `save_dir`! using default instead.");
+            return (false, Self::default_mirror_dir());
+        }
+        (true, dir)
+    }
+
+    fn check_files_save_dir(path: PathBuf) -> (bool, PathBuf) {
+        if !path.is_dir() {
+            log::warn!("Invalid files save directory! using default instead.");
+            return (false, Self::default_save_dir().to_owned());
+        }
+        (true, path)
+    }
+
+    #[inline(always)]
+    pub(crate) fn check_hostname_valid(hostname: &str) -> bool {
+        fshare_proto::Hostname::is_valid(hostname)
+    }
+
+    /// Hosts may be offline while the config is loaded, so only the address itself is checked.
+    fn check_addr_valid(addr: SocketAddr) -> bool {
+        !addr.ip().is_unspecified() && addr.port() != 0
+    }
+
+    /// Moves the per-host settings of old config files into the host records, returns whether
+    /// there was anything to move.
+    fn migrate(&mut self) -> bool {
+        let migrated = !self.host_receive_paths.is_empty() || !self.host_keys.is_empty();
+        for (name, template) in std::mem::take(&mut self.host_receive_paths) {
+            self.set_host_receive_path(&name, Some(template));
+        }
+        for (name, key) in std::mem::take(&mut self.host_keys) {
+            self.set_host_key(&name, Some(key));
+        }
+        migrated
+    }
+
+    pub(crate) fn checked(mut self) -> (bool, Self) {
+        let migrated = self.migrate();
+        let (num_workers_ok, num_workers) = Self::check_num_workers(self.num_workers);
+        let (recv_dir_ok, recv_dir) = Self::check_files_save_dir(self.save_dir);
+        let hosts_count = self.reg_hosts.len();
+        self.reg_hosts.retain(|name, host| {
+            Self::check_hostname_valid(name)
+                && host.addrs.iter().any(|addr| match addr {
+                    HostAddr::Socket(addr) => Self::check_addr_valid(*addr),
+                    HostAddr::Dns(_, port) => *port != 0,
+                })
+        });
+        let reg_hosts = &self.reg_hosts;
+        let mut groups_ok = true;
+        self.host_groups.retain(|group, members| {
+            let members_count = members.len();
+            members.retain(|name| reg_hosts.contains_key(name));
+            groups_ok = groups_ok && members.len() == members_count;
+            Self::check_hostname_valid(group)
+        });
+        let host_name_ok = Self::check_hostname_valid(&self.host_name);
+        if !host_name_ok {
+            self.host_name = Self::default_host_name();
+        }
+        let (mirror_dir_ok, mirror_dir) = Self::check_mirror_dir(self.mirror_dir);
+        self.mirror_dir = mirror_dir;
+        let hooks_ok = self.hooks.max_concurrent > 0;
+        if !hooks_ok {
+            self.hooks.max_concurrent = HooksConfig::default_max_concurrent();
+        }
+        let checked_ok = num_workers_ok
+            && recv_dir_ok
+            && mirror_dir_ok
+            && hooks_ok
+            && groups_ok
+            && host_name_ok
+            && !migrated
+            && self.reg_hosts.len() == hosts_count;
+        self.num_workers = num_workers;
+        self.save_dir = recv_dir;
+        (checked_ok, self)
+    }
+}
+
+/// Replaces the placeholders in `template`, the `{host}` value never introduces new path
+/// components.
+pub(crate) fn render_receive_path(
+    template: &str,
+    save_dir: &Path,
+    host: &str,
+    now: SystemTime,
+) -> PathBuf {
+    let mut host: String = host
+        .chars()
+        .map(|c| if std::path::is_separator(c) { '_' } else { c })
+        .collect();
+    if host == "." || host == ".." {
+        host = host.replace('.', "_");
+    }
+    let date = date_string(now);
+    PathBuf::from(
+        template
+            .replace(template_key::SAVE_DIR, &save_dir.to_string_lossy())
+            .replace(template_key::HOST, &host)
+            .replace(template_key::DATE, &date),
+    )
+}
+
+/// Formats `time` as an UTC `YYYY-MM-DD` date.
+fn date_string(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod config_tests {
+    use std::{
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{render_receive_path, Config};
+
+    #[test]
+    fn render_receive_path_test() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let p = render_receive_path(
+            "{save_dir}/{host}/{date}",
+            Path::new("/tmp/recv"),
+            "pc",
+            now,
+        );
+        assert_eq!(p, Path::new("/tmp/recv/pc/2023-11-14"));
+        let p = render_receive_path("{host}", Path::new("/tmp/recv"), "../etc", now);
+        assert_eq!(p, Path::new(".._etc"));
+        let p = render_receive_path("{host}", Path::new("/tmp/recv"), "..", now);
+        assert_eq!(p, Path::new("__"));
+    }
+
+    #[test]
+    fn config_toml_test() {
+        let old = r#"
+listener_addr = "0.0.0.0:10020"
+num_workers = 4
+save_dir = "/tmp"
+ipc_socket_name = "share.sock"
+
+[reg_hosts]
+pc = "127.0.0.1:10020"
+
+[host_receive_paths]
+pc = "{host}"
+"#;
+        let mut config = toml::from_str::<Config>(old).unwrap();
+        assert!(config.hooks().is_empty());
+        assert!(config.migrate());
+        let pc = config.get_host("pc").unwrap();
+        assert_eq!(pc.addrs, vec!["127.0.0.1:10020".parse().unwrap()]);
+        assert_eq!(pc.policy.receive_path.as_deref(), Some("{host}"));
+        assert!(pc.policy.auto_accept);
+        config.set_host_group("lab", Some(vec!["pc".into(), "gone".into(), "pc".into()]));
+        assert_eq!(config.host_group("lab"), Some(&["pc".into()][..]));
+        assert_eq!(
+            config.get_name_by_ip("127.0.0.1".parse().unwrap()),
+            Some("pc")
+        );
+        assert_eq!(
+            config.get_name_by_ip("::ffff:127.0.0.1".parse().unwrap()),
+            Some("pc")
+        );
+
+        config.hooks.on_file.push("echo $FSHARE_FILE_PATH".into());
+        let s = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&s).unwrap(), config);
+    }
+
+    #[test]
+    fn update_host_test() {
+        let mut config = toml::from_str::<Config>(
+            r#"
+listener_addr = "0.0.0.0:10020"
+num_workers = 4
+save_dir = "/tmp"
+ipc_socket_name = "share.sock"
+
+[reg_hosts.pc]
+addrs = ["127.0.0.1:10020"]
+
+[reg_hosts.nas]
+addrs = ["127.0.0.1:10021"]
+"#,
+        )
+        .unwrap();
+        config.set_host_group("lab", Some(vec!["pc".into()]));
+
+        assert!(!config.rename_host("gone", "desk"));
+        assert!(!config.rename_host("pc", "nas"));
+        assert!(config.rename_host("pc", "desk"));
+        assert_eq!(config.host_group("lab"), Some(&["desk".into()][..]));
+        assert!(config.set_host_addrs("desk", vec!["pc.lan:10020".parse().unwrap()]));
+        assert!(!config.set_host_addrs("desk", vec![]));
+        assert!(config.unregister_host("desk").is_some());
+        assert_eq!(config.host_group("lab"), Some(&[][..]));
+        assert_eq!(config.reg_hosts().len(), 1);
+    }
+}
@@ -0,0 +1,730 @@
+//! Delta transfer of a file the receiver has an older copy of, like rsync. The receiver sends the
+//! signatures of the blocks of its copy, the sender answers with references to the blocks it can
+//! reuse and the literal data in between, and the receiver rebuilds the file next to its copy.
+//!
+//! Only used when both sides list `DELTA` in their `CAPS` and `SEND_START` lines, the receiver then
+//! answers a `HASH` line with `DELTA <block size> <size of its copy>` instead of `SEND`.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use sha2::{Digest, Sha256};
+use smol_str::{SmolStr, ToSmolStr};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, Take};
+
+use crate::{
+    common::{LocalResponse, StartLine},
+    consts,
+    handler::{self, WriteLine},
+    policy::Throttle,
+    request_tag::delta,
+    transfer::Transfer,
+};
+
+/// Files smaller than this are always sent in full.
+pub(crate) const MIN_FILE_SIZE: u64 = 64 * 1024;
+const MIN_BLOCK_SIZE: u64 = 2 * 1024;
+const MAX_BLOCK_SIZE: u64 = 128 * 1024;
+/// Unmatched data is cut into pieces of this size, so that the window buffer stays small.
+const MAX_LITERAL_LEN: usize = 64 * 1024;
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The block size for a copy of `size` bytes, about its square root like rsync.
+pub(crate) fn block_size(size: u64) -> u64 {
+    size.isqrt()
+        .next_multiple_of(1024)
+        .clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+/// The rsync rolling checksum of a window, which is moved by one byte cheaply.
+#[derive(Debug, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(data: &[u8]) -> Self {
+        let (mut a, mut b) = (0_u32, 0_u32);
+        for &x in data {
+            a = a.wrapping_add(x as u32);
+            b = b.wrapping_add(a);
+        }
+        Self {
+            a,
+            b,
+            len: data.len() as u32,
+        }
+    }
+
+    fn roll(self, out: u8, into: u8) -> Self {
+        let a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        let b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(a);
+        Self { a, b, ..self }
+    }
+
+    fn digest(self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong_hash(data: &[u8]) -> [u8; 16] {
+    let mut strong = [0; 16];
+    strong.copy_from_slice(&Sha256::digest(data)[..16]);
+    strong
+}
+
+/// The checksums of a block of the receiver's copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BlockSignature {
+    weak: u32,
+    strong: [u8; 16],
+}
+
+impl BlockSignature {
+    fn of(data: &[u8]) -> Self {
+        Self {
+            weak: Rolling::new(data).digest(),
+            strong: strong_hash(data),
+        }
+    }
+
+    /// `<weak> <strong>`, both hex encoded.
+    pub(crate) fn to_line(self) -> SmolStr {
+        smol_str::format_smolstr!("{:08x} {}", self.weak, handler::hex_string(&self.strong))
+    }
+
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let (weak, strong_hex) = line.trim().split_once(consts::STARTLINE_SEP)?;
+        let weak = u32::from_str_radix(weak, 16).ok()?;
+        if strong_hex.len() != 32 || !strong_hex.is_ascii() {
+            return None;
+        }
+        let mut strong = [0; 16];
+        for (i, b) in strong.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&strong_hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(Self { weak, strong })
+    }
+}
+
+/// The signatures of the blocks of `file`, the last block may be shorter.
+pub(crate) fn signatures(
+    file: &mut impl Read,
+    block_size: u64,
+) -> std::io::Result<Vec<BlockSignature>> {
+    let mut signatures = vec![];
+    let mut block = Vec::with_capacity(block_size as usize);
+    loop {
+        block.clear();
+        (&mut *file).take(block_size).read_to_end(&mut block)?;
+        if block.is_empty() {
+            return Ok(signatures);
+        }
+        signatures.push(BlockSignature::of(&block));
+    }
+}
+
+/// A piece of the new file, either blocks of the receiver's copy or literal data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    /// `count` blocks from `index` on, `len` bytes in total.
+    Copy { index: u64, count: u64, len: u64 },
+    /// `len` bytes of the new file from `offset` on.
+    Data { offset: u64, len: u64 },
+}
+
+impl Op {
+    fn len(self) -> u64 {
+        match self {
+            Op::Copy { len, .. } | Op::Data { len, .. } => len,
+        }
+    }
+}
+
+fn push_copy(ops: &mut Vec<Op>, block: u64, block_len: u64) {
+    if let Some(Op::Copy { index, count, len }) = ops.last_mut() {
+        if *index + *count == block {
+            *count += 1;
+            *len += block_len;
+            return;
+        }
+    }
+    ops.push(Op::Copy {
+        index: block,
+        count: 1,
+        len: block_len,
+    });
+}
+
+fn push_data(ops: &mut Vec<Op>, offset: u64, len: usize) {
+    if len > 0 {
+        ops.push(Op::Data {
+            offset,
+            len: len as u64,
+        });
+    }
+}
+
+/// Compares the new file with the signatures of the receiver's copy of `base_size` bytes and
+/// returns how to rebuild the new file from that copy.
+pub(crate) fn diff(
+    signatures: &[BlockSignature],
+    block_size: u64,
+    base_size: u64,
+    new: &mut impl Read,
+) -> std::io::Result<Vec<Op>> {
+    let bs = block_size as usize;
+    let block_len = |i: usize| (base_size - i as u64 * block_size).min(block_size) as usize;
+    let mut table: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, signature) in signatures.iter().enumerate() {
+        table.entry(signature.weak).or_default().push(i);
+    }
+    let mut ops = vec![];
+    // `buf` starts at `buf_offset` of the new file, the data before `literal` is handled and
+    // the window starts at `window`.
+    let (mut buf, mut buf_offset, mut literal, mut window) = (vec![], 0_u64, 0, 0);
+    let mut rolling: Option<Rolling> = None;
+    let mut eof = false;
+    loop {
+        // One byte more than the window, to roll it.
+        while !eof && buf.len() <= window + bs {
+            let len = buf.len();
+            buf.resize(len + READ_CHUNK_SIZE, 0);
+            let read_size = new.read(&mut buf[len..])?;
+            buf.truncate(len + read_size);
+            eof = read_size == 0;
+        }
+        let end = (window + bs).min(buf.len());
+        if window == end {
+            break;
+        }
+        let sum = *rolling.get_or_insert_with(|| Rolling::new(&buf[window..end]));
+        let matched = table.get(&sum.digest()).and_then(|candidates| {
+            let strong = strong_hash(&buf[window..end]);
+            candidates
+                .iter()
+                .copied()
+                .find(|&i| signatures[i].strong == strong && block_len(i) == end - window)
+        });
+        if let Some(i) = matched {
+            push_data(&mut ops, buf_offset + literal as u64, window - literal);
+            push_copy(&mut ops, i as u64, (end - window) as u64);
+            (literal, window, rolling) = (end, end, None);
+        } else if end < buf.len() {
+            rolling = Some(sum.roll(buf[window], buf[end]));
+            window += 1;
+            if window - literal >= MAX_LITERAL_LEN {
+                push_data(&mut ops, buf_offset + literal as u64, window - literal);
+                literal = window;
+            }
+        } else {
+            break;
+        }
+        if literal >= READ_CHUNK_SIZE {
+            buf.drain(..literal);
+            buf_offset += literal as u64;
+            window -= literal;
+            literal = 0;
+        }
+    }
+    push_data(&mut ops, buf_offset + literal as u64, buf.len() - literal);
+    Ok(ops)
+}
+
+/// Passes `count` blocks from `index` on of the receiver's copy to `write`, returns their length.
+pub(crate) fn copy_blocks(
+    base: &mut File,
+    block_size: u64,
+    index: u64,
+    count: u64,
+    mut write: impl FnMut(&[u8]) -> std::io::Result<()>,
+) -> std::io::Result<u64> {
+    base.seek(SeekFrom::Start(index * block_size))?;
+    let mut blocks = base.take(count * block_size);
+    let mut buf = [0; consts::FILE_TRANS_BUF_SIZE];
+    let mut copied = 0;
+    loop {
+        let read_size = blocks.read(&mut buf)?;
+        if read_size == 0 {
+            return Ok(copied);
+        }
+        write(&buf[..read_size])?;
+        copied += read_size as u64;
+    }
+}
+
+/// A line of the delta stream of the sender, `DATA` is followed by the literal data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Instruction {
+    Copy { index: u64, count: u64 },
+    Data(u64),
+    End,
+}
+
+impl Instruction {
+    pub(crate) fn to_line(self) -> SmolStr {
+        match self {
+            Instruction::Copy { index, count } => {
+                smol_str::format_smolstr!("{} {} {}", delta::COPY, index, count)
+            }
+            Instruction::Data(len) => smol_str::format_smolstr!("{} {}", delta::DATA, len),
+            Instruction::End => delta::DELTA_END.into(),
+        }
+    }
+
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim().split(consts::STARTLINE_SEP);
+        let instruction = match fields.next()? {
+            delta::COPY => Instruction::Copy {
+                index: fields.next()?.parse().ok()?,
+                count: fields.next()?.parse().ok()?,
+            },
+            delta::DATA => Instruction::Data(fields.next()?.parse().ok()?),
+            delta::DELTA_END => Instruction::End,
+            _ => return None,
+        };
+        fields.next().is_none().then_some(instruction)
+    }
+}
+
+impl From<Op> for Instruction {
+    fn from(op: Op) -> Self {
+        match op {
+            Op::Copy { index, count, .. } => Instruction::Copy { index, count },
+            Op::Data { len, .. } => Instruction::Data(len),
+        }
+    }
+}
+
+/// Parses `DELTA <block size> <size of the copy>`.
+pub(crate) fn parse_reply(line: &str) -> Option<(u64, u64)> {
+    let mut fields = line.trim().split(consts::STARTLINE_SEP);
+    if fields.next()? != delta::DELTA {
+        return None;
+    }
+    let block_size = fields.next()?.parse().ok()?;
+    let base_size = fields.next()?.parse().ok()?;
+    ((MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size)
+        && base_size <= consts::FILE_SIZE_LIMIT)
+        .then_some((block_size, base_size))
+}
+
+/// Sends `file` as a delta against the receiver's copy, after the receiver answered with `reply`.
+/// Returns false if the transfer was cancelled.
+pub(crate) async fn send<R, W, L>(
+    dest_reader: &mut Take<R>,
+    dest_writer: &mut W,
+    mut local_write_half: L,
+    file: &mut File,
+    reply: (u64, u64),
+    transfer: &Transfer,
+) -> std::io::Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+    L: AsyncWrite + Unpin,
+{
+    let (block_size, base_size) = reply;
+    // The sizes come from the receiver, so the list only grows with the lines which arrive.
+    let mut signatures = vec![];
+    let mut line = String::new();
+    for _ in 0..base_size.div_ceil(block_size) {
+        line.clear();
+        dest_reader.set_limit(StartLine::LENGTH_LIMIT);
+        dest_reader.read_line(&mut line).await?;
+        signatures.push(BlockSignature::parse(&line).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid block signature")
+        })?);
+    }
+    let file_size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let ops = diff(&signatures, block_size, base_size, file)?;
+    let mut position = 0;
+    for op in ops {
+        dest_writer
+            .write_line(Instruction::from(op).to_line())
+            .await?;
+        if let Op::Data { offset, len } = op {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut data = (&mut *file).take(len);
+            let mut buf = [0; consts::FILE_TRANS_BUF_SIZE];
+            loop {
+                if transfer.is_cancelled() {
+                    local_write_half
+                        .write_line(LocalResponse::Cancelled.to_str_unchecked())
+                        .await?;
+                    return Ok(false);
+                }
+                let read_size = data.read(&mut buf)?;
+                if read_size == 0 {
+                    break;
+                }
+                dest_writer.write_all(&buf[..read_size]).await?;
+                position += read_size as u64;
+                transfer.progress(read_size as u64);
+                local_write_half
+                    .write_line(
+                        LocalResponse::Progress(position as f64 / file_size.max(1) as f64)
+                            .to_smolstr(),
+                    )
+                    .await?;
+            }
+            dest_writer.flush().await?;
+        } else {
+            position += op.len();
+            transfer.progress(op.len());
+        }
+    }
+    dest_writer.write_line(Instruction::End.to_line()).await?;
+    local_write_half
+        .write_line(LocalResponse::Progress(1.0).to_smolstr())
+        .await?;
+    Ok(true)
+}
+
+/// How receiving a delta ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Received {
+    Complete,
+    /// Cancelled, or the sender stopped before `DELTA_END`.
+    Stopped,
+    /// The rebuilt file does not match the hash the sender announced.
+    Corrupt,
+}
+
+/// Receives the new content of `path` as a delta against its current content, which is replaced
+/// once the whole file arrived and matches `hash`.
+pub(crate) async fn receive<R, W>(
+    reader: &mut Take<R>,
+    write_half: &mut W,
+    path: &Path,
+    file_size: u64,
+    hash: &str,
+    transfer: &Transfer,
+    throttle: &mut Throttle,
+) -> std::io::Result<Received>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut base = File::open(path)?;
+    let base_size = base.metadata()?.len();
+    let block_size = block_size(base_size);
+    let signatures = signatures(&mut base, block_size)?;
+    write_half
+        .write_line(smol_str::format_smolstr!(
+            "{} {} {}",
+            delta::DELTA,
+            block_size,
+            base_size
+        ))
+        .await?;
+    let mut lines = String::new();
+    for signature in &signatures {
+        lines.push_str(&signature.to_line());
+        lines.push_str(consts::LINE_SEP);
+        if lines.len() >= READ_CHUNK_SIZE {
+            write_half.write_all(lines.as_bytes()).await?;
+            lines.clear();
+        }
+    }
+    write_half.write_all(lines.as_bytes()).await?;
+    write_half.flush().await?;
+
+    let part_path = consts::part_path(path);
+    let mut out = File::create(&part_path)?;
+    let mut hasher = Sha256::new();
+    let mut written = 0;
+    let received = loop {
+        if transfer.is_cancelled() {
+            break Received::Stopped;
+        }
+        let mut line = String::new();
+        reader.set_limit(StartLine::LENGTH_LIMIT);
+        if reader.read_line(&mut line).await? == 0 {
+            break Received::Stopped;
+        }
+        match Instruction::parse(&line) {
+            Some(Instruction::Copy { index, count })
+                if index.saturating_add(count) <= signatures.len() as u64 =>
+            {
+                let copied = copy_blocks(&mut base, block_size, index, count, |data| {
+                    hasher.update(data);
+                    out.write_all(data)
+                })?;
+                written += copied;
+                transfer.progress(copied);
+            }
+            Some(Instruction::Data(len))
+                if written.checked_add(len).is_some_and(|end| end <= file_size) =>
+            {
+                reader.set_limit(len);
+                let mut buf = [0; consts::FILE_TRANS_BUF_SIZE];
+                let mut received = 0;
+                loop {
+                    let read_size = reader.read(&mut buf).await?;
+                    if read_size == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..read_size]);
+                    out.write_all(&buf[..read_size])?;
+                    received += read_size as u64;
+                    transfer.progress(read_size as u64);
+                    throttle.consume(read_size).await;
+                }
+                written += received;
+                if received < len {
+                    break Received::Stopped;
+                }
+            }
+            Some(Instruction::End) => {
+                if written == file_size && handler::hex_string(&hasher.finalize()) == hash {
+                    break Received::Complete;
+                }
+                break Received::Corrupt;
+            }
+            _ => break Received::Stopped,
+        }
+    };
+    drop(base);
+    if received == Received::Complete {
+        out.flush()?;
+        drop(out);
+        std::fs::rename(&part_path, path)?;
+    } else {
+        drop(out);
+        let _ = std::fs::remove_file(&part_path);
+    }
+    Ok(received)
+}
+
+#[cfg(test)]
+mod delta_tests {
+    use std::{fs::File, io::Cursor, path::Path};
+
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+    use super::{
+        block_size, diff, parse_reply, receive, send, signatures, BlockSignature, Instruction, Op,
+        Received, Rolling,
+    };
+    use crate::{
+        common::StartLine,
+        consts,
+        handler::WriteLine,
+        policy::Throttle,
+        store,
+        transfer::{Direction, Transfer},
+    };
+
+    /// Deterministic bytes which do not repeat within a block.
+    fn bytes(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    /// Rebuilds the new file like the receiver does.
+    fn apply(base: &[u8], block_size: u64, ops: &[Op], new: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        for op in ops {
+            match *op {
+                Op::Copy { index, count, len } => {
+                    let start = (index * block_size) as usize;
+                    let end = (start + (count * block_size) as usize).min(base.len());
+                    assert_eq!((end - start) as u64, len);
+                    out.extend_from_slice(&base[start..end]);
+                }
+                Op::Data { offset, len } => {
+                    out.extend_from_slice(&new[offset as usize..(offset + len) as usize])
+                }
+            }
+        }
+        out
+    }
+
+    fn literal_len(ops: &[Op]) -> u64 {
+        ops.iter()
+            .map(|op| match op {
+                Op::Data { len, .. } => *len,
+                Op::Copy { .. } => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn rolling_test() {
+        let data = bytes(100, 1);
+        let mut rolling = Rolling::new(&data[..32]);
+        for i in 0..68 {
+            rolling = rolling.roll(data[i], data[i + 32]);
+            assert_eq!(
+                rolling.digest(),
+                Rolling::new(&data[i + 1..i + 33]).digest()
+            );
+        }
+    }
+
+    #[test]
+    fn diff_test() {
+        let base = bytes(300_000, 7);
+        let bs = block_size(base.len() as u64);
+        assert_eq!(bs, 2048);
+        let sigs = signatures(&mut Cursor::new(&base), bs).unwrap();
+        assert_eq!(sigs.len() as u64, (base.len() as u64).div_ceil(bs));
+
+        // The same file is copied in one piece.
+        let ops = diff(&sigs, bs, base.len() as u64, &mut Cursor::new(&base)).unwrap();
+        assert_eq!(ops.len(), 1);
+        assert_eq!(apply(&base, bs, &ops, &base), base);
+
+        // An edit, an insertion and a new tail cost little more than themselves.
+        let mut new = base.clone();
+        new[1000..1010].copy_from_slice(&[0; 10]);
+        new.splice(150_000..150_000, bytes(777, 9));
+        new.truncate(new.len() - 5000);
+        new.extend_from_slice(&bytes(3000, 11));
+        let ops = diff(&sigs, bs, base.len() as u64, &mut Cursor::new(&new)).unwrap();
+        assert_eq!(apply(&base, bs, &ops, &new), new);
+        assert!(literal_len(&ops) < 4 * bs + 3000 + 777);
+
+        // Nothing in common.
+        let other = bytes(100_000, 13);
+        let ops = diff(&sigs, bs, base.len() as u64, &mut Cursor::new(&other)).unwrap();
+        assert_eq!(apply(&base, bs, &ops, &other), other);
+        assert_eq!(literal_len(&ops), other.len() as u64);
+    }
+
+    #[test]
+    fn lines_test() {
+        let sig = BlockSignature::of(b"block");
+        assert_eq!(BlockSignature::parse(&sig.to_line()), Some(sig));
+        assert_eq!(BlockSignature::parse("0000 00"), None);
+        for instruction in [
+            Instruction::Copy { index: 3, count: 2 },
+            Instruction::Data(10),
+            Instruction::End,
+        ] {
+            assert_eq!(
+                Instruction::parse(&instruction.to_line()),
+                Some(instruction)
+            );
+        }
+        assert_eq!(Instruction::parse("COPY 1"), None);
+        assert_eq!(Instruction::parse("DATA 1 2"), None);
+    }
+
+    /// Sends `new` as a delta against `base` over an in-memory connection, announcing `hash`.
+    async fn send_delta(base: &Path, new: &Path, hash: &str) -> Received {
+        let (sender, receiver) = tokio::io::duplex(64 * 1024);
+        let (sender_read, mut sender_write) = tokio::io::split(sender);
+        let (receiver_read, mut receiver_write) = tokio::io::split(receiver);
+        let transfer = Transfer::queue(Direction::Send, "delta");
+        let file_size = std::fs::metadata(new).unwrap().len();
+        let receiving = async {
+            let mut reader = BufReader::new(receiver_read).take(0);
+            let mut throttle = Throttle::new(None);
+            receive(
+                &mut reader,
+                &mut receiver_write,
+                base,
+                file_size,
+                hash,
+                &transfer,
+                &mut throttle,
+            )
+            .await
+            .unwrap()
+        };
+        let sending = async {
+            let mut reader = BufReader::new(sender_read).take(StartLine::LENGTH_LIMIT);
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            let reply = parse_reply(&line).unwrap();
+            let mut file = File::open(new).unwrap();
+            let local = tokio::io::sink();
+            send(
+                &mut reader,
+                &mut sender_write,
+                local,
+                &mut file,
+                reply,
+                &transfer,
+            )
+            .await
+            .unwrap()
+        };
+        let (received, sent) = tokio::join!(receiving, sending);
+        assert!(sent);
+        received
+    }
+
+    #[tokio::test]
+    async fn send_receive_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let (base, new) = (dir.path().join("base.bin"), dir.path().join("new.bin"));
+        let old = bytes(300_000, 7);
+        let mut changed = old.clone();
+        changed[1000..1010].copy_from_slice(&[0; 10]);
+        changed.extend_from_slice(&bytes(3000, 11));
+        std::fs::write(&base, &old).unwrap();
+        std::fs::write(&new, &changed).unwrap();
+
+        // A result which does not match the hash leaves the copy alone.
+        let wrong = "0".repeat(64);
+        assert_eq!(send_delta(&base, &new, &wrong).await, Received::Corrupt);
+        assert_eq!(std::fs::read(&base).unwrap(), old);
+        assert!(!consts::part_path(&base).exists());
+
+        let hash = store::hash_file(&new).unwrap();
+        assert_eq!(send_delta(&base, &new, &hash).await, Received::Complete);
+        assert_eq!(std::fs::read(&base).unwrap(), changed);
+    }
+
+    #[tokio::test]
+    async fn oversized_data_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.bin");
+        std::fs::write(&base, bytes(300_000, 7)).unwrap();
+        // Large enough for the signatures, which are not read.
+        let (sender, receiver) = tokio::io::duplex(1024 * 1024);
+        let (_sender_read, mut sender_write) = tokio::io::split(sender);
+        let (receiver_read, mut receiver_write) = tokio::io::split(receiver);
+        let transfer = Transfer::queue(Direction::Send, "delta");
+        for instruction in [
+            Instruction::Copy { index: 0, count: 1 },
+            Instruction::Data(u64::MAX),
+        ] {
+            sender_write
+                .write_line(instruction.to_line())
+                .await
+                .unwrap();
+        }
+        let mut reader = BufReader::new(receiver_read).take(0);
+        let received = receive(
+            &mut reader,
+            &mut receiver_write,
+            &base,
+            300_000,
+            &"0".repeat(64),
+            &transfer,
+            &mut Throttle::new(None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(received, Received::Stopped);
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use smol_str::{SmolStr, ToSmolStr};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{net::UdpSocket, sync::RwLock};
+
+use crate::{config::Config, config::DiscoveryConfig, consts, request_tag};
+
+/// Announcements older than this many intervals are dropped from the peer table.
+const EXPIRE_INTERVALS: u32 = 3;
+const DATAGRAM_SIZE_LIMIT: usize = 256;
+
+/// What a daemon tells the LAN about itself: `FSHARE_ANNOUNCE <name> <port> <fingerprint>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Announcement {
+    pub(crate) name: SmolStr,
+    pub(crate) port: u16,
+    pub(crate) fingerprint: SmolStr,
+}
+
+impl ToSmolStr for Announcement {
+    fn to_smolstr(&self) -> SmolStr {
+        smol_str::format_smolstr!(
+            "{} {} {} {}",
+            request_tag::discovery::ANNOUNCE,
+            self.name,
+            self.port,
+            self.fingerprint
+        )
+    }
+}
+
+impl std::str::FromStr for Announcement {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(consts::STARTLINE_SEP);
+        if parts.next() != Some(request_tag::discovery::ANNOUNCE) {
+            return Err(());
+        }
+        let (Some(name), Some(port), Some(fingerprint), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(());
+        };
+        if !Config::check_hostname_valid(name) || fingerprint.is_empty() {
+            return Err(());
+        }
+        Ok(Self {
+            name: name.into(),
+            port: port.parse().map_err(|_| ())?,
+            fingerprint: fingerprint.into(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct DiscoveredPeer {
+    pub(crate) name: SmolStr,
+    pub(crate) addr: SocketAddr,
+    pub(crate) fingerprint: SmolStr,
+    pub(crate) last_seen: Instant,
+}
+
+/// Peers seen on the LAN, keyed by their key fingerprint.
+#[derive(Debug, Default)]
+pub(crate) struct PeerTable {
+    peers: HashMap<SmolStr, DiscoveredPeer>,
+}
+
+impl PeerTable {
+    fn update(&mut self, announcement: Announcement, from: IpAddr) {
+        self.peers.insert(
+            announcement.fingerprint.clone(),
+            DiscoveredPeer {
+                name: announcement.name,
+                addr: SocketAddr::new(from, announcement.port),
+                fingerprint: announcement.fingerprint,
+                last_seen: Instant::now(),
+            },
+        );
+    }
+
+    fn expire(&mut self, max_age: Duration) {
+        self.peers
+            .retain(|_, peer| peer.last_seen.elapsed() <= max_age);
+    }
+
+    /// Looks a peer up by its announced name, the most recently seen one wins.
+    pub(crate) fn get_by_name(&self, name: &str) -> Option<&DiscoveredPeer> {
+        self.peers
+            .values()
+            .filter(|peer| peer.name == name)
+            .max_by_key(|peer| peer.last_seen)
+    }
+
+    pub(crate) fn peers(&self) -> impl Iterator<Item = &DiscoveredPeer> {
+        self.peers.values()
+    }
+}
+
+/// The peers discovered by this daemon.
+pub(crate) fn peer_table() -> Arc<RwLock<PeerTable>> {
+    static PEER_TABLE: OnceLock<Arc<RwLock<PeerTable>>> = OnceLock::new();
+    PEER_TABLE.get_or_init(Default::default).clone()
+}
+
+pub(crate) struct Discovery {
+    socket: UdpSocket,
+    config: DiscoveryConfig,
+    own: Announcement,
+}
+
+impl Discovery {
+    /// Binds the discovery socket to the port of the configured group and joins the group if it
+    /// is a multicast address, otherwise the group address is used for broadcasting.
+    pub(crate) fn bind(config: DiscoveryConfig, own: Announcement) -> std::io::Result<Self> {
+        let group = config.group;
+        let socket = Socket::new(Domain::for_address(group), Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        match (group.ip(), config.interface) {
+            (IpAddr::V4(group_ip), interface) => {
+                socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, group.port())).into())?;
+                let interface = match interface {
+                    IpAddr::V4(ip) => ip,
+                    IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+                };
+                if group_ip.is_multicast() {
+                    socket.join_multicast_v4(&group_ip, &interface)?;
+                    socket.set_multicast_if_v4(&interface)?;
+                    socket.set_multicast_loop_v4(true)?;
+                } else {
+                    socket.set_broadcast(true)?;
+                }
+            }
+            (IpAddr::V6(group_ip), _) => {
+                socket.set_only_v6(true)?;
+                socket.bind(
+                    &SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, group.port())).into(),
+                )?;
+                if group_ip.is_multicast() {
+                    socket.join_multicast_v6(&group_ip, 0)?;
+                    socket.set_multicast_loop_v6(true)?;
+                }
+            }
+        }
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket: UdpSocket::from_std(socket.into())?,
+            config,
+            own,
+        })
+    }
+
+    /// Announces this daemon every interval and records the announcements of the others.
+    pub(crate) async fn run(self, table: Arc<RwLock<PeerTable>>) -> std::io::Result<()> {
+        let interval = self.config.interval();
+        let mut ticker = tokio::time::interval(interval);
+        let announcement = self.own.to_smolstr();
+        let mut buf = [0_u8; DATAGRAM_SIZE_LIMIT];
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if let Err(e) = self
+                        .socket
+                        .send_to(announcement.as_bytes(), self.config.group)
+                        .await
+                    {
+                        log::warn!("Send discovery announcement failed! Detail: {}", e);
+                    }
+                    table.write().await.expire(interval * EXPIRE_INTERVALS);
+                }
+                res = self.socket.recv_from(&mut buf) => {
+                    // E.g. an ICMP error for an earlier announcement, the socket is still usable.
+                    let (size, from) = match res {
+                        Ok(received) => received,
+                        Err(e) => {
+                            log::warn!("Receive discovery announcement failed! Detail: {}", e);
+                            continue;
+                        }
+                    };
+                    let Ok(msg) = std::str::from_utf8(&buf[..size]) else {
+                        continue;
+                    };
+                    match msg.parse::<Announcement>() {
+                        Ok(a) if a.fingerprint != self.own.fingerprint => {
+                            table.write().await.update(a, from.ip());
+                        }
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod discovery_tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use tokio::sync::RwLock;
+
+    use super::{Announcement, Discovery, PeerTable};
+    use crate::config::DiscoveryConfig;
+
+    #[test]
+    fn announcement_parse_test() {
+        let a = Announcement {
+            name: "pc".into(),
+            port: 10020,
+            fingerprint: "0123456789abcdef".into(),
+        };
+        let s = smol_str::ToSmolStr::to_smolstr(&a);
+        assert_eq!(s.parse::<Announcement>(), Ok(a));
+        assert!("FSHARE_ANNOUNCE pc 10020".parse::<Announcement>().is_err());
+        assert!("FSHARE_ANNOUNCE a_name_longer_than_twenty 1 ff"
+            .parse::<Announcement>()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn loopback_discovery_test() {
+        let config = DiscoveryConfig {
+            enabled: true,
+            group: SocketAddr::from((Ipv4Addr::new(239, 255, 70, 77), 41021)),
+            interface: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            interval_secs: 1,
+        };
+        let mut tables = vec![];
+        for (name, port, fingerprint) in [("a", 10020, "aaaa"), ("b", 10030, "bbbb")] {
+            let own = Announcement {
+                name: name.into(),
+                port,
+                fingerprint: fingerprint.into(),
+            };
+            let d = Discovery::bind(config.clone(), own).unwrap();
+            let table = Arc::new(RwLock::new(PeerTable::default()));
+            tokio::spawn(d.run(table.clone()));
+            tables.push(table);
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let a_table = tables[0].read().await;
+        let b = a_table.get_by_name("b").unwrap();
+        assert_eq!(b.addr, SocketAddr::from((Ipv4Addr::LOCALHOST, 10030)));
+        assert!(a_table.get_by_name("a").is_none());
+        let b_table = tables[1].read().await;
+        assert_eq!(b_table.get_by_name("a").unwrap().fingerprint, "aaaa");
+    }
+}
@@ -0,0 +1,67 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use smol_str::SmolStr;
+
+/// The errors of the server library.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing the config file failed.
+    #[error("Access config file `{}` failed: {source}", path.display())]
+    ConfigIo {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The config file or a value given for it is not valid.
+    #[error("Invalid config: {0}")]
+    InvalidConfig(SmolStr),
+    #[error("Invalid hostname `{0}`")]
+    InvalidHostname(SmolStr),
+    /// The listener for other daemons could not be bound to any address.
+    #[error("Bind listener to {addr} failed: {source}")]
+    Bind {
+        addr: SocketAddr,
+        #[source]
+        source: std::io::Error,
+    },
+    /// The IPC socket name is taken, most likely by another running daemon.
+    #[error("IPC socket `{0}` is in use by another process")]
+    IpcSocketInUse(SmolStr),
+    #[error("Create IPC socket `{name}` failed: {source}")]
+    IpcSocket {
+        name: SmolStr,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A peer answered with something the protocol does not allow.
+    #[error("Protocol violation: unexpected `{0}`")]
+    Protocol(SmolStr),
+    /// A peer refused the request, with the response it gave.
+    #[error("Rejected by peer: `{0}`")]
+    PeerRejected(SmolStr),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Maps an I/O error on the config file at `path`.
+    pub(crate) fn config_io(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::ConfigIo { path, source }
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Self::InvalidConfig(smol_str::format_smolstr!("{}", e))
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(e: toml::ser::Error) -> Self {
+        Self::InvalidConfig(smol_str::format_smolstr!("{}", e))
+    }
+}
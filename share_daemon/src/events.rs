@@ -0,0 +1,98 @@
+use std::{sync::OnceLock, time::Duration};
+
+use smol_str::ToSmolStr;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    sync::broadcast::{self, error::RecvError},
+};
+
+pub(crate) use fshare_proto::Event;
+
+use crate::{common::LocalResponse, handler::WriteLine};
+
+/// A subscriber which falls this many events behind misses the oldest ones.
+const EVENT_BUFFER_SIZE: usize = 256;
+/// Progress events of a transfer are emitted at most this often.
+pub(crate) const PROGRESS_EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
+fn sender() -> &'static broadcast::Sender<Event> {
+    static SENDER: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+    SENDER.get_or_init(|| broadcast::channel(EVENT_BUFFER_SIZE).0)
+}
+
+/// Sends the event to every subscriber, it is dropped if there is none.
+pub(crate) fn emit(event: Event) {
+    let _ = sender().send(event);
+}
+
+pub(crate) fn subscribe() -> broadcast::Receiver<Event> {
+    sender().subscribe()
+}
+
+/// The next event, a subscriber which fell behind gets a `Lagged` event for the ones it missed.
+pub(crate) async fn next(events: &mut broadcast::Receiver<Event>) -> Event {
+    match events.recv().await {
+        Ok(event) => event,
+        Err(RecvError::Lagged(missed)) => Event::Lagged { missed },
+        // The sender lives as long as the process.
+        Err(RecvError::Closed) => std::future::pending().await,
+    }
+}
+
+/// Answers `SUBSCRIBE` with `SUBSCRIBED` and an `EVENT` line for every event until the local
+/// process hangs up.
+pub(crate) async fn stream_events<S>(local_stream: &mut S) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut events = subscribe();
+    local_stream
+        .write_line(LocalResponse::Subscribed.to_str_unchecked())
+        .await?;
+    let mut hang_up_buf = [0_u8; 64];
+    loop {
+        let event = tokio::select! {
+            event = next(&mut events) => event,
+            read = local_stream.read(&mut hang_up_buf) => match read {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(_) => continue,
+            },
+        };
+        local_stream
+            .write_line(LocalResponse::Event(event).to_smolstr())
+            .await?;
+    }
+}
+
+#[cfg(test)]
+mod events_tests {
+    use super::Event;
+    use crate::transfer::Direction;
+
+    #[tokio::test]
+    async fn stream_events_test() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let handle = tokio::spawn(async move { super::stream_events(&mut server).await });
+        let mut lines = tokio::io::AsyncBufReadExt::lines(tokio::io::BufReader::new(&mut client));
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "SUBSCRIBED");
+        let event = Event::Progress {
+            transfer: 7,
+            direction: Direction::Receive,
+            host: "pc".into(),
+            done: 10,
+            total: None,
+            file: "a b.txt".into(),
+        };
+        super::emit(event.clone());
+        // Other tests may emit events meanwhile.
+        let expected = "EVENT progress 7 receive pc 10 - a b.txt";
+        let mut line = String::new();
+        while line != expected {
+            line = lines.next_line().await.unwrap().unwrap();
+        }
+        assert_eq!(line["EVENT ".len()..].parse::<Event>(), Ok(event));
+        drop(lines);
+        drop(client);
+        handle.await.unwrap().unwrap();
+    }
+}
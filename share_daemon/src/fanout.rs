@@ -0,0 +1,396 @@
+use std::{fs::File, io::Read, net::SocketAddr, path::PathBuf};
+
+use futures::future::join_all;
+use smol_str::{SmolStr, ToSmolStr};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+use crate::{
+    common::{LocalResponse, StartLine},
+    config::{Config, HostAddr},
+    consts,
+    handler::{self, Capabilities, WriteLine},
+    request_tag, store,
+    transfer::{Direction, Transfer},
+};
+
+/// A host a batch is sent to, `name` is how the local process addressed it.
+#[derive(Debug, Clone)]
+pub(crate) struct Destination {
+    pub(crate) name: SmolStr,
+    pub(crate) addrs: Vec<HostAddr>,
+    pub(crate) relay_target: Option<SmolStr>,
+}
+
+/// Resolves the argument of a `SHARE` request, a comma separated list of registered hosts, host
+/// groups and `target@relay` pairs. The flag tells whether the batch has to be fanned out, which
+/// is the case for more than one host or any host group.
+pub(crate) fn resolve_destinations(
+    config: &Config,
+    arg: &str,
+) -> Result<(bool, Vec<Destination>), &'static str> {
+    let mut fan_out = false;
+    let mut dests: Vec<Destination> = vec![];
+    for item in arg.split(consts::HOSTS_SEP).map(str::trim) {
+        let (target, host) = match item.split_once(consts::RELAY_SEP) {
+            Some((target, relay)) => (Some(target), relay),
+            None => (None, item),
+        };
+        if target.is_some_and(|t| !Config::check_hostname_valid(t)) {
+            return Err(crate::common::Response::InvalidHostname.to_str_unchecked());
+        }
+        let mut push = |name: &str, addrs: &[HostAddr]| {
+            if dests.iter().all(|d| d.name != name) {
+                dests.push(Destination {
+                    name: name.into(),
+                    addrs: addrs.to_vec(),
+                    relay_target: target.map(SmolStr::from),
+                });
+            }
+        };
+        if let Some(addrs) = config.get_addrs_by_name(host) {
+            push(item, addrs);
+        } else if let Some(members) = config.host_group(host).filter(|_| target.is_none()) {
+            fan_out = true;
+            for member in members {
+                if let Some(addrs) = config.get_addrs_by_name(member) {
+                    push(member, addrs);
+                }
+            }
+        } else {
+            return Err(LocalResponse::UnregisteredHostname.to_str_unchecked());
+        }
+    }
+    if dests.is_empty() {
+        return Err(LocalResponse::UnregisteredHostname.to_str_unchecked());
+    }
+    Ok((fan_out || dests.len() > 1, dests))
+}
+
+struct Connected {
+    name: SmolStr,
+    /// The data address of the host, or of the relay.
+    addr: SocketAddr,
+    /// What the host supports, deltas are never sent to several hosts.
+    caps: Capabilities,
+    reader: BufReader<OwnedReadHalf>,
+    /// `None` once writing to the host failed.
+    writer: Option<BufWriter<OwnedWriteHalf>>,
+    /// Whether the host already has the content of the current file.
+    has_file: bool,
+    /// The files written to the host, reported once it answered how many it received.
+    sent: Vec<SmolStr>,
+    /// The result of a host which stopped the batch early.
+    result: Option<LocalResponse>,
+}
+
+async fn connect(dest: &Destination) -> Result<Connected, LocalResponse> {
+    let (dest_addr, caps) =
+        match handler::request_receive_addr(&dest.addrs, dest.relay_target.as_deref()).await {
+            Ok(Ok(addr_caps)) => addr_caps,
+            Ok(Err(resp)) => return Err(resp),
+            Err(_) => return Err(LocalResponse::UnexpectedRemoteResponse),
+        };
+    let stream = TcpStream::connect(dest_addr)
+        .await
+        .map_err(|_| LocalResponse::UnreachableAddress(dest_addr.into()))?;
+    let (reader, writer) = stream.into_split();
+    Ok(Connected {
+        name: dest.name.clone(),
+        addr: dest_addr,
+        caps: Capabilities {
+            delta: false,
+            ..caps
+        },
+        reader: BufReader::new(reader),
+        writer: Some(BufWriter::new(writer)),
+        has_file: false,
+        sent: vec![],
+        result: None,
+    })
+}
+
+/// Writes `data` to every host still alive at the same time, a host which fails is dropped.
+async fn write_all_dests(dests: &mut [Connected], data: &[u8]) {
+    write_dests(dests, data, |_| true).await
+}
+
+/// Writes file content to every host still alive which does not have the file yet.
+async fn write_content_dests(dests: &mut [Connected], data: &[u8]) {
+    write_dests(dests, data, |dest| !dest.has_file).await
+}
+
+/// Writes `data` to every host still alive that `to` selects.
+async fn write_dests(dests: &mut [Connected], data: &[u8], to: impl Fn(&Connected) -> bool) {
+    let to = &to;
+    join_all(dests.iter_mut().map(|dest| async move {
+        if !to(dest) {
+            return;
+        }
+        let Some(writer) = dest.writer.as_mut() else {
+            return;
+        };
+        let res = match writer.write_all(data).await {
+            Ok(()) => writer.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            log::warn!("Sending to `{}` failed! Detail: {}", dest.name, e);
+            dest.writer = None;
+        }
+    }))
+    .await;
+}
+
+async fn read_batch_result(dest: &mut Connected, files_count: u8) -> LocalResponse {
+    if let Some(result) = dest.result.take() {
+        return result;
+    }
+    if dest.writer.is_none() {
+        return LocalResponse::UnexpectedSendResp;
+    }
+    let mut reader = (&mut dest.reader).take(StartLine::LENGTH_LIMIT);
+    let mut line = String::new();
+    match reader.read_line(&mut line).await {
+        Ok(size) if size != 0 => handler::batch_result(&line, files_count),
+        _ => LocalResponse::UnexpectedSendResp,
+    }
+}
+
+/// Reads whether the host has the announced file, a host which answers anything else than
+/// `HAVE` or `SEND` stopped the batch and is dropped.
+async fn read_hash_reply(dest: &mut Connected, files_count: u8, file_size: u64) {
+    if dest.writer.is_none() || !dest.caps.hash {
+        return;
+    }
+    let mut reader = (&mut dest.reader).take(StartLine::LENGTH_LIMIT);
+    let mut line = String::new();
+    let _ = handler::read_hash_reply(&mut reader, &mut line, file_size).await;
+    match line.trim() {
+        request_tag::send_flag::HAVE => dest.has_file = true,
+        request_tag::send_flag::SEND => dest.has_file = false,
+        _ => {
+            dest.result = Some(handler::batch_result(&line, files_count));
+            dest.writer = None;
+        }
+    }
+}
+
+/// Sends the files to all destinations in parallel, the content of each file is read only once
+/// for all hosts which do not have it yet. Besides the overall progress, the local process gets a
+/// `HOST <name> ...` line for every host and file and for the result of every host, the list ends
+/// with `LIST_END`. A file only counts as sent to a host once the host confirmed it.
+pub(crate) async fn send_files<S>(
+    mut local_write_half: S,
+    dests: Vec<Destination>,
+    files_paths: Vec<PathBuf>,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let start_flag_with_line =
+        smol_str::format_smolstr!("{}{}", request_tag::send_flag::SEND_START, consts::LINE_SEP);
+    local_write_half.write_line(&start_flag_with_line).await?;
+    let names: Vec<&str> = dests.iter().map(|d| d.name.as_str()).collect();
+    let transfer = Transfer::queue(Direction::Send, &names.join(","));
+    local_write_half
+        .write_line(LocalResponse::TransferId(transfer.id()).to_smolstr())
+        .await?;
+    let mut connected = Vec::with_capacity(dests.len());
+    for (dest, res) in dests.iter().zip(join_all(dests.iter().map(connect)).await) {
+        match res {
+            Ok(c) => connected.push(c),
+            Err(resp) => {
+                local_write_half
+                    .write_line(LocalResponse::Host(dest.name.clone(), Box::new(resp)).to_smolstr())
+                    .await?
+            }
+        }
+    }
+    let hash = Capabilities {
+        hash: true,
+        delta: false,
+    };
+    for caps in [Capabilities::default(), hash] {
+        write_dests(
+            &mut connected,
+            smol_str::format_smolstr!(
+                "{}{}{}",
+                caps.start_line(),
+                consts::LINE_SEP,
+                consts::LINE_SEP
+            )
+            .as_bytes(),
+            |dest| dest.caps == caps,
+        )
+        .await;
+    }
+    let mut files_count: u8 = 0;
+    for p in &files_paths {
+        if connected.iter().all(|c| c.writer.is_none()) {
+            break;
+        }
+        let name = handler::announced_file_name(p);
+        let opened = File::open(p).and_then(|f| Ok((f.metadata()?.len(), f)));
+        let (file_size, mut f) = match opened {
+            Ok((size, f)) if size <= consts::FILE_SIZE_LIMIT => (size, f),
+            _ => {
+                log::warn!(
+                    "Skipped file \"{}\", it is unreadable or exceeds the size limit(10GB)",
+                    p.to_string_lossy()
+                );
+                local_write_half
+                    .write_line(LocalResponse::FileFailed(name).to_smolstr())
+                    .await?;
+                continue;
+            }
+        };
+        // Only the hosts which take `HASH` lines need the hash.
+        let hash = if connected.iter().any(|c| c.writer.is_some() && c.caps.hash) {
+            match store::hash_file_blocking(p).await {
+                Ok(hash) => hash,
+                Err(e) => {
+                    log::warn!(
+                        "Skipped file \"{}\", it cannot be hashed: {}",
+                        p.display(),
+                        e
+                    );
+                    local_write_half
+                        .write_line(LocalResponse::FileFailed(name).to_smolstr())
+                        .await?;
+                    continue;
+                }
+            }
+        } else {
+            SmolStr::default()
+        };
+        transfer.start_file(&name, Some(file_size));
+        let file_info = LocalResponse::FileInfo(name.clone(), Some(file_size)).to_smolstr();
+        for hash_line in [false, true] {
+            let line = if hash_line {
+                smol_str::format_smolstr!(
+                    "{}{}{} {}{}",
+                    file_info,
+                    consts::LINE_SEP,
+                    request_tag::send_flag::HASH,
+                    hash,
+                    consts::LINE_SEP
+                )
+            } else {
+                smol_str::format_smolstr!("{}{}", file_info, consts::LINE_SEP)
+            };
+            write_dests(&mut connected, line.as_bytes(), |dest| {
+                dest.caps.hash == hash_line
+            })
+            .await;
+        }
+        local_write_half.write_line(&file_info).await?;
+        join_all(
+            connected
+                .iter_mut()
+                .map(|c| read_hash_reply(c, files_count, file_size)),
+        )
+        .await;
+        let content_needed = connected.iter().any(|c| c.writer.is_some() && !c.has_file);
+        if !content_needed {
+            transfer.progress(file_size);
+            local_write_half
+                .write_line(LocalResponse::Progress(1.0).to_smolstr())
+                .await?;
+        }
+        let mut size_count = 0;
+        loop {
+            if transfer.is_cancelled() {
+                return local_write_half
+                    .write_line(LocalResponse::Cancelled.to_str_unchecked())
+                    .await;
+            }
+            let mut buf = [0_u8; consts::FILE_TRANS_BUF_SIZE];
+            // The file is not read at all if every host has it.
+            let read_size = if content_needed { f.read(&mut buf)? } else { 0 };
+            if read_size == 0 {
+                break;
+            }
+            size_count += read_size;
+            write_content_dests(&mut connected, &buf[..read_size]).await;
+            transfer.progress(read_size as u64);
+            local_write_half
+                .write_line(
+                    LocalResponse::Progress(size_count as f64 / file_size.max(1) as f64)
+                        .to_smolstr(),
+                )
+                .await?;
+        }
+        write_all_dests(&mut connected, consts::LINE_SEP.as_bytes()).await;
+        files_count += 1;
+        for c in &mut connected {
+            if c.writer.is_some() {
+                c.sent.push(name.clone());
+                continue;
+            }
+            let resp = LocalResponse::FileFailed(name.clone());
+            local_write_half
+                .write_line(LocalResponse::Host(c.name.clone(), Box::new(resp)).to_smolstr())
+                .await?;
+        }
+    }
+    write_all_dests(
+        &mut connected,
+        smol_str::format_smolstr!("{}{}", request_tag::send_flag::SEND_END, consts::LINE_SEP)
+            .as_bytes(),
+    )
+    .await;
+    local_write_half
+        .write_line(request_tag::send_flag::SEND_END)
+        .await?;
+    let results = join_all(
+        connected
+            .iter_mut()
+            .map(|c| read_batch_result(c, files_count)),
+    )
+    .await;
+    if connected.len() == dests.len()
+        && results
+            .iter()
+            .all(|resp| matches!(resp, LocalResponse::AllFilesSucceeded))
+    {
+        transfer.succeed();
+    }
+    for (c, resp) in connected.iter().zip(results) {
+        if matches!(
+            resp,
+            LocalResponse::AllFilesSucceeded | LocalResponse::FilesSucceeded(_)
+        ) {
+            handler::record_contact(c.addr.ip(), true).await;
+        }
+        // The host receives the files in order, the ones after its count did not arrive.
+        let received = match resp {
+            LocalResponse::AllFilesSucceeded => c.sent.len(),
+            LocalResponse::FilesSucceeded(count)
+            | LocalResponse::RemoteQuotaExceeded(count)
+            | LocalResponse::RemoteChecksumMismatch(count) => count.into(),
+            _ => 0,
+        };
+        for (i, name) in c.sent.iter().enumerate() {
+            let file_resp = if i < received {
+                LocalResponse::FileSent(name.clone())
+            } else {
+                LocalResponse::FileFailed(name.clone())
+            };
+            local_write_half
+                .write_line(LocalResponse::Host(c.name.clone(), Box::new(file_resp)).to_smolstr())
+                .await?;
+        }
+        local_write_half
+            .write_line(LocalResponse::Host(c.name.clone(), Box::new(resp)).to_smolstr())
+            .await?;
+    }
+    local_write_half
+        .write_line(LocalResponse::ListEnd.to_str_unchecked())
+        .await
+}
@@ -1,655 +1,1462 @@
-use std::{
-    fs::File,
-    io::{Read, Write},
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    path::PathBuf,
-};
-
-use smol_str::ToSmolStr;
-use tokio::{
-    io::{
-        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter,
-    },
-    net::{TcpListener, TcpStream},
-    sync::RwLock,
-};
-
-pub trait WriteLine {
-    async fn write_line<S: AsRef<str>>(&mut self, str: S) -> std::io::Result<()>;
-}
-
-impl<W> WriteLine for W
-where
-    W: AsyncWriteExt + ?Sized + Unpin,
-{
-    async fn write_line<S: AsRef<str>>(&mut self, str: S) -> std::io::Result<()> {
-        self.write_all(
-            smol_str::format_smolstr!("{}{}", str.as_ref(), consts::LINE_SEP).as_bytes(),
-        )
-        .await?;
-        self.flush().await
-    }
-}
-
-use crate::{
-    common::{LocalResponse, RemoteResponse, Response, StartLine},
-    config::Config,
-    consts, global, request_tag,
-};
-
-pub(crate) async fn handle_local<S>(mut local_stream: S) -> std::io::Result<()>
-where
-    S: AsyncWrite + AsyncRead + Unpin,
-    for<'a> &'a mut S: AsyncRead,
-{
-    // let mut local_write_half = stream.as_tokio_async_write();
-    let mut local_reader =
-        BufReader::new(&mut local_stream).take(consts::HOST_NAME_LENGTH_LIMIT as u64 + 50);
-    let mut line = String::new();
-    if local_reader.read_line(&mut line).await? != 0 {
-        if let Some((command, arg)) = line.trim().split_once(consts::STARTLINE_SEP) {
-            match command {
-                request_tag::local::SHARE => {
-                    if let Some(host) = global::config_store()
-                        .await
-                        .read()
-                        .await
-                        .get_addr_by_name(arg)
-                    {
-                        let mut recv_paths = Vec::with_capacity(consts::NUMBER_PATHS_PER_REQUEST);
-                        local_reader.set_limit(consts::FILE_PATH_LIMIT);
-                        line.clear();
-                        while recv_paths.len() < consts::NUMBER_PATHS_PER_REQUEST
-                            && local_reader.read_line(&mut line).await? != 0
-                            && !line.trim().is_empty()
-                        {
-                            let path = PathBuf::from(line.trim());
-                            if !path.is_file() {
-                                local_stream
-                                    .write_line(LocalResponse::AnyPathInvalid.to_str_unchecked())
-                                    .await?;
-                                return Ok(());
-                            }
-                            recv_paths.push(path);
-                            local_reader.set_limit(consts::FILE_PATH_LIMIT);
-                            line.clear();
-                        }
-                        if !recv_paths.is_empty() {
-                            handle_file_send(*host, local_stream, recv_paths).await?;
-                            return Ok(());
-                        }
-                    } else {
-                        local_stream
-                            .write_line(LocalResponse::UnregisteredHostname.to_str_unchecked())
-                            .await?;
-                        return Ok(());
-                    }
-                }
-                request_tag::local::REG => {
-                    if let Some((hostname, addr_str)) = arg.trim().split_once(consts::PAIR_SEP) {
-                        if !Config::check_hostname_valid(hostname) {
-                            local_stream
-                                .write_line(Response::InvalidHostname.to_str_unchecked())
-                                .await?;
-                            return Ok(());
-                        }
-                        if let Ok(addr) = <SocketAddr as std::str::FromStr>::from_str(addr_str) {
-                            if let Ok(option_ip) = try_register_to_local(hostname, addr).await {
-                                if let Some(replaced) = option_ip {
-                                    local_stream
-                                        .write_line(
-                                            LocalResponse::ReplacedAddress(replaced).to_smolstr(),
-                                        )
-                                        .await?;
-                                } else {
-                                    local_stream
-                                        .write_line(Response::RegisterSucceeded.to_str_unchecked())
-                                        .await?;
-                                }
-                            } else {
-                                local_stream
-                                    .write_line(
-                                        LocalResponse::LocalRegisterFailed.to_str_unchecked(),
-                                    )
-                                    .await?;
-                            }
-                            return Ok(());
-                        }
-                    }
-                }
-                _ => (),
-            }
-        }
-    }
-    local_stream
-        .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
-        .await?;
-    Ok(())
-}
-
-async fn try_register_to_local(
-    hostname: &str,
-    host_addr: SocketAddr,
-) -> anyhow::Result<Option<SocketAddr>> {
-    let conf_store_lock = global::config_store().await;
-    let mut config_store = conf_store_lock.write().await;
-    let replaced = config_store.register_host(hostname, host_addr);
-    config_store.update_to_file()?;
-    Ok(replaced)
-}
-
-fn checked_expected_port(port: u16) -> u16 {
-    if port.wrapping_add(1) == 0 {
-        port.wrapping_add(11)
-    } else {
-        port + 1
-    }
-}
-
-async fn handle_file_send<S>(
-    remote_addr: SocketAddr,
-    mut local_write_half: S,
-    files_paths: Vec<PathBuf>,
-) -> std::io::Result<()>
-where
-    S: AsyncWrite + Unpin,
-{
-    if let Ok(remote_stream) = TcpStream::connect(remote_addr).await {
-        let (remote_read_half, mut remote_write_half) = remote_stream.into_split();
-        remote_write_half
-            .write_line(smol_str::format_smolstr!(
-                "{} {}",
-                request_tag::remote::PORT,
-                checked_expected_port(remote_addr.port())
-            ))
-            .await?;
-        let mut line = String::with_capacity(50);
-        let mut remote_reader = BufReader::with_capacity(128, remote_read_half).take(50);
-        remote_reader.read_line(&mut line).await?;
-        match line.parse::<RemoteResponse>() {
-            Ok(RemoteResponse::UnregisteredHost) => {
-                local_write_half
-                    .write_line(LocalResponse::RemoteUnregistered.to_str_unchecked())
-                    .await?
-            }
-            Ok(RemoteResponse::NoAvailablePort) => {
-                local_write_half
-                    .write_line(LocalResponse::RemoteNoAvailablePort.to_str_unchecked())
-                    .await?
-            }
-            Ok(RemoteResponse::PortConfirm(port)) => {
-                send_files(
-                    local_write_half,
-                    SocketAddr::from((remote_addr.ip(), port)),
-                    files_paths,
-                )
-                .await?
-            }
-            _ => {
-                local_write_half
-                    .write_line(LocalResponse::UnexpectedRemoteResponse.to_str_unchecked())
-                    .await?;
-                remote_write_half
-                    .write_line(Response::UnexpectedResponse.to_str_unchecked())
-                    .await?;
-            }
-        }
-        return Ok(());
-    } else {
-        local_write_half
-            .write_line(LocalResponse::UnreachableAddress(remote_addr).to_smolstr())
-            .await?;
-    }
-
-    Ok(())
-}
-
-
-pub(crate) async fn handle_remote<S>(
-    mut remote_stream: S,
-    peer_addr: SocketAddr,
-) -> anyhow::Result<()>
-where
-    S: AsyncWrite + AsyncRead + Unpin,
-    for<'a> &'a mut S: AsyncRead,
-{
-    let first_line_length_limit = (request_tag::remote::PORT.len()
-        + std::mem::size_of::<u16>()
-        + consts::LINE_SEP.len()) as u64;
-    let mut remote_reader = BufReader::new(&mut remote_stream).take(first_line_length_limit);
-    let mut line = String::new();
-    if let Ok(size) = remote_reader.read_line(&mut line).await {
-        if size != 0 {
-            if let Some((req_tag, arg)) = line.trim().split_once(consts::STARTLINE_SEP) {
-                if req_tag == request_tag::remote::PORT {
-                    if global::config_store()
-                        .await
-                        .read()
-                        .await
-                        .check_addr_registered(peer_addr)
-                    {
-                        if let Ok(expected_port) = arg.parse::<u16>() {
-                            if let Some(l) = create_receive_listener(expected_port).await {
-                                let actual_port = l.local_addr()?.port();
-                                tokio::spawn(async move {
-                                    if let Err(e) = receive_files(l, peer_addr.ip()).await {
-                                        log::error!(
-                                            "Error occurred in `receive_files`, error detail: {}",
-                                            e
-                                        );
-                                    }
-                                });
-                                remote_stream
-                                    .write_line(
-                                        RemoteResponse::PortConfirm(actual_port).to_smolstr(),
-                                    )
-                                    .await?;
-                            } else {
-                                remote_stream
-                                    .write_line(RemoteResponse::NoAvailablePort.to_str_unchecked())
-                                    .await?;
-                            }
-                        } else {
-                            remote_stream
-                                .write_line(RemoteResponse::InvalidPort.to_str_unchecked())
-                                .await?;
-                        }
-                    } else {
-                        remote_stream
-                            .write_line(RemoteResponse::UnregisteredHost.to_str_unchecked())
-                            .await?;
-                    }
-                    return Ok(());
-                }
-            }
-        }
-    }
-    if let Err(e) = remote_stream
-        .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
-        .await
-    {
-        log::error!("A remote connection maybe closed! Detail: {}", e);
-    }
-    Ok(())
-}
-
-async fn send_files<S>(
-    mut local_write_half: S,
-    dest_addr: SocketAddr,
-    files_paths: Vec<PathBuf>,
-) -> std::io::Result<()>
-where
-    S: AsyncWrite + Unpin,
-{
-    let dest_stream = TcpStream::connect(dest_addr).await?;
-    let (remote_read_half, remote_write_half) = dest_stream.into_split();
-    let mut dest_writer = BufWriter::new(remote_write_half);
-    let start_flag_with_line =
-        smol_str::format_smolstr!("{}{}", request_tag::send_flag::SEND_START, consts::LINE_SEP);
-    dest_writer.write_line(&start_flag_with_line).await?;
-    local_write_half.write_line(&start_flag_with_line).await?;
-    for p in &files_paths {
-        let name_cow = p.file_name().unwrap().to_string_lossy();
-        let name = if name_cow.len() >= consts::FILE_NAME_LENGTH_LIMIT {
-            unsafe {
-                name_cow
-                    .get_unchecked(0..consts::FILE_NAME_LENGTH_LIMIT)
-                    .to_smolstr()
-            }
-        } else {
-            name_cow.to_smolstr()
-        };
-        let mut f = File::open(&p)?;
-        let file_size = if let Some(size) = f.metadata().ok().map(|m| m.len()) {
-            if size > consts::FILE_SIZE_LIMIT {
-                log::warn!(
-                    "The size of file exceeds limit(10GB), file: \"{}\"",
-                    p.to_string_lossy()
-                );
-                continue;
-            }
-            Some(size)
-        } else {
-            None
-        };
-        dest_writer
-            .write_line(LocalResponse::FileInfo(name, file_size).to_smolstr())
-            .await?;
-        let mut size_count = 0;
-        loop {
-            let mut buf = [0_u8; consts::FILE_TRANS_BUF_SIZE];
-            let read_size = f.read(&mut buf)?;
-            if read_size == 0 {
-                break;
-            }
-            size_count += read_size;
-            dest_writer
-                .write_all(unsafe { buf.get_unchecked(0..read_size) })
-                .await?;
-            dest_writer.flush().await?;
-            local_write_half
-                .write_line(
-                    LocalResponse::Progress(
-                        file_size
-                            .map(|size| size_count as f64 / size as f64)
-                            .unwrap_or(-1.0),
-                    )
-                    .to_smolstr(),
-                )
-                .await?;
-        }
-        dest_writer.write_all(consts::LINE_SEP.as_bytes()).await?;
-    }
-    local_write_half
-        .write_line(request_tag::send_flag::SEND_END)
-        .await?;
-    dest_writer
-        .write_line(request_tag::send_flag::SEND_END)
-        .await?;
-    let mut dest_reader = BufReader::new(remote_read_half).take(StartLine::LENGTH_LIMIT);
-    let mut line = String::new();
-    if dest_reader.read_line(&mut line).await? != 0 {
-        let mut resp_parts = line.split(consts::STARTLINE_SEP);
-        match resp_parts.next().unwrap().parse::<RemoteResponse>() {
-            Ok(RemoteResponse::FilesReceived(recv_count)) if resp_parts.clone().count() == 2 => {
-                if recv_count == files_paths.len() as u8 {
-                    local_write_half
-                        .write_line(LocalResponse::AllFilesSucceeded.to_str_unchecked())
-                        .await?;
-                    return Ok(());
-                } else {
-                    local_write_half
-                        .write_line(LocalResponse::FilesSucceeded(recv_count).to_smolstr())
-                        .await?;
-                    return Ok(());
-                }
-            }
-            _ => (),
-        }
-    }
-    local_write_half
-        .write_line(LocalResponse::UnexpectedSendResp.to_str_unchecked())
-        .await?;
-    dest_writer
-        .write_line(LocalResponse::UnexpectedSendResp.to_str_unchecked())
-        .await?;
-    Ok(())
-}
-
-async fn receive_files(listener: TcpListener, send_host_ip: IpAddr) -> std::io::Result<()> {
-    loop {
-        let (mut stream, peer_addr) = listener.accept().await?;
-        if peer_addr.ip() == send_host_ip {
-            let (read_half, mut write_half) = stream.into_split();
-            let mut reader =
-                BufReader::new(read_half).take(request_tag::send_flag::SEND_START.len() as u64 + 2);
-            let mut line = String::new();
-            if reader.read_line(&mut line).await? != 0
-                && line.trim() == request_tag::send_flag::SEND_START
-            {
-                let mut files_count: u8 = 0;
-                line.clear();
-                reader.set_limit(4);
-                let mut recv_dir = global::config_store()
-                    .await
-                    .read()
-                    .await
-                    .receive_dir()
-                    .to_path_buf();
-                while reader.read_line(&mut line).await? != 0 && line.trim().is_empty() {
-                    reader.set_limit(consts::FILE_NAME_LENGTH_LIMIT as u64 + 40);
-                    line.clear();
-                    if reader.read_line(&mut line).await? == 0 {
-                        break;
-                    }
-                    let trimmed_line = line.trim();
-                    if trimmed_line == request_tag::send_flag::SEND_END {
-                        if files_count == 0 {
-                            break;
-                        }
-                        write_half
-                            .write_line(RemoteResponse::FilesReceived(files_count).to_smolstr())
-                            .await?;
-                        return Ok(());
-                    }
-                    let mut pair = trimmed_line.split(consts::PAIR_SEP);
-                    if pair.clone().count() != 2 {
-                        break;
-                    }
-
-                    let name = pair.next().unwrap();
-                    let file_size_res = pair.next().unwrap().parse::<usize>();
-                    if file_size_res.is_err() {
-                        break;
-                    }
-                    reader.set_limit(file_size_res.unwrap() as u64);
-                    recv_dir.push(name);
-                    let f = RwLock::new(File::create(&recv_dir)?);
-                    let mut file_writer = f.write().await;
-                    loop {
-                        let mut buf = [0; consts::FILE_TRANS_BUF_SIZE];
-                        let read_size = reader.read(&mut buf).await?;
-                        if read_size == 0 {
-                            break;
-                        }
-                        file_writer.write_all(unsafe { buf.get_unchecked(0..read_size) })?;
-                    }
-                    file_writer.flush()?;
-                    files_count += 1;
-                    line.clear();
-                }
-                if files_count > 0 {
-                    write_half
-                        .write_line(RemoteResponse::UnexpectedEndFlag(files_count).to_smolstr())
-                        .await?;
-                    return Ok(());
-                }
-            }
-            write_half
-                .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
-                .await?;
-            return Ok(());
-        }
-        stream
-            .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
-            .await?;
-        tokio::task::yield_now().await;
-    }
-}
-
-async fn create_receive_listener(port: u16) -> Option<tokio::net::TcpListener> {
-    for p in port..u16::MAX {
-        if let Ok(l) = tokio::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, p)).await {
-            return Some(l);
-        }
-    }
-    for p in 3000..port {
-        if let Ok(l) = tokio::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, p)).await {
-            return Some(l);
-        }
-    }
-    None
-}
-
-#[cfg(test)]
-mod handler_tests {
-    use std::{cell::RefCell, sync::Arc, task::Poll};
-
-    use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader};
-
-    use super::WriteLine;
-
-    #[derive(Debug)]
-    struct MockStream {
-        data_s2c: Vec<u8>,
-        data_c2s: Vec<u8>,
-    }
-
-    impl MockStream {
-        pub fn new() -> Self {
-            Self {
-                data_c2s: vec![],
-                data_s2c: vec![],
-            }
-        }
-
-        pub fn into_split(self) -> (ClientStream, ServerStream) {
-            println!("split inner");
-            let arc = Arc::new(RefCell::new(self));
-            (
-                ClientStream {
-                    inner_stream: arc.clone(),
-                },
-                ServerStream { inner_stream: arc },
-            )
-        }
-    }
-
-    #[derive(Debug, Clone)]
-    struct ClientStream {
-        inner_stream: Arc<RefCell<MockStream>>,
-    }
-
-    #[derive(Debug, Clone)]
-    struct ServerStream {
-        inner_stream: Arc<RefCell<MockStream>>,
-    }
-
-    impl Unpin for MockStream {}
-
-    impl AsyncWrite for ClientStream {
-        fn poll_write(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-            buf: &[u8],
-        ) -> std::task::Poll<Result<usize, std::io::Error>> {
-            let mut inner_stream = self.inner_stream.as_ref().borrow_mut();
-            inner_stream.data_c2s = Vec::from(buf);
-            Poll::Ready(Ok(buf.len()))
-        }
-
-        fn poll_flush(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-        ) -> std::task::Poll<Result<(), std::io::Error>> {
-            Poll::Ready(Ok(()))
-        }
-
-        fn poll_shutdown(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-        ) -> std::task::Poll<Result<(), std::io::Error>> {
-            Poll::Ready(Ok(()))
-        }
-    }
-
-    impl AsyncRead for ClientStream {
-        fn poll_read(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-            buf: &mut tokio::io::ReadBuf<'_>,
-        ) -> std::task::Poll<std::io::Result<()>> {
-            let mut inner_stream = self.inner_stream.as_ref().borrow_mut();
-            if inner_stream.data_s2c.len() > 0 {
-                let size = usize::min(inner_stream.data_s2c.len(), buf.remaining());
-                buf.put_slice(&inner_stream.data_s2c[..size]);
-                inner_stream.data_s2c.drain(..size);
-            }
-            Poll::Ready(Ok(()))
-        }
-    }
-
-    impl AsyncWrite for ServerStream {
-        fn poll_write(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-            buf: &[u8],
-        ) -> Poll<Result<usize, std::io::Error>> {
-            let mut inner_stream = self.inner_stream.as_ref().borrow_mut();
-            inner_stream.data_s2c = Vec::from(buf);
-            Poll::Ready(Ok(buf.len()))
-        }
-
-        fn poll_flush(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-        ) -> Poll<Result<(), std::io::Error>> {
-            Poll::Ready(Ok(()))
-        }
-
-        fn poll_shutdown(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-        ) -> Poll<Result<(), std::io::Error>> {
-            Poll::Ready(Ok(()))
-        }
-    }
-
-    impl AsyncRead for ServerStream {
-        fn poll_read(
-            self: std::pin::Pin<&mut Self>,
-            _cx: &mut std::task::Context<'_>,
-            buf: &mut tokio::io::ReadBuf<'_>,
-        ) -> Poll<std::io::Result<()>> {
-            let mut inner_stream = self.inner_stream.as_ref().borrow_mut();
-            if inner_stream.data_c2s.len() > 0 {
-                let size = usize::min(inner_stream.data_c2s.len(), buf.remaining());
-                buf.put_slice(&inner_stream.data_c2s[..size]);
-                inner_stream.data_c2s.drain(..size);
-            }
-            Poll::Ready(Ok(()))
-        }
-    }
-
-    const START_LINE: &str = "GET / HTTP/1.1";
-    const HELLO_RESPONSE: &str = "<html><head></head><body><h1>Hello</h1></body></html>";
-
-    async fn simple_handle_connection(mut server_stream: ServerStream) -> std::io::Result<()> {
-        println!("into simple_handle_connection");
-        let mut line = String::new();
-        let mut reader = BufReader::new(&mut server_stream).take(20);
-        let read_size = reader.read_line(&mut line).await?;
-        println!("read a line finished, line = {}", line.trim());
-        if read_size != 0 && line.trim() == START_LINE.trim() {
-            println!("read a line and its equals START_LINE: \"{}\"", START_LINE);
-            println!("write content =  {}", HELLO_RESPONSE);
-            server_stream.write_line(HELLO_RESPONSE).await?;
-            println!("write response finished!");
-            println!(
-                "inner stream data_s2c = {}",
-                String::from_utf8_lossy(
-                    server_stream
-                        .inner_stream
-                        .as_ref()
-                        .borrow()
-                        .data_s2c
-                        .as_slice()
-                )
-            );
-        }
-        Ok(())
-    }
-
-    #[tokio::test(flavor = "current_thread")]
-    async fn simple_handle_test() {
-        println!("before initial");
-        let stream = MockStream::new();
-        println!("stream initial finished");
-        let (mut c, s) = stream.into_split();
-        println!("split stream succeeded...");
-        let mut res = c.write_line(START_LINE).await;
-        assert!(res.is_ok());
-        println!(
-            "client write startline finished, inner data = {}",
-            String::from_utf8_lossy(c.inner_stream.as_ref().borrow().data_c2s.as_slice())
-        );
-        res = simple_handle_connection(s).await;
-        assert!(res.is_ok());
-        let mut client_reader = BufReader::new(c).take(128);
-        let mut resp = String::new();
-        let read_res = client_reader.read_to_string(&mut resp).await;
-        println!("read result = {}", &resp);
-        assert!(read_res.is_ok());
-        assert_eq!(resp.trim(), HELLO_RESPONSE);
-    }
-}
+use std::{
+    fs::File,
+    io::{Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use fshare_proto::FileName;
+use sha2::{Digest, Sha256};
+use smol_str::{SmolStr, ToSmolStr};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader, BufWriter, Take,
+    },
+    net::{TcpListener, TcpStream},
+    sync::RwLock,
+};
+
+pub trait WriteLine {
+    async fn write_line<S: AsRef<str>>(&mut self, str: S) -> std::io::Result<()>;
+}
+
+impl<W> WriteLine for W
+where
+    W: AsyncWriteExt + ?Sized + Unpin,
+{
+    async fn write_line<S: AsRef<str>>(&mut self, str: S) -> std::io::Result<()> {
+        self.write_all(
+            smol_str::format_smolstr!("{}{}", str.as_ref(), consts::LINE_SEP).as_bytes(),
+        )
+        .await?;
+        self.flush().await
+    }
+}
+
+use crate::{
+    common::{LocalResponse, RemoteResponse, Response, StartLine},
+    config::{Config, HostAddr, HostPolicy},
+    consts, delta, discovery,
+    error::{self, Error},
+    events::{self, Event},
+    fanout::{self, Destination},
+    global,
+    history::{self, HistoryEntry},
+    hook::{self, ReceivedFile},
+    hosts, pairing,
+    policy::{self, Throttle},
+    registration, relay, request_tag, resolve, rpc, status, store, sync,
+    transfer::{self, Direction, Transfer},
+};
+
+/// Runs the rest of a request outside of the workers. A subscription lasts as long as the local
+/// process wants, a registration request or a pairing code waits for a user, any of them would
+/// hold a worker all that time.
+fn spawn_outside_workers<F, E>(what: &'static str, task: F)
+where
+    F: std::future::Future<Output = Result<(), E>> + Send + 'static,
+    E: std::fmt::Display,
+{
+    tokio::spawn(async move {
+        if let Err(e) = task.await {
+            log::error!("Error occurred while {}: {}", what, e);
+        }
+    });
+}
+
+/// Long enough for a `SHARE` request to several hosts.
+const LOCAL_FIRST_LINE_LIMIT: u64 = 512;
+/// `HASH` with a hex encoded SHA-256.
+const HASH_LINE_LIMIT: u64 = 72;
+
+/// The optional parts of the send protocol. A receiver lists the ones it supports in a `CAPS` line
+/// after `PORT_CONFIRM`, the sender the ones it uses after `SEND_START`. A peer which lists
+/// nothing gets the plain protocol.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Capabilities {
+    /// A `HASH` line follows every file header and is answered with `HAVE` or `SEND`.
+    pub(crate) hash: bool,
+    /// `HASH` may be answered with `DELTA` as well.
+    pub(crate) delta: bool,
+}
+
+impl Capabilities {
+    pub(crate) const ALL: Self = Self {
+        hash: true,
+        delta: true,
+    };
+
+    /// Takes the arguments of a `CAPS` or `SEND_START` line, unknown ones are skipped.
+    fn from_args<'a>(args: impl Iterator<Item = &'a str>) -> Self {
+        let mut caps = Self::default();
+        for arg in args {
+            match arg {
+                request_tag::send_flag::HASH => caps.hash = true,
+                request_tag::delta::DELTA => caps.delta = true,
+                _ => (),
+            }
+        }
+        // A delta is an answer to `HASH`.
+        caps.delta &= caps.hash;
+        caps
+    }
+
+    pub(crate) fn parse_line(line: &str) -> Option<Self> {
+        let mut args = line.split_whitespace();
+        (args.next() == Some(request_tag::send_flag::CAPS)).then(|| Self::from_args(args))
+    }
+
+    fn args(self) -> SmolStr {
+        let mut args = String::new();
+        if self.hash {
+            args.push(consts::STARTLINE_SEP);
+            args.push_str(request_tag::send_flag::HASH);
+        }
+        if self.delta {
+            args.push(consts::STARTLINE_SEP);
+            args.push_str(request_tag::delta::DELTA);
+        }
+        args.into()
+    }
+
+    pub(crate) fn to_line(self) -> SmolStr {
+        smol_str::format_smolstr!("{}{}", request_tag::send_flag::CAPS, self.args())
+    }
+
+    /// The start line of a batch which uses these.
+    pub(crate) fn start_line(self) -> SmolStr {
+        smol_str::format_smolstr!("{}{}", request_tag::send_flag::SEND_START, self.args())
+    }
+}
+
+/// Reads the `CAPS` line which follows `PORT_CONFIRM`. A receiver which closes the connection
+/// instead only knows the plain protocol.
+pub(crate) async fn read_capabilities<R>(reader: &mut Take<R>) -> Capabilities
+where
+    R: AsyncBufRead + Unpin,
+{
+    reader.set_limit(StartLine::LENGTH_LIMIT);
+    let mut line = String::new();
+    match tokio::time::timeout(consts::HOST_CHECK_TIMEOUT, reader.read_line(&mut line)).await {
+        Ok(Ok(_)) => Capabilities::parse_line(&line).unwrap_or_default(),
+        _ => Capabilities::default(),
+    }
+}
+
+/// Reads the answer to the `HASH` line of a file of `file_size` bytes into `line`, which is left
+/// empty if none came in time.
+pub(crate) async fn read_hash_reply<R>(
+    reader: &mut Take<R>,
+    line: &mut String,
+    file_size: u64,
+) -> std::io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+{
+    line.clear();
+    reader.set_limit(StartLine::LENGTH_LIMIT);
+    let timeout = consts::hash_reply_timeout(file_size);
+    match tokio::time::timeout(timeout, reader.read_line(line)).await {
+        Ok(res) => res.map(|_| ()),
+        Err(_) => {
+            log::warn!("The receiver did not answer a `HASH` line in time");
+            Ok(())
+        }
+    }
+}
+
+pub(crate) async fn handle_local<S>(mut local_stream: S) -> std::io::Result<()>
+where
+    S: AsyncWrite + AsyncRead + Unpin + Send + 'static,
+    for<'a> &'a mut S: AsyncRead,
+{
+    // let mut local_write_half = stream.as_tokio_async_write();
+    let mut local_reader = BufReader::new(&mut local_stream).take(LOCAL_FIRST_LINE_LIMIT);
+    let mut line = String::new();
+    if local_reader.read_line(&mut line).await? != 0 {
+        if line.trim_start().starts_with('{') {
+            if let Some(subscription) = rpc::serve(local_reader.into_inner(), line).await? {
+                spawn_outside_workers("streaming events", async move {
+                    subscription.stream(&mut local_stream).await
+                });
+            }
+            return Ok(());
+        }
+        let trimmed_line = line.trim();
+        let (command, arg) = trimmed_line
+            .split_once(consts::STARTLINE_SEP)
+            .unwrap_or((trimmed_line, ""));
+        match command {
+            request_tag::local::SHARE => {
+                let resolved = {
+                    let config = global::config_store().await.read().await;
+                    fanout::resolve_destinations(&config, arg)
+                };
+                match resolved {
+                    Ok((fan_out, dests)) => {
+                        let mut recv_paths = Vec::with_capacity(consts::NUMBER_PATHS_PER_REQUEST);
+                        local_reader.set_limit(consts::FILE_PATH_LIMIT);
+                        line.clear();
+                        while recv_paths.len() < consts::NUMBER_PATHS_PER_REQUEST
+                            && local_reader.read_line(&mut line).await? != 0
+                            && !line.trim().is_empty()
+                        {
+                            let path = PathBuf::from(line.trim());
+                            if !path.is_file() {
+                                local_stream
+                                    .write_line(LocalResponse::AnyPathInvalid.to_str_unchecked())
+                                    .await?;
+                                return Ok(());
+                            }
+                            recv_paths.push(path);
+                            local_reader.set_limit(consts::FILE_PATH_LIMIT);
+                            line.clear();
+                        }
+                        if !recv_paths.is_empty() {
+                            if fan_out {
+                                fanout::send_files(local_stream, dests, recv_paths).await?;
+                            } else {
+                                handle_file_send(&dests[0], local_stream, recv_paths).await?;
+                            }
+                            return Ok(());
+                        }
+                    }
+                    Err(resp) => {
+                        local_stream.write_line(resp).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            request_tag::local::SYNC => {
+                let (host, delete) = match arg.split_once(consts::STARTLINE_SEP) {
+                    Some((host, request_tag::local::SYNC_DELETE)) => (host, true),
+                    Some(_) => ("", false),
+                    None => (arg, false),
+                };
+                let host = SmolStr::from(host);
+                local_reader.set_limit(consts::FILE_PATH_LIMIT);
+                line.clear();
+                if !host.is_empty() && local_reader.read_line(&mut line).await? != 0 {
+                    let dir = PathBuf::from(line.trim());
+                    if !dir.is_dir() {
+                        return local_stream
+                            .write_line(LocalResponse::AnyPathInvalid.to_str_unchecked())
+                            .await;
+                    }
+                    return sync::sync_to_host(local_stream, &host, dir, delete).await;
+                }
+            }
+            request_tag::local::REG | request_tag::local::REG_REMOTE => {
+                if let Some((hostname, addr_str)) = arg.trim().split_once(consts::PAIR_SEP) {
+                    if let Ok(addr) = addr_str.parse::<HostAddr>() {
+                        if command == request_tag::local::REG {
+                            register_to_local(&mut local_stream, hostname, addr).await?;
+                            return Ok(());
+                        }
+                        let hostname = SmolStr::from(hostname);
+                        spawn_outside_workers("requesting a registration", async move {
+                            registration::request_registration(&mut local_stream, &hostname, addr)
+                                .await
+                        });
+                        return Ok(());
+                    }
+                }
+            }
+            request_tag::local::REG_PENDING => {
+                for (name, addr) in registration::pending_requests().await {
+                    local_stream
+                        .write_line(LocalResponse::RegRequest(name, addr).to_smolstr())
+                        .await?;
+                }
+                local_stream
+                    .write_line(LocalResponse::ListEnd.to_str_unchecked())
+                    .await?;
+                return Ok(());
+            }
+            request_tag::local::STATUS => {
+                return status::write_status(&mut local_stream).await;
+            }
+            request_tag::local::SUBSCRIBE => {
+                spawn_outside_workers("streaming events", async move {
+                    events::stream_events(&mut local_stream).await
+                });
+                return Ok(());
+            }
+            request_tag::local::CANCEL => {
+                if let Ok(id) = arg.parse::<u64>() {
+                    let resp = if transfer::cancel(id) {
+                        LocalResponse::Cancelled
+                    } else {
+                        LocalResponse::UnknownTransfer
+                    };
+                    return local_stream.write_line(resp.to_str_unchecked()).await;
+                }
+            }
+            request_tag::local::HOSTS => {
+                return hosts::list_hosts(&mut local_stream).await;
+            }
+            request_tag::local::UNREG
+            | request_tag::local::RENAME
+            | request_tag::local::SET_ADDR => {
+                return hosts::update_host(&mut local_stream, command, arg).await;
+            }
+            request_tag::local::REG_APPROVE | request_tag::local::REG_REJECT => {
+                let approve = command == request_tag::local::REG_APPROVE;
+                return registration::decide(&mut local_stream, arg, approve).await;
+            }
+            request_tag::local::PAIR_OFFER => {
+                spawn_outside_workers("offering a pairing code", async move {
+                    offer_pairing(&mut local_stream).await
+                });
+                return Ok(());
+            }
+            request_tag::local::PAIR => {
+                if let Some((code, addr_str)) = arg.split_once(consts::STARTLINE_SEP) {
+                    if let Ok(addr) = addr_str.parse::<SocketAddr>() {
+                        let resp = match pairing::pair_with(addr, code).await {
+                            Ok(Ok(paired)) => LocalResponse::Paired(paired.name, paired.addr),
+                            Ok(Err(resp)) => resp,
+                            Err(Error::Io(e)) => {
+                                log::error!("Pairing with {} failed! Detail: {}", addr, e);
+                                LocalResponse::UnreachableAddress(addr.into())
+                            }
+                            Err(e) => {
+                                log::error!("Pairing with {} failed! Detail: {}", addr, e);
+                                LocalResponse::PairFailed
+                            }
+                        };
+                        local_stream.write_line(resp.to_smolstr()).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            request_tag::local::PEERS => {
+                let table = discovery::peer_table();
+                for peer in table.read().await.peers() {
+                    local_stream
+                        .write_line(
+                            LocalResponse::Peer(
+                                peer.name.clone(),
+                                peer.addr,
+                                peer.fingerprint.clone(),
+                            )
+                            .to_smolstr(),
+                        )
+                        .await?;
+                }
+                local_stream
+                    .write_line(LocalResponse::ListEnd.to_str_unchecked())
+                    .await?;
+                return Ok(());
+            }
+            request_tag::local::REG_PEER => {
+                let peer = discovery::peer_table()
+                    .read()
+                    .await
+                    .get_by_name(arg)
+                    .map(|peer| (peer.name.clone(), peer.addr));
+                if let Some((name, addr)) = peer {
+                    register_to_local(&mut local_stream, &name, addr.into()).await?;
+                    return Ok(());
+                }
+                local_stream
+                    .write_line(LocalResponse::UnknownPeer.to_str_unchecked())
+                    .await?;
+                return Ok(());
+            }
+            _ => (),
+        }
+    }
+    local_stream
+        .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
+        .await?;
+    Ok(())
+}
+
+async fn offer_pairing<S>(local_stream: &mut S) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let (code, done) = match pairing::create_offer().await {
+        Ok(offer) => offer,
+        Err(e) => {
+            log::error!("Create pairing code failed! Detail: {}", e);
+            return local_stream
+                .write_line(LocalResponse::PairFailed.to_str_unchecked())
+                .await;
+        }
+    };
+    local_stream
+        .write_line(LocalResponse::PairCode(code.clone()).to_smolstr())
+        .await?;
+    let resp = match tokio::time::timeout(pairing::PAIR_TIMEOUT, done).await {
+        Ok(Ok(Ok(paired))) => LocalResponse::Paired(paired.name, paired.addr),
+        Ok(Ok(Err(resp))) => resp,
+        Ok(Err(_)) => LocalResponse::PairFailed,
+        Err(_) => {
+            pairing::cancel_offer(&code).await;
+            LocalResponse::PairFailed
+        }
+    };
+    local_stream.write_line(resp.to_smolstr()).await
+}
+
+/// Registers the host and writes the outcome to the local process, returns whether it succeeded.
+pub(crate) async fn register_to_local<S>(
+    local_stream: &mut S,
+    hostname: &str,
+    addr: HostAddr,
+) -> std::io::Result<bool>
+where
+    S: AsyncWrite + Unpin,
+{
+    if !Config::check_hostname_valid(hostname) {
+        local_stream
+            .write_line(Response::InvalidHostname.to_str_unchecked())
+            .await?;
+        return Ok(false);
+    }
+    let resp = match try_register_to_local(hostname, addr).await {
+        Ok(Some(replaced)) => LocalResponse::ReplacedAddress(replaced).to_smolstr(),
+        Ok(None) => Response::RegisterSucceeded.to_smolstr(),
+        Err(e) => {
+            log::error!("Register host `{}` failed! Detail: {}", hostname, e);
+            local_stream
+                .write_line(LocalResponse::LocalRegisterFailed.to_str_unchecked())
+                .await?;
+            return Ok(false);
+        }
+    };
+    local_stream.write_line(resp).await?;
+    Ok(true)
+}
+
+async fn try_register_to_local(
+    hostname: &str,
+    host_addr: HostAddr,
+) -> error::Result<Option<HostAddr>> {
+    let conf_store_lock = global::config_store().await;
+    let mut config_store = conf_store_lock.write().await;
+    let before = Config::clone(&config_store);
+    let replaced = config_store.register_host(hostname, host_addr.clone());
+    if let Err(e) = config_store.update_to_file() {
+        // Keeps memory in line with the file, the host is reported as not registered.
+        config_store.set_config(before);
+        return Err(e);
+    }
+    events::emit(Event::HostRegistered {
+        name: hostname.into(),
+        addr: host_addr,
+    });
+    Ok(replaced)
+}
+
+fn checked_expected_port(port: u16) -> u16 {
+    if port.wrapping_add(1) == 0 {
+        port.wrapping_add(11)
+    } else {
+        port + 1
+    }
+}
+
+/// Sends the files to `dest`, or through it to its relay target.
+pub(crate) async fn handle_file_send<S>(
+    dest: &Destination,
+    mut local_write_half: S,
+    files_paths: Vec<PathBuf>,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let transfer = Transfer::queue(Direction::Send, &dest.name);
+    local_write_half
+        .write_line(LocalResponse::TransferId(transfer.id()).to_smolstr())
+        .await?;
+    match request_receive_addr(&dest.addrs, dest.relay_target.as_deref()).await? {
+        Ok((dest_addr, caps)) => {
+            send_files(local_write_half, dest_addr, caps, files_paths, &transfer).await?;
+            record_contact(dest_addr.ip(), true).await;
+            Ok(())
+        }
+        Err(resp) => local_write_half.write_line(resp.to_smolstr()).await,
+    }
+}
+
+/// Asks the daemon at the first reachable of `remote_addrs` for a receive port, or the relay there
+/// for one of `relay_target`, along with what the receiver supports. The error is the response
+/// for the local process.
+pub(crate) async fn request_receive_addr(
+    remote_addrs: &[HostAddr],
+    relay_target: Option<&str>,
+) -> std::io::Result<Result<(SocketAddr, Capabilities), LocalResponse>> {
+    let Ok(remote_stream) = resolve::connect(remote_addrs).await else {
+        return Ok(Err(LocalResponse::UnreachableAddress(
+            remote_addrs[0].clone(),
+        )));
+    };
+    let remote_addr = remote_stream.peer_addr()?;
+    let (remote_read_half, mut remote_write_half) = remote_stream.into_split();
+    let expected_port = checked_expected_port(remote_addr.port());
+    let port_request = match relay_target {
+        Some(target) => smol_str::format_smolstr!(
+            "{} {} {}",
+            request_tag::remote::RELAY,
+            target,
+            expected_port
+        ),
+        None => smol_str::format_smolstr!("{} {}", request_tag::remote::PORT, expected_port),
+    };
+    remote_write_half.write_line(port_request).await?;
+    let mut line = String::with_capacity(50);
+    let mut remote_reader = BufReader::with_capacity(128, remote_read_half).take(50);
+    remote_reader.read_line(&mut line).await?;
+    Ok(match line.parse::<RemoteResponse>() {
+        Ok(RemoteResponse::UnregisteredHost) => Err(LocalResponse::RemoteUnregistered),
+        Ok(RemoteResponse::NoAvailablePort) => Err(LocalResponse::RemoteNoAvailablePort),
+        Ok(RemoteResponse::UnknownTarget) => Err(LocalResponse::RelayUnknownTarget),
+        Ok(RemoteResponse::TargetUnreachable) => Err(LocalResponse::RelayTargetUnreachable),
+        Ok(RemoteResponse::NotAccepted) => Err(LocalResponse::RemoteNotAccepted),
+        Ok(RemoteResponse::PortConfirm(port)) => Ok((
+            peer_addr_at(remote_addr, port),
+            read_capabilities(&mut remote_reader).await,
+        )),
+        _ => {
+            remote_write_half
+                .write_line(Response::UnexpectedResponse.to_str_unchecked())
+                .await?;
+            Err(LocalResponse::UnexpectedRemoteResponse)
+        }
+    })
+}
+
+/// Maps the final line of a receiver to the result of a batch of `files_count` files.
+pub(crate) fn batch_result(line: &str, files_count: u8) -> LocalResponse {
+    match line.parse::<RemoteResponse>() {
+        Ok(RemoteResponse::FilesReceived(recv_count)) if recv_count == files_count => {
+            LocalResponse::AllFilesSucceeded
+        }
+        Ok(RemoteResponse::FilesReceived(recv_count)) => LocalResponse::FilesSucceeded(recv_count),
+        Ok(RemoteResponse::QuotaExceeded(recv_count)) => {
+            LocalResponse::RemoteQuotaExceeded(recv_count)
+        }
+        Ok(RemoteResponse::ChecksumMismatch(recv_count)) => {
+            LocalResponse::RemoteChecksumMismatch(recv_count)
+        }
+        _ => LocalResponse::UnexpectedSendResp,
+    }
+}
+
+/// The name a file is announced with, truncated to the length limit.
+pub(crate) fn announced_file_name(p: &Path) -> SmolStr {
+    FileName::lossy(&p.file_name().unwrap().to_string_lossy())
+        .as_str()
+        .into()
+}
+
+/// Checks that batches from `peer_addr` are accepted and records that it was seen.
+pub(crate) async fn authorize_sender(peer_addr: SocketAddr) -> Result<(), RemoteResponse> {
+    resolve::refresh_registered().await;
+    let auto_accept = global::config_store()
+        .await
+        .read()
+        .await
+        .get_host_by_ip(peer_addr.ip())
+        .map(|(_, host)| host.policy.auto_accept);
+    match auto_accept {
+        Some(true) => {
+            record_contact(peer_addr.ip(), false).await;
+            Ok(())
+        }
+        Some(false) => Err(RemoteResponse::NotAccepted),
+        None => Err(RemoteResponse::UnregisteredHost),
+    }
+}
+
+/// Whether a contact time changed since [`save_contacts`] last saved them.
+static CONTACTS_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Updates when the registered host at `ip` was last seen (and transferred with). The times are
+/// saved by [`save_contacts`], not on every connection.
+pub(crate) async fn record_contact(ip: IpAddr, transferred: bool) {
+    let conf_store_lock = global::config_store().await;
+    if conf_store_lock.write().await.touch_host(ip, transferred) {
+        CONTACTS_CHANGED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Saves the host records every `interval` if a contact time changed since.
+pub(crate) async fn save_contacts(interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if !CONTACTS_CHANGED.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+        if let Err(e) = global::config_store().await.write().await.update_to_file() {
+            log::error!("Save host records failed! Detail: {}", e);
+        }
+    }
+}
+
+/// Long enough for a `PORT` request as well as a `PAIR` or `SYNC` one.
+const REMOTE_FIRST_LINE_LIMIT: u64 = 300;
+
+pub(crate) async fn handle_remote<S>(
+    mut remote_stream: S,
+    peer_addr: SocketAddr,
+) -> error::Result<()>
+where
+    S: AsyncWrite + AsyncRead + Unpin + Send + 'static,
+    for<'a> &'a mut S: AsyncRead,
+{
+    let mut remote_reader = BufReader::new(&mut remote_stream).take(REMOTE_FIRST_LINE_LIMIT);
+    let mut line = String::new();
+    if let Ok(size) = remote_reader.read_line(&mut line).await {
+        if size != 0 {
+            if let Some((req_tag, arg)) = line.trim().split_once(consts::STARTLINE_SEP) {
+                if req_tag == request_tag::pair::PAIR {
+                    return pairing::handle_pair_request(&mut remote_stream, peer_addr, arg).await;
+                }
+                if req_tag == request_tag::remote::REG {
+                    let pending =
+                        registration::handle_reg_request(&mut remote_stream, peer_addr, arg)
+                            .await?;
+                    if let Some(pending) = pending {
+                        spawn_outside_workers("waiting for a registration decision", async move {
+                            pending.answer(&mut remote_stream).await
+                        });
+                    }
+                    return Ok(());
+                }
+                if req_tag == request_tag::remote::SYNC {
+                    return sync::handle_sync_request(&mut remote_stream, peer_addr, arg).await;
+                }
+                if req_tag == request_tag::remote::RELAY {
+                    return relay::handle_relay_request(&mut remote_stream, peer_addr, arg).await;
+                }
+                if req_tag == request_tag::remote::PORT {
+                    if let Err(resp) = authorize_sender(peer_addr).await {
+                        remote_stream.write_line(resp.to_str_unchecked()).await?;
+                        return Ok(());
+                    }
+                    if let Ok(expected_port) = arg.parse::<u16>() {
+                        if let Some(l) = create_receive_listener(expected_port).await {
+                            let actual_port = l.local_addr()?.port();
+                            tokio::spawn(async move {
+                                if let Err(e) = receive_files(l, peer_addr.ip()).await {
+                                    log::error!(
+                                        "Error occurred in `receive_files`, error detail: {}",
+                                        e
+                                    );
+                                }
+                            });
+                            remote_stream
+                                .write_line(RemoteResponse::PortConfirm(actual_port).to_smolstr())
+                                .await?;
+                            remote_stream
+                                .write_line(Capabilities::ALL.to_line())
+                                .await?;
+                        } else {
+                            remote_stream
+                                .write_line(RemoteResponse::NoAvailablePort.to_str_unchecked())
+                                .await?;
+                        }
+                    } else {
+                        remote_stream
+                            .write_line(RemoteResponse::InvalidPort.to_str_unchecked())
+                            .await?;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+    if let Err(e) = remote_stream
+        .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
+        .await
+    {
+        log::error!("A remote connection maybe closed! Detail: {}", e);
+    }
+    Ok(())
+}
+
+async fn send_files<S>(
+    mut local_write_half: S,
+    dest_addr: SocketAddr,
+    caps: Capabilities,
+    files_paths: Vec<PathBuf>,
+    transfer: &Transfer,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let dest_stream = TcpStream::connect(dest_addr).await?;
+    let (remote_read_half, remote_write_half) = dest_stream.into_split();
+    let mut dest_writer = BufWriter::new(remote_write_half);
+    let mut dest_reader = BufReader::new(remote_read_half).take(StartLine::LENGTH_LIMIT);
+    let mut line = String::new();
+    let start_flag_with_line =
+        smol_str::format_smolstr!("{}{}", request_tag::send_flag::SEND_START, consts::LINE_SEP);
+    dest_writer
+        .write_line(smol_str::format_smolstr!(
+            "{}{}",
+            caps.start_line(),
+            consts::LINE_SEP
+        ))
+        .await?;
+    local_write_half.write_line(&start_flag_with_line).await?;
+    for p in &files_paths {
+        let name = announced_file_name(p);
+        let mut f = File::open(p)?;
+        let file_size = if let Some(size) = f.metadata().ok().map(|m| m.len()) {
+            if size > consts::FILE_SIZE_LIMIT {
+                log::warn!(
+                    "The size of file exceeds limit(10GB), file: \"{}\"",
+                    p.to_string_lossy()
+                );
+                continue;
+            }
+            Some(size)
+        } else {
+            None
+        };
+        let hash = if caps.hash {
+            Some(store::hash_file_blocking(p).await?)
+        } else {
+            None
+        };
+        transfer.start_file(&name, file_size);
+        let file_info = LocalResponse::FileInfo(name, file_size).to_smolstr();
+        dest_writer.write_line(&file_info).await?;
+        local_write_half.write_line(&file_info).await?;
+        if let Some(hash) = hash {
+            dest_writer
+                .write_line(smol_str::format_smolstr!(
+                    "{} {}",
+                    request_tag::send_flag::HASH,
+                    hash
+                ))
+                .await?;
+            read_hash_reply(&mut dest_reader, &mut line, file_size.unwrap_or_default()).await?;
+            match line.trim() {
+                request_tag::send_flag::HAVE => {
+                    transfer.progress(file_size.unwrap_or_default());
+                    local_write_half
+                        .write_line(LocalResponse::Progress(1.0).to_smolstr())
+                        .await?;
+                    dest_writer.write_all(consts::LINE_SEP.as_bytes()).await?;
+                    continue;
+                }
+                request_tag::send_flag::SEND => (),
+                reply => match delta::parse_reply(reply) {
+                    Some(reply) => {
+                        let sent = delta::send(
+                            &mut dest_reader,
+                            &mut dest_writer,
+                            &mut local_write_half,
+                            &mut f,
+                            reply,
+                            transfer,
+                        )
+                        .await?;
+                        if !sent {
+                            return Ok(());
+                        }
+                        dest_writer.write_all(consts::LINE_SEP.as_bytes()).await?;
+                        continue;
+                    }
+                    // The receiver stopped the batch, such as for its quota.
+                    None => {
+                        return finish_batch(
+                            local_write_half,
+                            dest_writer,
+                            &line,
+                            &files_paths,
+                            transfer,
+                        )
+                        .await
+                    }
+                },
+            }
+        }
+        let mut size_count = 0;
+        loop {
+            if transfer.is_cancelled() {
+                return local_write_half
+                    .write_line(LocalResponse::Cancelled.to_str_unchecked())
+                    .await;
+            }
+            let mut buf = [0_u8; consts::FILE_TRANS_BUF_SIZE];
+            let read_size = f.read(&mut buf)?;
+            if read_size == 0 {
+                break;
+            }
+            size_count += read_size;
+            dest_writer
+                .write_all(unsafe { buf.get_unchecked(0..read_size) })
+                .await?;
+            dest_writer.flush().await?;
+            transfer.progress(read_size as u64);
+            local_write_half
+                .write_line(
+                    LocalResponse::Progress(
+                        file_size
+                            .map(|size| size_count as f64 / size as f64)
+                            .unwrap_or(-1.0),
+                    )
+                    .to_smolstr(),
+                )
+                .await?;
+        }
+        dest_writer.write_all(consts::LINE_SEP.as_bytes()).await?;
+    }
+    local_write_half
+        .write_line(request_tag::send_flag::SEND_END)
+        .await?;
+    dest_writer
+        .write_line(request_tag::send_flag::SEND_END)
+        .await?;
+    line.clear();
+    dest_reader.set_limit(StartLine::LENGTH_LIMIT);
+    dest_reader.read_line(&mut line).await?;
+    finish_batch(local_write_half, dest_writer, &line, &files_paths, transfer).await
+}
+
+/// Passes the final line of the receiver on as the result of the batch.
+async fn finish_batch<S, D>(
+    mut local_write_half: S,
+    mut dest_writer: D,
+    line: &str,
+    files_paths: &[PathBuf],
+    transfer: &Transfer,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+    D: AsyncWrite + Unpin,
+{
+    let resp = batch_result(line, files_paths.len() as u8);
+    if matches!(resp, LocalResponse::AllFilesSucceeded) {
+        transfer.succeed();
+    }
+    local_write_half.write_line(resp.to_smolstr()).await?;
+    if matches!(resp, LocalResponse::UnexpectedSendResp) {
+        dest_writer.write_line(resp.to_str_unchecked()).await?;
+    }
+    Ok(())
+}
+
+async fn receive_files(listener: TcpListener, send_host_ip: IpAddr) -> std::io::Result<()> {
+    let sender_name = global::config_store()
+        .await
+        .read()
+        .await
+        .get_name_by_ip(send_host_ip)
+        .map(SmolStr::from)
+        .unwrap_or_else(|| send_host_ip.to_canonical().to_smolstr());
+    let transfer = Transfer::queue(Direction::Receive, &sender_name);
+    events::emit(Event::ShareAnnounced {
+        transfer: transfer.id(),
+        host: sender_name,
+    });
+    loop {
+        // A sender which never connects fails the transfer instead of keeping it queued.
+        let (mut stream, peer_addr) =
+            tokio::time::timeout(consts::HOST_CHECK_TIMEOUT, listener.accept())
+                .await
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        if peer_addr.ip().to_canonical() == send_host_ip.to_canonical() {
+            let (read_half, mut write_half) = stream.into_split();
+            let mut reader = BufReader::new(read_half).take(StartLine::LENGTH_LIMIT);
+            let mut line = String::new();
+            reader.read_line(&mut line).await?;
+            let mut start_line = line.split_whitespace();
+            if start_line.next() == Some(request_tag::send_flag::SEND_START) {
+                let caps = Capabilities::from_args(start_line);
+                let mut files_count: u8 = 0;
+                // Whether a file did not match its hash, it is left out and the batch fails.
+                let mut corrupt = false;
+                line.clear();
+                reader.set_limit(4);
+                let (recv_dir, sender, hooks, policy, content_store) = {
+                    let config = global::config_store().await.read().await;
+                    let (sender, policy) = match config.get_host_by_ip(send_host_ip) {
+                        Some((name, host)) => (SmolStr::from(name), host.policy.clone()),
+                        None => (send_host_ip.to_smolstr(), HostPolicy::default()),
+                    };
+                    (
+                        config.receive_dir_for(send_host_ip),
+                        sender,
+                        config.hooks().clone(),
+                        policy,
+                        config.content_store(),
+                    )
+                };
+                let mut throttle = Throttle::new(policy.bandwidth_limit);
+                tokio::fs::create_dir_all(&recv_dir).await?;
+                let mut received_files = vec![];
+                let mut file_hooks = vec![];
+                while reader.read_line(&mut line).await? != 0 && line.trim().is_empty() {
+                    reader.set_limit(consts::FILE_NAME_LENGTH_LIMIT as u64 + 40);
+                    line.clear();
+                    if reader.read_line(&mut line).await? == 0 {
+                        break;
+                    }
+                    let trimmed_line = line.trim();
+                    if trimmed_line == request_tag::send_flag::SEND_END {
+                        if files_count == 0 && !corrupt {
+                            break;
+                        }
+                        if corrupt {
+                            write_half
+                                .write_line(
+                                    RemoteResponse::ChecksumMismatch(files_count).to_smolstr(),
+                                )
+                                .await?;
+                        } else {
+                            write_half
+                                .write_line(RemoteResponse::FilesReceived(files_count).to_smolstr())
+                                .await?;
+                            transfer.succeed();
+                        }
+                        if files_count > 0 {
+                            history::record(HistoryEntry::BatchReceived {
+                                sender: &sender,
+                                files_count,
+                            })
+                            .await;
+                            record_contact(send_host_ip, true).await;
+                            if !hooks.is_empty() {
+                                hook::spawn_batch_hooks(
+                                    hooks,
+                                    sender,
+                                    recv_dir,
+                                    received_files,
+                                    file_hooks,
+                                );
+                            }
+                        }
+                        return Ok(());
+                    }
+                    let (name, file_size) = match LocalResponse::parse_file_info(trimmed_line) {
+                        Some((name, Some(size))) => match name.parse::<FileName>() {
+                            Ok(n) => (n, size),
+                            Err(_) => break,
+                        },
+                        _ => break,
+                    };
+                    let hash = if caps.hash {
+                        reader.set_limit(HASH_LINE_LIMIT);
+                        line.clear();
+                        reader.read_line(&mut line).await?;
+                        match line.trim().split_once(consts::STARTLINE_SEP) {
+                            Some((request_tag::send_flag::HASH, hash))
+                                if store::is_valid_hash(hash) =>
+                            {
+                                Some(SmolStr::from(hash))
+                            }
+                            _ => break,
+                        }
+                    } else {
+                        None
+                    };
+                    transfer.start_file(&name, Some(file_size));
+                    let file_path = recv_dir.join(name.as_str());
+                    let identical = match &hash {
+                        Some(hash) => {
+                            store::place_identical_blocking(
+                                file_path.clone(),
+                                content_store.clone(),
+                                file_size,
+                                hash.clone(),
+                            )
+                            .await
+                        }
+                        None => false,
+                    };
+                    let received = if let Some(hash) = hash.clone().filter(|_| identical) {
+                        log::info!("`{}` from `{}` is already present", name, sender);
+                        write_half.write_line(request_tag::send_flag::HAVE).await?;
+                        transfer.progress(file_size);
+                        reader.set_limit(consts::LINE_SEP.len() as u64);
+                        ReceivedFile {
+                            path: file_path,
+                            size: file_size,
+                            checksum: hash,
+                        }
+                    } else {
+                        let Some(reservation) =
+                            policy::reserve_quota(&sender, policy.daily_quota, file_size)
+                        else {
+                            log::warn!("Host `{}` exceeded its daily quota", sender);
+                            write_half
+                                .write_line(RemoteResponse::QuotaExceeded(files_count).to_smolstr())
+                                .await?;
+                            return Ok(());
+                        };
+                        let delta_base = std::fs::metadata(&file_path)
+                            .is_ok_and(|m| m.is_file() && m.len() >= delta::MIN_FILE_SIZE);
+                        let delta_hash = hash.filter(|_| {
+                            caps.delta && delta_base && file_size >= delta::MIN_FILE_SIZE
+                        });
+                        let checksum = if let Some(hash) = delta_hash {
+                            match delta::receive(
+                                &mut reader,
+                                &mut write_half,
+                                &file_path,
+                                file_size,
+                                &hash,
+                                &transfer,
+                                &mut throttle,
+                            )
+                            .await?
+                            {
+                                delta::Received::Complete => hash,
+                                delta::Received::Stopped => {
+                                    log::info!(
+                                        "Receiving the changes of `{}` from `{}` stopped early",
+                                        name,
+                                        sender
+                                    );
+                                    return Ok(());
+                                }
+                                // The old copy is kept, the stream is still in step with the
+                                // sender so the batch goes on.
+                                delta::Received::Corrupt => {
+                                    log::warn!(
+                                        "`{}` from `{}` does not match its hash after the changes",
+                                        name,
+                                        sender
+                                    );
+                                    corrupt = true;
+                                    reader.set_limit(consts::LINE_SEP.len() as u64);
+                                    line.clear();
+                                    continue;
+                                }
+                            }
+                        } else {
+                            if caps.hash {
+                                write_half.write_line(request_tag::send_flag::SEND).await?;
+                            }
+                            reader.set_limit(file_size);
+                            let f = RwLock::new(File::create(&file_path)?);
+                            let mut file_writer = f.write().await;
+                            let mut hasher = Sha256::new();
+                            let mut received_size = 0;
+                            loop {
+                                if transfer.is_cancelled() {
+                                    break;
+                                }
+                                let mut buf = [0; consts::FILE_TRANS_BUF_SIZE];
+                                let read_size = reader.read(&mut buf).await?;
+                                if read_size == 0 {
+                                    break;
+                                }
+                                let data = unsafe { buf.get_unchecked(0..read_size) };
+                                hasher.update(data);
+                                file_writer.write_all(data)?;
+                                received_size += read_size as u64;
+                                transfer.progress(read_size as u64);
+                                throttle.consume(read_size).await;
+                            }
+                            if received_size < file_size {
+                                // Cancelled on either side, a partial file is of no use.
+                                drop(file_writer);
+                                let _ = std::fs::remove_file(&file_path);
+                                log::info!("Receiving `{}` from `{}` stopped early", name, sender);
+                                return Ok(());
+                            }
+                            file_writer.flush()?;
+                            hex_string(&hasher.finalize())
+                        };
+                        reservation.keep();
+                        reader.set_limit(consts::LINE_SEP.len() as u64);
+                        let received = ReceivedFile {
+                            path: file_path,
+                            size: file_size,
+                            checksum,
+                        };
+                        if let Some(store) = &content_store {
+                            store::keep(store, &received.path, &received.checksum);
+                        }
+                        received
+                    };
+                    history::record(HistoryEntry::FileReceived {
+                        sender: &sender,
+                        path: &received.path,
+                        size: received.size,
+                        checksum: &received.checksum,
+                    })
+                    .await;
+                    events::emit(Event::FileReceived {
+                        host: sender.clone(),
+                        size: received.size,
+                        path: received.path.to_string_lossy().into(),
+                    });
+                    if !hooks.on_file.is_empty() {
+                        file_hooks.push(hook::spawn_file_hooks(
+                            hooks.clone(),
+                            sender.clone(),
+                            received.clone(),
+                        ));
+                    }
+                    received_files.push(received);
+                    files_count += 1;
+                    line.clear();
+                }
+                if files_count > 0 {
+                    write_half
+                        .write_line(RemoteResponse::UnexpectedEndFlag(files_count).to_smolstr())
+                        .await?;
+                    return Ok(());
+                }
+            }
+            write_half
+                .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
+                .await?;
+            return Ok(());
+        }
+        stream
+            .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
+            .await?;
+        tokio::task::yield_now().await;
+    }
+}
+
+pub(crate) fn hex_string(bytes: &[u8]) -> SmolStr {
+    use std::fmt::Write as _;
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        let _ = write!(s, "{:02x}", b);
+    }
+    s.into()
+}
+
+/// The address of a peer which connected from `addr`, but at `port`. IPv4-mapped addresses are
+/// turned back into IPv4 and the scope ID of a link-local address is kept.
+pub(crate) fn peer_addr_at(addr: SocketAddr, port: u16) -> SocketAddr {
+    let mut peer_addr = match addr.ip().to_canonical() {
+        ip @ IpAddr::V4(_) => SocketAddr::new(ip, port),
+        IpAddr::V6(_) => addr,
+    };
+    peer_addr.set_port(port);
+    peer_addr
+}
+
+/// Binds a listener at `addr`, the unspecified IPv6 address accepts IPv4 connections as well.
+pub(crate) fn bind_listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.ip() == Ipv6Addr::UNSPECIFIED {
+        socket.set_only_v6(false)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+/// Binds a data port from `port` on, the address family follows the one of the main listener.
+pub(crate) async fn create_receive_listener(port: u16) -> Option<TcpListener> {
+    let ip = match global::config_store()
+        .await
+        .read()
+        .await
+        .listener_addr()
+        .ip()
+    {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    (port..u16::MAX)
+        .chain(consts::MIN_PORT..port)
+        .find_map(|p| bind_listener(SocketAddr::new(ip, p)).ok())
+}
+
+#[cfg(test)]
+mod handler_tests {
+    use std::{
+        cell::RefCell,
+        fs::File,
+        path::Path,
+        rc::Rc,
+        task::Poll,
+        time::{Duration, SystemTime},
+    };
+
+    use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader};
+
+    use super::{
+        bind_listener, read_capabilities, receive_files, send_files, Capabilities, WriteLine,
+    };
+    use crate::{
+        common::LocalResponse,
+        global,
+        transfer::{Direction, Transfer},
+    };
+
+    #[derive(Debug)]
+    struct MockStream {
+        data_s2c: Vec<u8>,
+        data_c2s: Vec<u8>,
+    }
+
+    impl MockStream {
+        pub fn new() -> Self {
+            Self {
+                data_c2s: vec![],
+                data_s2c: vec![],
+            }
+        }
+
+        pub fn into_split(self) -> (ClientStream, ServerStream) {
+            println!("split inner");
+            let arc = Rc::new(RefCell::new(self));
+            (
+                ClientStream {
+                    inner_stream: arc.clone(),
+                },
+                ServerStream { inner_stream: arc },
+            )
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct ClientStream {
+        inner_stream: Rc<RefCell<MockStream>>,
+    }
+
+    #[derive(Debug, Clone)]
+    struct ServerStream {
+        inner_stream: Rc<RefCell<MockStream>>,
+    }
+
+    impl Unpin for MockStream {}
+
+    impl AsyncWrite for ClientStream {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<Result<usize, std::io::Error>> {
+            let mut inner_stream = self.inner_stream.as_ref().borrow_mut();
+            inner_stream.data_c2s = Vec::from(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncRead for ClientStream {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let mut inner_stream = self.inner_stream.as_ref().borrow_mut();
+            if !inner_stream.data_s2c.is_empty() {
+                let size = usize::min(inner_stream.data_s2c.len(), buf.remaining());
+                buf.put_slice(&inner_stream.data_s2c[..size]);
+                inner_stream.data_s2c.drain(..size);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for ServerStream {
+        fn poll_write(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, std::io::Error>> {
+            let mut inner_stream = self.inner_stream.as_ref().borrow_mut();
+            inner_stream.data_s2c = Vec::from(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<Result<(), std::io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> Poll<Result<(), std::io::Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncRead for ServerStream {
+        fn poll_read(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let mut inner_stream = self.inner_stream.as_ref().borrow_mut();
+            if !inner_stream.data_c2s.is_empty() {
+                let size = usize::min(inner_stream.data_c2s.len(), buf.remaining());
+                buf.put_slice(&inner_stream.data_c2s[..size]);
+                inner_stream.data_c2s.drain(..size);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    const START_LINE: &str = "GET / HTTP/1.1";
+    const HELLO_RESPONSE: &str = "<html><head></head><body><h1>Hello</h1></body></html>";
+
+    async fn simple_handle_connection(mut server_stream: ServerStream) -> std::io::Result<()> {
+        println!("into simple_handle_connection");
+        let mut line = String::new();
+        let mut reader = BufReader::new(&mut server_stream).take(20);
+        let read_size = reader.read_line(&mut line).await?;
+        println!("read a line finished, line = {}", line.trim());
+        if read_size != 0 && line.trim() == START_LINE.trim() {
+            println!("read a line and its equals START_LINE: \"{}\"", START_LINE);
+            println!("write content =  {}", HELLO_RESPONSE);
+            server_stream.write_line(HELLO_RESPONSE).await?;
+            println!("write response finished!");
+            println!(
+                "inner stream data_s2c = {}",
+                String::from_utf8_lossy(
+                    server_stream
+                        .inner_stream
+                        .as_ref()
+                        .borrow()
+                        .data_s2c
+                        .as_slice()
+                )
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn simple_handle_test() {
+        println!("before initial");
+        let stream = MockStream::new();
+        println!("stream initial finished");
+        let (mut c, s) = stream.into_split();
+        println!("split stream succeeded...");
+        let mut res = c.write_line(START_LINE).await;
+        assert!(res.is_ok());
+        println!(
+            "client write startline finished, inner data = {}",
+            String::from_utf8_lossy(c.inner_stream.as_ref().borrow().data_c2s.as_slice())
+        );
+        res = simple_handle_connection(s).await;
+        assert!(res.is_ok());
+        let mut client_reader = BufReader::new(c).take(128);
+        let mut resp = String::new();
+        let read_res = client_reader.read_to_string(&mut resp).await;
+        println!("read result = {}", &resp);
+        assert!(read_res.is_ok());
+        assert_eq!(resp.trim(), HELLO_RESPONSE);
+    }
+
+    #[tokio::test]
+    async fn dual_stack_listener_test() {
+        let listener =
+            super::bind_listener("[::]:0".parse().unwrap()).expect("IPv6 is not available");
+        let port = listener.local_addr().unwrap().port();
+        let _client = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let (_, peer_addr) = listener.accept().await.unwrap();
+        assert_eq!(
+            super::peer_addr_at(peer_addr, 10020),
+            "127.0.0.1:10020".parse().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn capabilities_test() {
+        assert_eq!(
+            Capabilities::parse_line("CAPS HASH DELTA\r\n"),
+            Some(Capabilities::ALL)
+        );
+        assert_eq!(
+            Capabilities::parse_line("CAPS DELTA"),
+            Some(Capabilities::default())
+        );
+        assert_eq!(
+            Capabilities::parse_line("CAPS"),
+            Some(Capabilities::default())
+        );
+        assert_eq!(Capabilities::parse_line("FILES_RECEIVED 1"), None);
+        assert_eq!(Capabilities::ALL.start_line(), "SEND_START HASH DELTA");
+        // An older receiver closes the connection after `PORT_CONFIRM`.
+        let mut reader = BufReader::new(&b""[..]).take(0);
+        assert_eq!(
+            read_capabilities(&mut reader).await,
+            Capabilities::default()
+        );
+    }
+
+    /// Sends `path` to a receiver over a loopback connection, returns what the local process got.
+    async fn send_loopback(path: &Path, caps: Capabilities) -> String {
+        let listener = bind_listener("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let receiver = tokio::spawn(receive_files(listener, addr.ip()));
+        let mut local = vec![];
+        let transfer = Transfer::queue(Direction::Send, "loopback");
+        send_files(&mut local, addr, caps, vec![path.to_owned()], &transfer)
+            .await
+            .unwrap();
+        receiver.await.unwrap().unwrap();
+        String::from_utf8(local).unwrap()
+    }
+
+    #[tokio::test]
+    async fn loopback_send_test() {
+        let save_dir = global::init_test_config_store().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("loopback.txt");
+        let received = save_dir.join("loopback.txt");
+        let succeeded = format!(
+            "{}\r\n",
+            LocalResponse::AllFilesSucceeded.to_str_unchecked()
+        );
+
+        // An older sender lists nothing and sends the content right away.
+        std::fs::write(&path, "content").unwrap();
+        let local = send_loopback(&path, Capabilities::default()).await;
+        assert!(local.ends_with(&succeeded), "{}", local);
+        assert_eq!(std::fs::read(&received).unwrap(), b"content");
+
+        // The receiver already has the file, so it answers `HAVE` and leaves it alone.
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options()
+            .write(true)
+            .open(&received)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let local = send_loopback(&path, Capabilities::ALL).await;
+        assert!(local.ends_with(&succeeded), "{}", local);
+        assert_eq!(received.metadata().unwrap().modified().unwrap(), modified);
+
+        std::fs::write(&path, "changed").unwrap();
+        let local = send_loopback(&path, Capabilities::ALL).await;
+        assert!(local.ends_with(&succeeded), "{}", local);
+        assert_eq!(std::fs::read(&received).unwrap(), b"changed");
+    }
+}
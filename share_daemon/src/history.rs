@@ -0,0 +1,135 @@
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tokio::sync::Mutex;
+
+use crate::global;
+
+pub(crate) const HISTORY_FILE_NAME: &str = "history.log";
+
+/// A line of the transfer history.
+#[derive(Debug, Clone)]
+pub(crate) enum HistoryEntry<'a> {
+    FileReceived {
+        sender: &'a str,
+        path: &'a Path,
+        size: u64,
+        checksum: &'a str,
+    },
+    BatchReceived {
+        sender: &'a str,
+        files_count: u8,
+    },
+    HookFailed {
+        hook: &'a str,
+        target: &'a Path,
+        detail: &'a str,
+    },
+    Relayed {
+        sender: &'a str,
+        target: &'a str,
+        bytes: u64,
+    },
+    Synced {
+        sender: &'a str,
+        dir: &'a Path,
+        files_count: u32,
+        deleted: u32,
+    },
+}
+
+impl Display for HistoryEntry<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryEntry::FileReceived {
+                sender,
+                path,
+                size,
+                checksum,
+            } => write!(
+                f,
+                "FILE_RECEIVED\tsender={}\tpath={}\tsize={}\tsha256={}",
+                sender,
+                path.to_string_lossy(),
+                size,
+                checksum
+            ),
+            HistoryEntry::BatchReceived {
+                sender,
+                files_count,
+            } => write!(
+                f,
+                "BATCH_RECEIVED\tsender={}\tfiles={}",
+                sender, files_count
+            ),
+            HistoryEntry::HookFailed {
+                hook,
+                target,
+                detail,
+            } => write!(
+                f,
+                "HOOK_FAILED\thook={}\ttarget={}\tdetail={}",
+                hook,
+                target.to_string_lossy(),
+                detail
+            ),
+            HistoryEntry::Relayed {
+                sender,
+                target,
+                bytes,
+            } => write!(
+                f,
+                "RELAYED\tsender={}\ttarget={}\tbytes={}",
+                sender, target, bytes
+            ),
+            HistoryEntry::Synced {
+                sender,
+                dir,
+                files_count,
+                deleted,
+            } => write!(
+                f,
+                "SYNCED\tsender={}\tdir={}\tfiles={}\tdeleted={}",
+                sender,
+                dir.to_string_lossy(),
+                files_count,
+                deleted
+            ),
+        }
+    }
+}
+
+/// Keeps concurrent transfers from interleaving their lines.
+static HISTORY_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// The history is kept next to the config file, wherever `--config` put it.
+async fn history_path() -> PathBuf {
+    global::config_store()
+        .await
+        .read()
+        .await
+        .config_path()
+        .with_file_name(HISTORY_FILE_NAME)
+}
+
+/// Appends `entry` to the transfer history, failures are only logged.
+pub(crate) async fn record(entry: HistoryEntry<'_>) {
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let path = history_path().await;
+    let _guard = HISTORY_LOCK.lock().await;
+    let res = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| writeln!(f, "{}\t{}", secs, entry));
+    if let Err(e) = res {
+        log::error!("Write transfer history failed! Detail: {}", e);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+};
+
+use smol_str::SmolStr;
+use tokio::{process::Command, sync::Semaphore, task::JoinHandle};
+
+use crate::{
+    config::HooksConfig,
+    history::{self, HistoryEntry},
+};
+
+pub(crate) mod env_key {
+    pub const FILE_PATH: &str = "FSHARE_FILE_PATH";
+    pub const FILE_SIZE: &str = "FSHARE_FILE_SIZE";
+    pub const FILE_CHECKSUM: &str = "FSHARE_FILE_CHECKSUM";
+    pub const SENDER: &str = "FSHARE_SENDER";
+    pub const RECEIVE_DIR: &str = "FSHARE_RECEIVE_DIR";
+    pub const FILES_COUNT: &str = "FSHARE_FILES_COUNT";
+    pub const FILE_PATHS: &str = "FSHARE_FILE_PATHS";
+}
+
+/// A file that was written completely to the receive directory.
+#[derive(Debug, Clone)]
+pub(crate) struct ReceivedFile {
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    pub(crate) checksum: SmolStr,
+}
+
+/// The semaphore limiting hooks to `max_concurrent`, replaced when the config changes the limit.
+/// Hooks already running keep the permits of the old one.
+fn semaphore(max_concurrent: u8) -> Arc<Semaphore> {
+    static SEMAPHORE: Mutex<Option<(u8, Arc<Semaphore>)>> = Mutex::new(None);
+    let mut current = SEMAPHORE.lock().unwrap();
+    match current.as_ref() {
+        Some((max, semaphore)) if *max == max_concurrent => semaphore.clone(),
+        _ => {
+            let semaphore = Arc::new(Semaphore::new(max_concurrent as usize));
+            *current = Some((max_concurrent, semaphore.clone()));
+            semaphore
+        }
+    }
+}
+
+fn shell_command(command: &str) -> Command {
+    #[cfg(windows)]
+    let mut c = {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(command);
+        c
+    };
+    #[cfg(not(windows))]
+    let mut c = {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(command);
+        c
+    };
+    c.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    c
+}
+
+async fn run_hook(hooks: &HooksConfig, hook: &str, target: &Path, envs: &[(&str, String)]) {
+    let _permit = semaphore(hooks.max_concurrent).acquire_owned().await;
+    let mut command = shell_command(hook);
+    command.envs(envs.iter().map(|(k, v)| (*k, v.as_str())));
+    let detail = match command.spawn() {
+        Ok(child) => match tokio::time::timeout(hooks.timeout(), child.wait_with_output()).await {
+            Ok(Ok(output)) if output.status.success() => return,
+            Ok(Ok(output)) => format!(
+                "{}, stderr: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("timed out after {}s", hooks.timeout_secs),
+        },
+        Err(e) => e.to_string(),
+    };
+    log::warn!("Hook `{}` failed: {}", hook, detail);
+    history::record(HistoryEntry::HookFailed {
+        hook,
+        target,
+        detail: &detail,
+    })
+    .await;
+}
+
+/// Spawns the `on_file` hooks of `file` one after another, the returned handle finishes once all
+/// of them exited.
+pub(crate) fn spawn_file_hooks(
+    hooks: HooksConfig,
+    sender: SmolStr,
+    file: ReceivedFile,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let envs = [
+            (env_key::FILE_PATH, file.path.to_string_lossy().into_owned()),
+            (env_key::FILE_SIZE, file.size.to_string()),
+            (env_key::FILE_CHECKSUM, file.checksum.to_string()),
+            (env_key::SENDER, sender.to_string()),
+        ];
+        for hook in &hooks.on_file {
+            run_hook(&hooks, hook, &file.path, &envs).await;
+        }
+    })
+}
+
+/// Spawns the `on_batch` hooks after `file_hooks` finished.
+pub(crate) fn spawn_batch_hooks(
+    hooks: HooksConfig,
+    sender: SmolStr,
+    recv_dir: PathBuf,
+    files: Vec<ReceivedFile>,
+    file_hooks: Vec<JoinHandle<()>>,
+) {
+    tokio::spawn(async move {
+        for h in file_hooks {
+            let _ = h.await;
+        }
+        let paths: Vec<_> = files
+            .iter()
+            .map(|f| f.path.to_string_lossy().into_owned())
+            .collect();
+        let envs = [
+            (
+                env_key::RECEIVE_DIR,
+                recv_dir.to_string_lossy().into_owned(),
+            ),
+            (env_key::FILES_COUNT, files.len().to_string()),
+            (env_key::FILE_PATHS, paths.join("\n")),
+            (env_key::SENDER, sender.to_string()),
+        ];
+        for hook in &hooks.on_batch {
+            run_hook(&hooks, hook, &recv_dir, &envs).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod hook_tests {
+    use std::sync::Arc;
+
+    use super::semaphore;
+
+    #[test]
+    fn semaphore_test() {
+        let two = semaphore(2);
+        assert!(Arc::ptr_eq(&two, &semaphore(2)));
+        let three = semaphore(3);
+        assert!(!Arc::ptr_eq(&two, &three));
+        assert_eq!(three.available_permits(), 3);
+    }
+}
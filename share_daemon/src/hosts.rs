@@ -0,0 +1,112 @@
+use smol_str::{SmolStr, ToSmolStr};
+use tokio::io::AsyncWrite;
+
+use crate::{
+    common::{HostInfo, LocalResponse, RemoteResponse, Response},
+    config::{Config, HostAddr, HostRecord},
+    consts, global,
+    handler::WriteLine,
+    identity, request_tag,
+};
+
+fn host_info(name: &str, host: &HostRecord) -> HostInfo {
+    HostInfo {
+        name: name.into(),
+        addrs: host.addrs.clone(),
+        fingerprint: host
+            .public_key
+            .as_deref()
+            .and_then(identity::parse_hex::<32>)
+            .map(|key| identity::fingerprint(&key)),
+        auto_accept: host.policy.auto_accept,
+        last_seen: host.last_seen,
+        last_transfer: host.last_transfer,
+        description: host.description.clone(),
+    }
+}
+
+/// Answers a `HOSTS` request with a `REG_HOST` line for every registered host and `LIST_END`.
+pub(crate) async fn list_hosts<S>(local_stream: &mut S) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let lines: Vec<_> = global::config_store()
+        .await
+        .read()
+        .await
+        .reg_hosts()
+        .into_iter()
+        .map(|(name, host)| LocalResponse::RegHost(Box::new(host_info(name, host))).to_smolstr())
+        .collect();
+    for line in lines {
+        local_stream.write_line(line).await?;
+    }
+    local_stream
+        .write_line(LocalResponse::ListEnd.to_str_unchecked())
+        .await
+}
+
+/// Answers `UNREG <name>`, `RENAME <name> <new name>` and `SET_ADDR <name> <addr>[,<addr>...]`,
+/// the change is written to the config file right away.
+pub(crate) async fn update_host<S>(
+    local_stream: &mut S,
+    command: &str,
+    arg: &str,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let (hostname, rest) = arg.split_once(consts::STARTLINE_SEP).unwrap_or((arg, ""));
+    let resp = {
+        let conf_store_lock = global::config_store().await;
+        let mut config_store = conf_store_lock.write().await;
+        let before = Config::clone(&config_store);
+        match apply_update(&mut config_store, command, hostname, rest) {
+            Ok(()) => match config_store.update_to_file() {
+                Ok(()) => LocalResponse::HostUpdated.to_smolstr(),
+                Err(e) => {
+                    log::error!("Save the config after `{}` failed! Detail: {}", command, e);
+                    // Keeps memory in line with the file, the change is reported as failed.
+                    config_store.set_config(before);
+                    LocalResponse::LocalRegisterFailed.to_smolstr()
+                }
+            },
+            Err(resp) => resp,
+        }
+    };
+    local_stream.write_line(resp).await
+}
+
+fn apply_update(
+    config: &mut Config,
+    command: &str,
+    hostname: &str,
+    rest: &str,
+) -> Result<(), SmolStr> {
+    if config.get_host(hostname).is_none() {
+        return Err(LocalResponse::UnregisteredHostname.to_smolstr());
+    }
+    match command {
+        request_tag::local::UNREG => {
+            config.unregister_host(hostname);
+        }
+        request_tag::local::RENAME => {
+            if !Config::check_hostname_valid(rest) {
+                return Err(Response::InvalidHostname.to_smolstr());
+            }
+            if !config.rename_host(hostname, rest) {
+                return Err(LocalResponse::HostnameTaken.to_smolstr());
+            }
+        }
+        _ => {
+            let addrs = rest
+                .split(consts::HOSTS_SEP)
+                .map(|addr| addr.trim().parse::<HostAddr>())
+                .collect::<Result<Vec<_>, _>>();
+            if !addrs.is_ok_and(|addrs| config.set_host_addrs(hostname, addrs)) {
+                return Err(RemoteResponse::InvalidRequest.to_smolstr());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+use smol_str::SmolStr;
+use tokio::sync::OnceCell;
+
+use crate::{
+    error::{self, Error},
+    global,
+    handler::hex_string,
+};
+
+pub(crate) const IDENTITY_FILE_NAME: &str = "identity.key";
+
+/// The long-term key pair of this daemon, the secret part is kept next to the config file.
+#[derive(Debug)]
+pub(crate) struct Identity {
+    signing_key: SigningKey,
+}
+
+impl Identity {
+    pub(crate) fn generate() -> error::Result<Self> {
+        let mut secret = [0_u8; ed25519_dalek::SECRET_KEY_LENGTH];
+        getrandom::fill(&mut secret).map_err(std::io::Error::other)?;
+        Ok(Self {
+            signing_key: SigningKey::from_bytes(&secret),
+        })
+    }
+
+    pub(crate) fn load_or_create<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let path = path.as_ref();
+        if path.is_file() {
+            let content = std::fs::read_to_string(path).map_err(Error::config_io(path))?;
+            let secret = parse_hex::<{ ed25519_dalek::SECRET_KEY_LENGTH }>(content.trim())
+                .ok_or_else(|| Error::InvalidConfig("Invalid identity key file!".into()))?;
+            return Ok(Self {
+                signing_key: SigningKey::from_bytes(&secret),
+            });
+        }
+        let identity = Self::generate()?;
+        std::fs::write(
+            path,
+            hex_string(&identity.signing_key.to_bytes()).as_bytes(),
+        )
+        .map_err(Error::config_io(path))?;
+        Ok(identity)
+    }
+
+    pub(crate) fn public_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    pub(crate) fn fingerprint(&self) -> SmolStr {
+        fingerprint(self.public_key().as_bytes())
+    }
+}
+
+/// The identity of this daemon, created on first use. No config guard may be held while calling
+/// this the first time.
+pub(crate) async fn identity() -> &'static Identity {
+    static IDENTITY: OnceCell<Identity> = OnceCell::const_new();
+    IDENTITY
+        .get_or_init(|| async {
+            let path = identity_path().await;
+            Identity::load_or_create(&path).unwrap_or_else(|e| {
+                log::error!(
+                    "Load identity key from \"{}\" failed, using a temporary one! Detail: {}",
+                    path.to_string_lossy(),
+                    e
+                );
+                Identity::generate().expect("Unexpected: generate identity key failed!")
+            })
+        })
+        .await
+}
+
+/// The key is kept next to the config file, wherever `--config` put it.
+async fn identity_path() -> PathBuf {
+    global::config_store()
+        .await
+        .read()
+        .await
+        .config_path()
+        .with_file_name(IDENTITY_FILE_NAME)
+}
+
+/// A short, human comparable digest of a public key.
+pub(crate) fn fingerprint(public_key: &[u8]) -> SmolStr {
+    hex_string(&Sha256::digest(public_key)[..8])
+}
+
+pub(crate) fn parse_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 || !s.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0_u8; N];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
@@ -1,65 +1,118 @@
-pub mod common;
-pub mod config;
-pub mod request_tag;
-pub mod server;
-
-pub(crate) mod handler;
-
-pub mod consts {
-    use std::{
-        net::{IpAddr, Ipv4Addr, SocketAddr},
-        time::Duration,
-    };
-    const KB: u64 = 1024;
-    const MB: u64 = 1024 * KB;
-    const GB: u64 = 1024 * MB;
-
-    pub const FILE_SIZE_LIMIT: u64 = 10 * GB;
-    pub const DEFAULT_CONFIG_DIR_NAME: &str = ".tinyfileshare";
-    pub const DEFAULT_CONFIG_FILE_NAME: &str = "config.toml";
-    pub const MIN_PORT: u16 = 3000;
-    const DEFAULT_PORT: u16 = 10020;
-    pub const HOST_CHECK_TIMEOUT: Duration = Duration::from_secs(15);
-    pub const DEFAULT_LISTENER_ADDR: SocketAddr =
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), DEFAULT_PORT);
-    pub const UNSPECIFIED_LISTENER_ADDR: SocketAddr =
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
-    pub const DEFAULT_IPC_SOCK_NAME: &str = "share.sock";
-    pub const HOST_NAME_LENGTH_LIMIT: usize = 20;
-    pub const LINE_SEP: &str = "\r\n";
-    pub const ASCII_SPACE: char = ' ';
-    pub const STARTLINE_SEP: char = ' ';
-    pub const PAIR_SEP: char = ':';
-    pub const FILE_NAME_LENGTH_LIMIT: usize = 260;
-    pub const NUMBER_PATHS_PER_REQUEST: usize = 4;
-    pub const FILE_TRANS_BUF_SIZE: usize = 8192;
-    pub const FILE_PATH_LIMIT: u64 = 500;
-    pub const DEFAULT_NUM_WORKERS: u8 = 4;
-}
-
-mod global {
-    use std::sync::OnceLock;
-
-    use tokio::sync::RwLock;
-
-    use crate::config::ConfigStore;
-
-    pub(crate) async fn config_store() -> &'static RwLock<ConfigStore> {
-        static mut CONFIG: OnceLock<RwLock<ConfigStore>> = OnceLock::new();
-        unsafe {
-            match CONFIG.get_mut() {
-                Some(conf_store_lock) => {
-                    let mut config_store = conf_store_lock.write().await;
-                    if let Err(e) = config_store.try_update_from_file() {
-                        log::error!("Try to update from config file failed! Detail: {}", e);
-                    }
-                    conf_store_lock
-                }
-                None => CONFIG.get_or_init(|| RwLock::new(ConfigStore::default())),
-            }
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {}
+pub mod config;
+pub mod error;
+pub mod server;
+
+pub use fshare_proto::{common, request_tag};
+
+pub(crate) mod delta;
+pub(crate) mod discovery;
+pub(crate) mod events;
+pub(crate) mod fanout;
+pub(crate) mod handler;
+pub(crate) mod history;
+pub(crate) mod hook;
+pub(crate) mod hosts;
+pub(crate) mod identity;
+pub(crate) mod pairing;
+pub(crate) mod policy;
+pub(crate) mod registration;
+pub(crate) mod relay;
+pub(crate) mod resolve;
+pub(crate) mod rpc;
+pub(crate) mod status;
+pub(crate) mod store;
+pub(crate) mod sync;
+pub(crate) mod transfer;
+#[cfg(target_os = "linux")]
+pub(crate) mod watch;
+
+pub mod consts {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+        path::{Path, PathBuf},
+        time::Duration,
+    };
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * KB;
+    const GB: u64 = 1024 * MB;
+
+    pub use fshare_proto::consts::*;
+
+    pub const FILE_SIZE_LIMIT: u64 = 10 * GB;
+    pub const HOST_CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+    /// How long a sender waits for the answer to a `HASH` line, on top of the time the receiver
+    /// may take to hash its own copy of the file, see [`hash_reply_timeout`].
+    pub const HASH_REPLY_TIMEOUT: Duration = Duration::from_secs(300);
+    /// The slowest hashing a sender waits for, in bytes per second.
+    pub const MIN_HASH_RATE: u64 = 10 * MB;
+    pub const DNS_CACHE_TTL: Duration = Duration::from_secs(60);
+    pub const DNS_NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(10);
+    /// How often the last contact times of the registered hosts are saved.
+    pub const CONTACT_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+    /// Dual-stack, IPv4 peers connect with IPv4-mapped addresses.
+    pub const DEFAULT_LISTENER_ADDR: SocketAddr =
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), DEFAULT_PORT);
+    /// Used when IPv6 is not available.
+    pub const DEFAULT_V4_LISTENER_ADDR: SocketAddr =
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), DEFAULT_PORT);
+    pub const UNSPECIFIED_LISTENER_ADDR: SocketAddr =
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+    pub const DEFAULT_DISCOVERY_GROUP: SocketAddr = SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::new(239, 255, 70, 77)),
+        DEFAULT_PORT + 1,
+    );
+    pub const FILE_TRANS_BUF_SIZE: usize = 8192;
+    pub const DEFAULT_NUM_WORKERS: u8 = 4;
+    /// A file is written next to its path with this suffix and renamed once complete.
+    pub const PART_SUFFIX: &str = ".fshare-part";
+
+    /// How long the answer to the `HASH` line of a file of `size` bytes may take.
+    pub(crate) fn hash_reply_timeout(size: u64) -> Duration {
+        HASH_REPLY_TIMEOUT + Duration::from_secs(size / MIN_HASH_RATE)
+    }
+
+    /// The part file `path` is written to.
+    pub(crate) fn part_path(path: &Path) -> PathBuf {
+        let mut part = path.as_os_str().to_owned();
+        part.push(PART_SUFFIX);
+        PathBuf::from(part)
+    }
+}
+
+mod global {
+    use std::sync::OnceLock;
+
+    use tokio::sync::RwLock;
+
+    use crate::config::ConfigStore;
+
+    static CONFIG: OnceLock<RwLock<ConfigStore>> = OnceLock::new();
+
+    pub(crate) async fn config_store() -> &'static RwLock<ConfigStore> {
+        match CONFIG.get() {
+            Some(conf_store_lock) => {
+                let mut config_store = conf_store_lock.write().await;
+                match config_store.try_update_from_file() {
+                    Ok(true) => crate::events::emit(crate::events::Event::ConfigReloaded),
+                    Ok(false) => (),
+                    Err(e) => log::error!("Try to update from config file failed! Detail: {}", e),
+                }
+                conf_store_lock
+            }
+            None => CONFIG.get_or_init(|| RwLock::new(ConfigStore::default())),
+        }
+    }
+
+    /// Points the store at a config in a temporary directory, which is its save directory as
+    /// well, so tests going through the store never touch the config of the user. Returns the
+    /// directory.
+    #[cfg(test)]
+    pub(crate) async fn init_test_config_store() -> std::path::PathBuf {
+        let store = CONFIG
+            .get_or_init(|| RwLock::new(ConfigStore::in_dir(tempfile::tempdir().unwrap().keep())));
+        store.read().await.receive_dir().to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {}
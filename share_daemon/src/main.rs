@@ -104,7 +104,7 @@ impl std::str::FromStr for SockName {
         if name_res.is_ok()
             && interprocess::local_socket::ListenerOptions::new()
                 .name(name_res.unwrap())
-                .create_tokio()
+                .create_sync()
                 .is_ok()
         {
             return Ok(Self(s.into()));
@@ -207,6 +207,9 @@ mod arg_id {
     pub const IPC_SOCKET_NAME: &str = "ipc_socket";
 
     pub const FILES_SAVE_DIR: &str = "save_dir";
+    pub const RECEIVE_PATH: &str = "receive_path";
+    pub const HOST_NAME: &str = "host_name";
+    pub const DISCOVERY: &str = "discovery";
 }
 
 fn main() {
@@ -231,7 +234,7 @@ fn main() {
             clap::Arg::new(arg_id::PORT)
                 .short('p')
                 .long(arg_id::PORT)
-                .value_parser(clap::value_parser!(u16).range(fshare_server::consts::MIN_PORT as i64..)),
+                .value_parser(clap::value_parser!(fshare_proto::Port)),
         )
         .arg(
             clap::Arg::new(arg_id::ADDR)
@@ -256,6 +259,24 @@ fn main() {
                 .long(arg_id::FILES_SAVE_DIR)
                 .value_parser(clap::value_parser!(DirPath)),
         )
+        .arg(
+            clap::Arg::new(arg_id::RECEIVE_PATH)
+                .long(arg_id::RECEIVE_PATH)
+                .value_parser(clap::value_parser!(String))
+                .help("Template of the directory received files are saved to, such as `{save_dir}/{host}/{date}`."),
+        )
+        .arg(
+            clap::Arg::new(arg_id::HOST_NAME)
+                .long(arg_id::HOST_NAME)
+                .value_parser(clap::value_parser!(String))
+                .help("The name this daemon announces itself with on the LAN."),
+        )
+        .arg(
+            clap::Arg::new(arg_id::DISCOVERY)
+                .long(arg_id::DISCOVERY)
+                .action(clap::ArgAction::SetTrue)
+                .help("Announce this daemon and discover the others on the LAN."),
+        )
         .get_matches();
     let mut server = fshare_server::server::Server::default();
     if let Some(mut log_dir) = matches.remove_one::<DirPath>(arg_id::LOG_DIR) {
@@ -279,6 +300,8 @@ fn main() {
                 e
             );
         }
+    } else if let Err(e) = server.load_default_config_file() {
+        log::error!("Load from the default config file failed! Detail: {}", e);
     }
 
     if let Some(addr) = matches.remove_one::<SocketAddr>(arg_id::ADDR) {
@@ -287,8 +310,8 @@ fn main() {
         if let Some(ip) = matches.remove_one::<IpAddr>(arg_id::IP) {
             server.set_listener_ip(ip);
         }
-        if let Some(port) = matches.remove_one::<u16>(arg_id::PORT) {
-            server.set_listener_port(port);
+        if let Some(port) = matches.remove_one::<fshare_proto::Port>(arg_id::PORT) {
+            server.set_listener_port(port.get());
         }
     }
     if let Some(socket_name) = matches.remove_one::<SockName>(arg_id::IPC_SOCKET_NAME) {
@@ -299,8 +322,21 @@ fn main() {
         server.set_save_dir(files_save_dir);
     }
 
+    if let Some(receive_path) = matches.remove_one::<String>(arg_id::RECEIVE_PATH) {
+        server.set_receive_path(receive_path.into());
+    }
+
+    if let Some(host_name) = matches.remove_one::<String>(arg_id::HOST_NAME) {
+        server.set_host_name(host_name.into());
+    }
+
+    if matches.get_flag(arg_id::DISCOVERY) {
+        server.enable_discovery();
+    }
+
     if let Err(e) = server.start() {
         eprintln!("Start server failed: \ndetail: {}", e);
+        std::process::exit(1);
     }
 }
 
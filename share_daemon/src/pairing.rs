@@ -0,0 +1,496 @@
+use std::{collections::HashMap, net::SocketAddr, sync::OnceLock, time::Duration};
+
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use smol_str::SmolStr;
+use spake2::{Ed25519Group, Identity as SpakeIdentity, Password, Spake2};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader},
+    net::TcpStream,
+    sync::{oneshot, Mutex},
+};
+
+use crate::{
+    common::LocalResponse,
+    config::Config,
+    consts, error,
+    events::{self, Event},
+    global,
+    handler::{self, hex_string, WriteLine},
+    identity::{self, parse_hex},
+    request_tag::pair,
+};
+
+/// How long an offered pairing code stays valid.
+pub(crate) const PAIR_TIMEOUT: Duration = Duration::from_secs(300);
+const PAIR_LINE_LIMIT: u64 = 256;
+const INITIATOR_ID: &[u8] = b"fshare-pair-initiator";
+const RESPONDER_ID: &[u8] = b"fshare-pair-responder";
+
+/// A host registered on both sides by a successful pairing.
+#[derive(Debug, Clone)]
+pub(crate) struct PairedHost {
+    pub(crate) name: SmolStr,
+    pub(crate) addr: SocketAddr,
+}
+
+/// How a pairing ended, the error is the response for the local process.
+pub(crate) type PairResult = Result<PairedHost, LocalResponse>;
+
+struct PendingOffer {
+    password: SmolStr,
+    done: oneshot::Sender<PairResult>,
+}
+
+fn offers() -> &'static Mutex<HashMap<u16, PendingOffer>> {
+    static OFFERS: OnceLock<Mutex<HashMap<u16, PendingOffer>>> = OnceLock::new();
+    OFFERS.get_or_init(Default::default)
+}
+
+fn random_u32() -> error::Result<u32> {
+    Ok(getrandom::u32().map_err(std::io::Error::other)?)
+}
+
+/// Creates a one-time pairing code such as `17-4821-9936`, the first group picks the offer and
+/// the rest is the password. The receiver resolves once somebody tried the code or it expired.
+pub(crate) async fn create_offer() -> error::Result<(SmolStr, oneshot::Receiver<PairResult>)> {
+    let password =
+        smol_str::format_smolstr!("{:04}-{:04}", random_u32()? % 10000, random_u32()? % 10000);
+    let mut offers = offers().lock().await;
+    offers.retain(|_, offer| !offer.done.is_closed());
+    let mut id = (random_u32()? % 90 + 10) as u16;
+    while offers.contains_key(&id) {
+        id = (random_u32()? % 90 + 10) as u16;
+    }
+    let (done, done_rx) = oneshot::channel();
+    offers.insert(
+        id,
+        PendingOffer {
+            password: password.clone(),
+            done,
+        },
+    );
+    Ok((smol_str::format_smolstr!("{}-{}", id, password), done_rx))
+}
+
+/// Drops an offer that nobody tried before it expired.
+pub(crate) async fn cancel_offer(code: &str) {
+    if let Some((id, _)) = parse_code(code) {
+        offers().lock().await.remove(&id);
+    }
+}
+
+pub(crate) fn parse_code(code: &str) -> Option<(u16, &str)> {
+    let (id, password) = code.trim().split_once('-')?;
+    Some((id.parse().ok()?, password))
+}
+
+/// The identity part of a pairing message: `<name> <port> <public key> <mac>`, the MAC binds it
+/// to the key agreed on through the pairing code.
+struct PairIdentity {
+    name: SmolStr,
+    port: u16,
+    public_key: SmolStr,
+}
+
+impl PairIdentity {
+    async fn own() -> Self {
+        let public_key = hex_string(identity::identity().await.public_key().as_bytes());
+        let config = global::config_store().await.read().await;
+        Self {
+            name: config.host_name().into(),
+            port: config.listener_addr().port(),
+            public_key,
+        }
+    }
+
+    fn mac(&self, key: &[u8], label: &[u8]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(key).expect("any key length");
+        mac.update(label);
+        mac.update(self.name.as_bytes());
+        mac.update(&self.port.to_be_bytes());
+        mac.update(self.public_key.as_bytes());
+        mac
+    }
+
+    fn to_line(&self, key: &[u8], label: &[u8]) -> SmolStr {
+        smol_str::format_smolstr!(
+            "{} {} {} {}",
+            self.name,
+            self.port,
+            self.public_key,
+            hex_string(&self.mac(key, label).finalize().into_bytes())
+        )
+    }
+
+    fn from_parts<'a>(
+        mut parts: impl Iterator<Item = &'a str>,
+        key: &[u8],
+        label: &[u8],
+    ) -> Option<Self> {
+        let (Some(name), Some(port), Some(public_key), Some(mac), None) = (
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) else {
+            return None;
+        };
+        if !Config::check_hostname_valid(name) {
+            return None;
+        }
+        parse_hex::<{ ed25519_dalek::PUBLIC_KEY_LENGTH }>(public_key)?;
+        let identity = Self {
+            name: name.into(),
+            port: port.parse().ok()?,
+            public_key: public_key.into(),
+        };
+        let mac = parse_hex::<32>(mac)?;
+        identity.mac(key, label).verify_slice(&mac).ok()?;
+        Some(identity)
+    }
+}
+
+/// Whether the name of the peer is registered for a host with another key, pairing again with
+/// the same host is fine.
+fn name_taken(config: &Config, identity: &PairIdentity) -> bool {
+    config
+        .get_host(&identity.name)
+        .is_some_and(|host| host.public_key.as_deref() != Some(identity.public_key.as_str()))
+}
+
+/// Registers the peer, unless its name is taken by another host.
+async fn register_paired(
+    identity: &PairIdentity,
+    addr: SocketAddr,
+) -> error::Result<Option<PairedHost>> {
+    let conf_store_lock = global::config_store().await;
+    let mut config_store = conf_store_lock.write().await;
+    if name_taken(&config_store, identity) {
+        log::warn!(
+            "Refused pairing with `{}` ({}), the name is taken by another host",
+            identity.name,
+            addr
+        );
+        return Ok(None);
+    }
+    config_store.register_host(&identity.name, addr.into());
+    config_store.set_host_key(&identity.name, Some(identity.public_key.clone()));
+    config_store.update_to_file()?;
+    events::emit(Event::HostRegistered {
+        name: identity.name.clone(),
+        addr: addr.into(),
+    });
+    Ok(Some(PairedHost {
+        name: identity.name.clone(),
+        addr,
+    }))
+}
+
+/// Answers a `PAIR <offer id> <spake2 message>` request sent to the daemon port.
+pub(crate) async fn handle_pair_request<S>(
+    remote_stream: &mut S,
+    peer_addr: SocketAddr,
+    arg: &str,
+) -> error::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let own = PairIdentity::own().await;
+    answer_pair_request(remote_stream, peer_addr, arg, own).await
+}
+
+async fn answer_pair_request<S>(
+    remote_stream: &mut S,
+    peer_addr: SocketAddr,
+    arg: &str,
+    own: PairIdentity,
+) -> error::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let parsed = arg
+        .split_once(consts::STARTLINE_SEP)
+        .and_then(|(id, msg)| Some((id.parse::<u16>().ok()?, hex_decode(msg)?)));
+    let offer = match parsed {
+        Some((id, _)) => offers().lock().await.remove(&id),
+        None => None,
+    };
+    let (Some((_, msg_a)), Some(offer)) = (parsed, offer) else {
+        remote_stream.write_line(pair::PAIR_FAILED).await?;
+        return Ok(());
+    };
+    let res = respond_pairing(remote_stream, peer_addr, &own, &offer.password, &msg_a).await;
+    let paired = match res {
+        Ok(Ok(paired)) => {
+            log::info!("Paired with host `{}` ({})", paired.name, paired.addr);
+            Ok(paired)
+        }
+        Ok(Err(resp)) => {
+            let line = match resp {
+                LocalResponse::HostnameTaken => pair::NAME_TAKEN,
+                _ => pair::PAIR_FAILED,
+            };
+            remote_stream.write_line(line).await?;
+            Err(resp)
+        }
+        Err(e) => {
+            log::error!("Pairing with {} failed! Detail: {}", peer_addr, e);
+            Err(LocalResponse::PairFailed)
+        }
+    };
+    let _ = offer.done.send(paired);
+    Ok(())
+}
+
+async fn respond_pairing<S>(
+    remote_stream: &mut S,
+    peer_addr: SocketAddr,
+    own: &PairIdentity,
+    password: &str,
+    msg_a: &[u8],
+) -> error::Result<PairResult>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (spake, msg_b) = Spake2::<Ed25519Group>::start_b(
+        &Password::new(password.as_bytes()),
+        &SpakeIdentity::new(INITIATOR_ID),
+        &SpakeIdentity::new(RESPONDER_ID),
+    );
+    let Ok(key) = spake.finish(msg_a) else {
+        return Ok(Err(LocalResponse::PairFailed));
+    };
+    remote_stream
+        .write_line(smol_str::format_smolstr!(
+            "{} {} {}",
+            pair::PAIR_MSG,
+            hex_string(&msg_b),
+            own.to_line(&key, RESPONDER_ID)
+        ))
+        .await?;
+    let mut reader = BufReader::new(remote_stream).take(PAIR_LINE_LIMIT);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.trim().split(consts::STARTLINE_SEP);
+    match parts.next() {
+        Some(pair::PAIR_ID) => (),
+        // The initiator has another host of our name.
+        Some(pair::NAME_TAKEN) => return Ok(Err(LocalResponse::HostnameTaken)),
+        _ => return Ok(Err(LocalResponse::PairFailed)),
+    }
+    let Some(peer) = PairIdentity::from_parts(parts, &key, INITIATOR_ID) else {
+        return Ok(Err(LocalResponse::PairFailed));
+    };
+    let Some(paired) = register_paired(&peer, handler::peer_addr_at(peer_addr, peer.port)).await?
+    else {
+        return Ok(Err(LocalResponse::HostnameTaken));
+    };
+    reader
+        .get_mut()
+        .get_mut()
+        .write_line(smol_str::format_smolstr!("{} {}", pair::PAIRED, own.name))
+        .await?;
+    Ok(Ok(paired))
+}
+
+/// Pairs with the daemon at `addr` which offered `code`, registering each other on both sides.
+/// A name already registered for another host on either side is refused.
+pub(crate) async fn pair_with(addr: SocketAddr, code: &str) -> error::Result<PairResult> {
+    let mut stream = tokio::time::timeout(consts::HOST_CHECK_TIMEOUT, TcpStream::connect(addr))
+        .await
+        .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+    let own = PairIdentity::own().await;
+    initiate_pairing(&mut stream, addr, &own, code).await
+}
+
+async fn initiate_pairing(
+    stream: &mut TcpStream,
+    addr: SocketAddr,
+    own: &PairIdentity,
+    code: &str,
+) -> error::Result<PairResult> {
+    let Some((id, password)) = parse_code(code) else {
+        return Ok(Err(LocalResponse::PairFailed));
+    };
+    let (spake, msg_a) = Spake2::<Ed25519Group>::start_a(
+        &Password::new(password.as_bytes()),
+        &SpakeIdentity::new(INITIATOR_ID),
+        &SpakeIdentity::new(RESPONDER_ID),
+    );
+    stream
+        .write_line(smol_str::format_smolstr!(
+            "{} {} {}",
+            pair::PAIR,
+            id,
+            hex_string(&msg_a)
+        ))
+        .await?;
+    let mut reader = BufReader::new(stream).take(PAIR_LINE_LIMIT);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.trim().split(consts::STARTLINE_SEP);
+    if parts.next() != Some(pair::PAIR_MSG) {
+        return Ok(Err(LocalResponse::PairFailed));
+    }
+    let Some(msg_b) = parts.next().and_then(hex_decode) else {
+        return Ok(Err(LocalResponse::PairFailed));
+    };
+    let Ok(key) = spake.finish(&msg_b) else {
+        return Ok(Err(LocalResponse::PairFailed));
+    };
+    let Some(peer) = PairIdentity::from_parts(parts, &key, RESPONDER_ID)
+        .filter(|peer| peer.public_key != own.public_key)
+    else {
+        return Ok(Err(LocalResponse::PairFailed));
+    };
+    // Checked before the responder registers us, it is checked again when registering.
+    let taken = name_taken(&*global::config_store().await.read().await, &peer);
+    if taken {
+        reader
+            .get_mut()
+            .get_mut()
+            .write_line(pair::NAME_TAKEN)
+            .await?;
+        return Ok(Err(LocalResponse::HostnameTaken));
+    }
+    reader
+        .get_mut()
+        .get_mut()
+        .write_line(smol_str::format_smolstr!(
+            "{} {}",
+            pair::PAIR_ID,
+            own.to_line(&key, INITIATOR_ID)
+        ))
+        .await?;
+    line.clear();
+    reader.set_limit(PAIR_LINE_LIMIT);
+    reader.read_line(&mut line).await?;
+    match line.trim().split(consts::STARTLINE_SEP).next() {
+        Some(pair::PAIRED) => (),
+        Some(pair::NAME_TAKEN) => return Ok(Err(LocalResponse::HostnameTaken)),
+        _ => return Ok(Err(LocalResponse::PairFailed)),
+    }
+    Ok(
+        register_paired(&peer, handler::peer_addr_at(addr, peer.port))
+            .await?
+            .ok_or(LocalResponse::HostnameTaken),
+    )
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod pairing_tests {
+    use smol_str::SmolStr;
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::{
+        answer_pair_request, create_offer, initiate_pairing, parse_code, PairIdentity, PairResult,
+        INITIATOR_ID, RESPONDER_ID,
+    };
+    use crate::{common::LocalResponse, global};
+
+    #[test]
+    fn parse_code_test() {
+        assert_eq!(parse_code("17-4821-9936"), Some((17, "4821-9936")));
+        assert_eq!(parse_code("x-4821"), None);
+        assert_eq!(parse_code("4821"), None);
+    }
+
+    #[test]
+    fn identity_mac_test() {
+        let identity = PairIdentity {
+            name: "pc".into(),
+            port: 10020,
+            public_key: "ab".repeat(32).into(),
+        };
+        let line = identity.to_line(b"key", INITIATOR_ID);
+        let parsed = PairIdentity::from_parts(line.split(' '), b"key", INITIATOR_ID).unwrap();
+        assert_eq!(parsed.name, "pc");
+        assert_eq!(parsed.port, 10020);
+        assert!(PairIdentity::from_parts(line.split(' '), b"other", INITIATOR_ID).is_none());
+        assert!(PairIdentity::from_parts(line.split(' '), b"key", RESPONDER_ID).is_none());
+    }
+
+    fn identity(name: &str, key: &str) -> PairIdentity {
+        PairIdentity {
+            name: name.into(),
+            port: 10020,
+            public_key: key.repeat(32).into(),
+        }
+    }
+
+    /// Pairs over a loopback connection, the hosts are registered with documentation addresses
+    /// so other tests never see them. Returns the results of the initiator and the responder.
+    async fn pair(initiator: PairIdentity, responder: PairIdentity) -> (PairResult, PairResult) {
+        let (code, done) = create_offer().await.unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let responding = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut line = String::new();
+            BufReader::new(&mut stream)
+                .read_line(&mut line)
+                .await
+                .unwrap();
+            let arg = line.trim().strip_prefix("PAIR ").unwrap().to_owned();
+            let peer_addr = "192.0.2.1:10020".parse().unwrap();
+            answer_pair_request(&mut stream, peer_addr, &arg, responder)
+                .await
+                .unwrap();
+        });
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let responder_addr = "192.0.2.2:10020".parse().unwrap();
+        let initiated = initiate_pairing(&mut stream, responder_addr, &initiator, &code)
+            .await
+            .unwrap();
+        responding.await.unwrap();
+        (initiated, done.await.unwrap())
+    }
+
+    async fn host_key(name: &str) -> Option<SmolStr> {
+        global::config_store()
+            .await
+            .read()
+            .await
+            .get_host(name)
+            .and_then(|host| host.public_key.clone())
+    }
+
+    #[tokio::test]
+    async fn pair_loopback_test() {
+        global::init_test_config_store().await;
+        let (initiated, responded) = pair(identity("desk", "ab"), identity("laptop", "cd")).await;
+        assert_eq!(initiated.unwrap().name, "laptop");
+        assert_eq!(responded.unwrap().name, "desk");
+        assert_eq!(host_key("laptop").await.unwrap(), "cd".repeat(32));
+
+        // Pairing again with the same hosts is fine.
+        let (initiated, responded) = pair(identity("desk", "ab"), identity("laptop", "cd")).await;
+        assert!(initiated.is_ok() && responded.is_ok());
+
+        // Another host named like a registered one is refused by either side.
+        let (initiated, responded) = pair(identity("pc", "12"), identity("laptop", "ef")).await;
+        assert!(matches!(initiated, Err(LocalResponse::HostnameTaken)));
+        assert!(matches!(responded, Err(LocalResponse::HostnameTaken)));
+        let (initiated, responded) = pair(identity("desk", "34"), identity("nas", "56")).await;
+        assert!(matches!(initiated, Err(LocalResponse::HostnameTaken)));
+        assert!(matches!(responded, Err(LocalResponse::HostnameTaken)));
+        assert_eq!(host_key("laptop").await.unwrap(), "cd".repeat(32));
+        assert_eq!(host_key("desk").await.unwrap(), "ab".repeat(32));
+        assert!(host_key("pc").await.is_none() && host_key("nas").await.is_none());
+    }
+}
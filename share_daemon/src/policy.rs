@@ -0,0 +1,123 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use smol_str::SmolStr;
+
+/// Keeps a transfer under a bandwidth limit by sleeping whenever it gets ahead of it.
+pub(crate) struct Throttle {
+    limit: Option<u64>,
+    start: Instant,
+    bytes: u64,
+}
+
+impl Throttle {
+    /// `limit` is in bytes per second, `None` (or zero) does not limit anything.
+    pub(crate) fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit: limit.filter(|l| *l > 0),
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    pub(crate) async fn consume(&mut self, size: usize) {
+        let Some(limit) = self.limit else {
+            return;
+        };
+        self.bytes += size as u64;
+        let expected = Duration::from_secs_f64(self.bytes as f64 / limit as f64);
+        let elapsed = self.start.elapsed();
+        if expected > elapsed {
+            tokio::time::sleep(expected - elapsed).await;
+        }
+    }
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() / 86400)
+        .unwrap_or_default()
+}
+
+/// The bytes each host sent today, kept in memory only.
+fn quota_usage() -> &'static Mutex<HashMap<SmolStr, (u64, u64)>> {
+    static QUOTA_USAGE: OnceLock<Mutex<HashMap<SmolStr, (u64, u64)>>> = OnceLock::new();
+    QUOTA_USAGE.get_or_init(Default::default)
+}
+
+/// Bytes counted against the daily quota of a host, they are given back when dropped unless the
+/// transfer completed.
+pub(crate) struct QuotaReservation {
+    host: SmolStr,
+    day: u64,
+    size: u64,
+}
+
+impl QuotaReservation {
+    /// Keeps the bytes counted, the file arrived.
+    pub(crate) fn keep(mut self) {
+        self.size = 0;
+    }
+}
+
+impl Drop for QuotaReservation {
+    fn drop(&mut self) {
+        if self.size == 0 {
+            return;
+        }
+        if let Some((day, used)) = quota_usage().lock().unwrap().get_mut(&self.host) {
+            if *day == self.day {
+                *used = used.saturating_sub(self.size);
+            }
+        }
+    }
+}
+
+/// Counts `size` bytes against the daily quota of `host`, unless they would exceed it.
+pub(crate) fn reserve_quota(host: &str, quota: Option<u64>, size: u64) -> Option<QuotaReservation> {
+    let today = today();
+    let Some(quota) = quota else {
+        return Some(QuotaReservation {
+            host: host.into(),
+            day: today,
+            size: 0,
+        });
+    };
+    let mut usage = quota_usage().lock().unwrap();
+    let (day, used) = usage.entry(host.into()).or_insert((today, 0));
+    if *day != today {
+        *day = today;
+        *used = 0;
+    }
+    if used.saturating_add(size) > quota {
+        return None;
+    }
+    *used += size;
+    Some(QuotaReservation {
+        host: host.into(),
+        day: today,
+        size,
+    })
+}
+
+#[cfg(test)]
+mod policy_tests {
+    use super::reserve_quota;
+
+    #[test]
+    fn reserve_quota_test() {
+        assert!(reserve_quota("a", None, u64::MAX).is_some());
+        reserve_quota("a", Some(10), 6).unwrap().keep();
+        assert!(reserve_quota("a", Some(10), 6).is_none());
+        reserve_quota("a", Some(10), 4).unwrap().keep();
+        reserve_quota("b", Some(10), 10).unwrap().keep();
+
+        // A failed transfer gives its bytes back.
+        drop(reserve_quota("c", Some(10), 10).unwrap());
+        assert!(reserve_quota("c", Some(10), 10).is_some());
+    }
+}
@@ -0,0 +1,356 @@
+use std::{collections::HashMap, net::SocketAddr, sync::OnceLock, time::Duration};
+
+use smol_str::{SmolStr, ToSmolStr};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader},
+    sync::{oneshot, Mutex},
+};
+
+use crate::{
+    common::{LocalResponse, RemoteResponse, Response, StartLine},
+    config::{Config, HostAddr},
+    consts, error, global,
+    handler::{self, WriteLine},
+    request_tag, resolve,
+};
+
+/// How long a registration request waits for the user of the peer.
+pub(crate) const REG_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+/// Requests beyond this many pending ones are rejected right away.
+const MAX_PENDING_REQUESTS: usize = 16;
+
+struct PendingRequest {
+    addr: SocketAddr,
+    decided: oneshot::Sender<bool>,
+}
+
+/// The requests waiting for approval, by the name the requesting host gave itself.
+fn pending() -> &'static Mutex<HashMap<SmolStr, PendingRequest>> {
+    static PENDING: OnceLock<Mutex<HashMap<SmolStr, PendingRequest>>> = OnceLock::new();
+    PENDING.get_or_init(Default::default)
+}
+
+pub(crate) async fn pending_requests() -> Vec<(SmolStr, SocketAddr)> {
+    let mut pending = pending().lock().await;
+    pending.retain(|_, req| !req.decided.is_closed());
+    pending
+        .iter()
+        .map(|(name, req)| (name.clone(), req.addr))
+        .collect()
+}
+
+/// A request answered with `REG_PENDING`, the decision is written by [`PendingRegistration::answer`]
+/// which waits outside of the workers.
+pub(crate) struct PendingRegistration {
+    decided: oneshot::Receiver<bool>,
+}
+
+impl PendingRegistration {
+    /// Waits until the local user decides, the requester hangs up or the request expires.
+    pub(crate) async fn answer<S>(self, remote_stream: &mut S) -> error::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut hang_up_buf = [0_u8; 1];
+        let accepted = tokio::time::timeout(REG_REQUEST_TIMEOUT, async {
+            tokio::select! {
+                decided = self.decided => decided.unwrap_or(false),
+                _ = remote_stream.read(&mut hang_up_buf) => false,
+            }
+        })
+        .await
+        .unwrap_or(false);
+        let resp = if accepted {
+            RemoteResponse::RegAccepted
+        } else {
+            RemoteResponse::RegRejected
+        };
+        remote_stream.write_line(resp.to_str_unchecked()).await?;
+        Ok(())
+    }
+}
+
+/// Answers a `REG <name> <port>` request sent to the daemon port, a valid one stays pending.
+pub(crate) async fn handle_reg_request<S>(
+    remote_stream: &mut S,
+    peer_addr: SocketAddr,
+    arg: &str,
+) -> error::Result<Option<PendingRegistration>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let parsed = arg
+        .split_once(consts::STARTLINE_SEP)
+        .and_then(|(name, port)| Some((name, port.parse::<u16>().ok()?)))
+        .filter(|(name, _)| Config::check_hostname_valid(name));
+    let Some((name, port)) = parsed else {
+        remote_stream
+            .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
+            .await?;
+        return Ok(None);
+    };
+    let (decided, decided_rx) = oneshot::channel();
+    {
+        let mut pending = pending().lock().await;
+        pending.retain(|_, req| !req.decided.is_closed());
+        let addr = handler::peer_addr_at(peer_addr, port);
+        // Another host must not swap in its address before the user decides.
+        let refused = match pending.get(name) {
+            Some(req) => req.addr != addr,
+            None => pending.len() >= MAX_PENDING_REQUESTS,
+        };
+        if refused {
+            remote_stream
+                .write_line(RemoteResponse::RegRejected.to_str_unchecked())
+                .await?;
+            return Ok(None);
+        }
+        pending.insert(name.into(), PendingRequest { addr, decided });
+    }
+    log::info!("Host `{}` ({}) asks to be registered", name, peer_addr);
+    remote_stream
+        .write_line(RemoteResponse::RegPending.to_str_unchecked())
+        .await?;
+    Ok(Some(PendingRegistration {
+        decided: decided_rx,
+    }))
+}
+
+/// Approves or rejects the pending request of `name`. An approved host is registered here before
+/// the requester is told, so a failed registration rejects it. So does a name registered for a
+/// host at another address.
+pub(crate) async fn decide<S>(
+    local_stream: &mut S,
+    name: &str,
+    approve: bool,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let req = pending()
+        .lock()
+        .await
+        .remove(name)
+        .filter(|req| !req.decided.is_closed());
+    let Some(req) = req else {
+        return local_stream
+            .write_line(LocalResponse::UnknownRegRequest.to_str_unchecked())
+            .await;
+    };
+    let taken = approve
+        && global::config_store()
+            .await
+            .read()
+            .await
+            .get_host(name)
+            .is_some_and(|host| !host.has_ip(req.addr.ip()));
+    let accepted = if taken {
+        log::warn!(
+            "Refused to register `{}` ({}), the name is taken by another host",
+            name,
+            req.addr
+        );
+        local_stream
+            .write_line(LocalResponse::HostnameTaken.to_str_unchecked())
+            .await?;
+        false
+    } else if approve {
+        handler::register_to_local(local_stream, name, req.addr.into()).await?
+    } else {
+        local_stream
+            .write_line(LocalResponse::RegRequestRejected.to_str_unchecked())
+            .await?;
+        false
+    };
+    let _ = req.decided.send(accepted);
+    Ok(())
+}
+
+/// Asks the daemon at `addr` to register this one and registers it as `hostname` once accepted.
+/// The local process gets `R_REG_PENDING` as soon as the peer waits for its user.
+pub(crate) async fn request_registration<S>(
+    local_stream: &mut S,
+    hostname: &str,
+    addr: HostAddr,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    if !Config::check_hostname_valid(hostname) {
+        return local_stream
+            .write_line(Response::InvalidHostname.to_str_unchecked())
+            .await;
+    }
+    let Ok(mut remote_stream) = resolve::connect(std::slice::from_ref(&addr)).await else {
+        return local_stream
+            .write_line(LocalResponse::UnreachableAddress(addr).to_smolstr())
+            .await;
+    };
+    let (own_name, own_port) = {
+        let config = global::config_store().await.read().await;
+        (
+            config.host_name().to_smolstr(),
+            config.listener_addr().port(),
+        )
+    };
+    remote_stream
+        .write_line(smol_str::format_smolstr!(
+            "{} {} {}",
+            request_tag::remote::REG,
+            own_name,
+            own_port
+        ))
+        .await?;
+    let mut remote_reader = BufReader::new(remote_stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = tokio::time::timeout(
+            REG_REQUEST_TIMEOUT + consts::HOST_CHECK_TIMEOUT,
+            (&mut remote_reader)
+                .take(StartLine::LENGTH_LIMIT)
+                .read_line(&mut line),
+        )
+        .await;
+        let resp = match read {
+            Ok(Ok(size)) if size != 0 => line.parse::<RemoteResponse>().ok(),
+            _ => None,
+        };
+        match resp {
+            Some(RemoteResponse::RegPending) => {
+                local_stream
+                    .write_line(LocalResponse::RemoteRegPending.to_str_unchecked())
+                    .await?
+            }
+            Some(RemoteResponse::RegAccepted) => {
+                handler::register_to_local(local_stream, hostname, addr).await?;
+                return Ok(());
+            }
+            Some(RemoteResponse::RegRejected) => {
+                return local_stream
+                    .write_line(LocalResponse::RemoteRegRejected.to_str_unchecked())
+                    .await
+            }
+            _ => {
+                return local_stream
+                    .write_line(LocalResponse::UnexpectedRemoteResponse.to_str_unchecked())
+                    .await
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod registration_tests {
+    use tokio::{
+        io::{AsyncBufReadExt, BufReader, DuplexStream},
+        task::JoinHandle,
+    };
+
+    use crate::{error, global};
+
+    /// Sends `REG <name> 10020` from `ip` and waits until it is pending.
+    async fn request(
+        name: &'static str,
+        ip: &str,
+    ) -> (BufReader<DuplexStream>, JoinHandle<error::Result<()>>) {
+        let (client, mut server) = tokio::io::duplex(256);
+        let peer_addr = format!("{}:50000", ip).parse().unwrap();
+        let arg = format!("{} 10020", name);
+        let handle = tokio::spawn(async move {
+            match super::handle_reg_request(&mut server, peer_addr, &arg).await? {
+                Some(pending) => pending.answer(&mut server).await,
+                None => Ok(()),
+            }
+        });
+        let mut client = BufReader::new(client);
+        let mut line = String::new();
+        client.read_line(&mut line).await.unwrap();
+        assert_eq!(line.trim(), "REG_PENDING");
+        (client, handle)
+    }
+
+    /// Decides on the request of `name`, returns the line for the local process and the one for
+    /// the requester.
+    async fn decide(
+        name: &str,
+        approve: bool,
+        (mut client, handle): (BufReader<DuplexStream>, JoinHandle<error::Result<()>>),
+    ) -> (String, String) {
+        let mut local = vec![];
+        super::decide(&mut local, name, approve).await.unwrap();
+        let mut line = String::new();
+        client.read_line(&mut line).await.unwrap();
+        handle.await.unwrap().unwrap();
+        (
+            String::from_utf8(local).unwrap().trim().into(),
+            line.trim().into(),
+        )
+    }
+
+    #[tokio::test]
+    async fn decide_request_test() {
+        // Documentation addresses, so other tests never see the registered hosts.
+        global::init_test_config_store().await;
+        let pending = request("pc", "192.0.2.1").await;
+        assert!(super::pending_requests()
+            .await
+            .contains(&("pc".into(), "192.0.2.1:10020".parse().unwrap())));
+        let decided = decide("pc", false, pending).await;
+        assert_eq!(
+            decided,
+            ("REG_REQUEST_REJECTED".into(), "REG_REJECTED".into())
+        );
+        assert!(!super::pending_requests()
+            .await
+            .iter()
+            .any(|(name, _)| name == "pc"));
+
+        let pending = request("pc", "192.0.2.1").await;
+        let (local, remote) = decide("pc", true, pending).await;
+        assert_eq!(remote, "REG_ACCEPTED", "{}", local);
+        let registered = global::config_store()
+            .await
+            .read()
+            .await
+            .get_addrs_by_name("pc")
+            .map(<[_]>::to_vec);
+        assert_eq!(registered, Some(vec!["192.0.2.1:10020".parse().unwrap()]));
+
+        // The same host may ask again, another one cannot take its name.
+        let pending = request("pc", "192.0.2.1").await;
+        assert_eq!(decide("pc", true, pending).await.1, "REG_ACCEPTED");
+        let pending = request("pc", "192.0.2.2").await;
+        let decided = decide("pc", true, pending).await;
+        assert_eq!(decided, ("HOSTNAME_TAKEN".into(), "REG_REJECTED".into()));
+    }
+
+    #[tokio::test]
+    async fn pending_request_kept_test() {
+        global::init_test_config_store().await;
+        let pending = request("tablet", "192.0.2.3").await;
+
+        // Another host asking under the same name is turned away.
+        let (mut client, mut server) = tokio::io::duplex(256);
+        let other = super::handle_reg_request(
+            &mut server,
+            "192.0.2.4:50000".parse().unwrap(),
+            "tablet 10020",
+        )
+        .await
+        .unwrap();
+        assert!(other.is_none());
+        let mut line = String::new();
+        BufReader::new(&mut client)
+            .read_line(&mut line)
+            .await
+            .unwrap();
+        assert_eq!(line.trim(), "REG_REJECTED");
+        assert!(super::pending_requests()
+            .await
+            .contains(&("tablet".into(), "192.0.2.3:10020".parse().unwrap())));
+
+        let decided = decide("tablet", false, pending).await;
+        assert_eq!(decided.1, "REG_REJECTED");
+    }
+}
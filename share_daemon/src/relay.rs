@@ -0,0 +1,182 @@
+use std::net::{IpAddr, SocketAddr};
+
+use smol_str::{SmolStr, ToSmolStr};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    common::{RemoteResponse, StartLine},
+    config::{Config, HostAddr},
+    consts,
+    error::{self, Error},
+    global,
+    handler::{self, Capabilities, WriteLine},
+    history::{self, HistoryEntry},
+    request_tag, resolve,
+    transfer::{Direction, Transfer},
+};
+
+/// Answers a `RELAY <target> <expected port>` request. The sender must be registered here and this
+/// daemon must be registered at the target, the data connection is then piped through to the
+/// target without being stored, so the progress and the final result of the sender are the
+/// target's.
+pub(crate) async fn handle_relay_request<S>(
+    remote_stream: &mut S,
+    peer_addr: SocketAddr,
+    arg: &str,
+) -> error::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let Some((target, port_str)) = arg.split_once(consts::STARTLINE_SEP) else {
+        remote_stream
+            .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
+            .await?;
+        return Ok(());
+    };
+    let Ok(expected_port) = port_str.parse::<u16>() else {
+        remote_stream
+            .write_line(RemoteResponse::InvalidPort.to_str_unchecked())
+            .await?;
+        return Ok(());
+    };
+    if let Err(resp) = handler::authorize_sender(peer_addr).await {
+        remote_stream.write_line(resp.to_str_unchecked()).await?;
+        return Ok(());
+    }
+    let (sender, target_addrs) = {
+        let config = global::config_store().await.read().await;
+        (
+            config
+                .get_name_by_ip(peer_addr.ip())
+                .map(SmolStr::from)
+                .unwrap_or_else(|| peer_addr.ip().to_canonical().to_smolstr()),
+            config.get_addrs_by_name(target).map(<[_]>::to_vec),
+        )
+    };
+    let Some(target_addrs) = target_addrs.filter(|_| Config::check_hostname_valid(target)) else {
+        remote_stream
+            .write_line(RemoteResponse::UnknownTarget.to_str_unchecked())
+            .await?;
+        return Ok(());
+    };
+    let (dest_addr, caps) = match request_target_port(&target_addrs, expected_port).await {
+        Ok(addr_caps) => addr_caps,
+        // The sender gets the answer of the target as if it had asked the target itself.
+        Err(Error::PeerRejected(resp)) => {
+            remote_stream.write_line(resp).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            log::error!(
+                "Relay to `{}` ({}) failed! Detail: {}",
+                target,
+                target_addrs[0],
+                e
+            );
+            remote_stream
+                .write_line(RemoteResponse::TargetUnreachable.to_str_unchecked())
+                .await?;
+            return Ok(());
+        }
+    };
+    let Some(listener) = handler::create_receive_listener(expected_port).await else {
+        remote_stream
+            .write_line(RemoteResponse::NoAvailablePort.to_str_unchecked())
+            .await?;
+        return Ok(());
+    };
+    let relay_port = listener.local_addr()?.port();
+    let target: SmolStr = target.into();
+    let transfer = Transfer::queue(
+        Direction::Relay,
+        &smol_str::format_smolstr!("{}->{}", sender, target),
+    );
+    tokio::spawn(async move {
+        match pipe_to_target(listener, peer_addr.ip(), dest_addr, &transfer).await {
+            Ok(bytes) => {
+                transfer.succeed();
+                log::info!("Relayed {} bytes from `{}` to `{}`", bytes, sender, target);
+                history::record(HistoryEntry::Relayed {
+                    sender: &sender,
+                    target: &target,
+                    bytes,
+                })
+                .await;
+            }
+            Err(e) => log::error!(
+                "Relay from `{}` to `{}` failed! Detail: {}",
+                sender,
+                target,
+                e
+            ),
+        }
+    });
+    remote_stream
+        .write_line(RemoteResponse::PortConfirm(relay_port).to_smolstr())
+        .await?;
+    remote_stream.write_line(caps.to_line()).await?;
+    Ok(())
+}
+
+/// Asks the target for a receive port like a sender would and returns the data address along with
+/// what the target supports, any other answer is returned as [`Error::PeerRejected`].
+async fn request_target_port(
+    target_addrs: &[HostAddr],
+    expected_port: u16,
+) -> error::Result<(SocketAddr, Capabilities)> {
+    let mut stream = resolve::connect(target_addrs).await?;
+    let target_addr = stream.peer_addr()?;
+    stream
+        .write_line(smol_str::format_smolstr!(
+            "{} {}",
+            request_tag::remote::PORT,
+            expected_port
+        ))
+        .await?;
+    let mut reader = BufReader::new(&mut stream).take(StartLine::LENGTH_LIMIT);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    match line.parse::<RemoteResponse>() {
+        Ok(RemoteResponse::PortConfirm(port)) => Ok((
+            handler::peer_addr_at(target_addr, port),
+            handler::read_capabilities(&mut reader).await,
+        )),
+        Ok(resp) => Err(Error::PeerRejected(resp.to_smolstr())),
+        Err(_) => Err(Error::Protocol(line.trim().into())),
+    }
+}
+
+/// Accepts the data connection of the sender and copies it to the target in both directions,
+/// returns the number of bytes sent to the target.
+async fn pipe_to_target(
+    listener: TcpListener,
+    sender_ip: IpAddr,
+    dest_addr: SocketAddr,
+    transfer: &Transfer,
+) -> error::Result<u64> {
+    let mut sender_stream = loop {
+        let (stream, addr) = tokio::time::timeout(consts::HOST_CHECK_TIMEOUT, listener.accept())
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        if addr.ip().to_canonical() == sender_ip.to_canonical() {
+            break stream;
+        }
+        log::warn!(
+            "Rejected a relay connection from unexpected address {}",
+            addr
+        );
+    };
+    let mut dest_stream = TcpStream::connect(dest_addr).await?;
+    transfer.start_file("", None);
+    tokio::select! {
+        res = tokio::io::copy_bidirectional(&mut sender_stream, &mut dest_stream) => Ok(res?.0),
+        _ = transfer.cancelled() => Err(std::io::Error::new(
+            std::io::ErrorKind::Interrupted,
+            "The relay was cancelled",
+        )
+        .into()),
+    }
+}
@@ -1,14 +0,0 @@
-pub mod local {
-    pub const SHARE: &str = "SHARE";
-    pub const REG: &str = "REG";
-}
-
-pub mod remote {
-    pub const PORT: &str = "PORT";
-}
-
-
-pub mod send_flag{
-    pub const SEND_START: &str = "SEND_START";
-    pub const SEND_END: &str = "SEND_END";
-}
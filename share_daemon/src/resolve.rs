@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use tokio::net::TcpStream;
+
+use crate::{config::HostAddr, consts, global};
+
+struct CacheEntry {
+    addrs: Vec<SocketAddr>,
+    expires: Instant,
+}
+
+fn cache() -> &'static Mutex<HashMap<HostAddr, CacheEntry>> {
+    static CACHE: OnceLock<Mutex<HashMap<HostAddr, CacheEntry>>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// The last resolution of `addr`, even if it expired.
+pub(crate) fn cached(addr: &HostAddr) -> Vec<SocketAddr> {
+    match addr {
+        HostAddr::Socket(a) => vec![*a],
+        HostAddr::Dns(..) => cache()
+            .lock()
+            .unwrap()
+            .get(addr)
+            .map(|entry| entry.addrs.clone())
+            .unwrap_or_default(),
+    }
+}
+
+/// Whether the host is at `ip`, DNS names are only matched against cached resolutions.
+pub(crate) fn matches_ip(addr: &HostAddr, ip: IpAddr) -> bool {
+    cached(addr)
+        .iter()
+        .any(|a| a.ip().to_canonical() == ip.to_canonical())
+}
+
+/// Resolves `addr`, DNS names are looked up again once their cache entry expired. A failed lookup
+/// is cached for a shorter time.
+pub(crate) async fn resolve(addr: &HostAddr) -> std::io::Result<Vec<SocketAddr>> {
+    let HostAddr::Dns(name, port) = addr else {
+        return Ok(cached(addr));
+    };
+    if let Some(entry) = cache().lock().unwrap().get(addr) {
+        if entry.expires > Instant::now() {
+            return Ok(entry.addrs.clone());
+        }
+    }
+    let res = tokio::net::lookup_host((name.as_str(), *port))
+        .await
+        .map(|addrs| addrs.collect::<Vec<_>>());
+    let (addrs, ttl) = match &res {
+        Ok(addrs) => (addrs.clone(), consts::DNS_CACHE_TTL),
+        Err(e) => {
+            log::warn!("Resolve `{}` failed! Detail: {}", addr, e);
+            (vec![], consts::DNS_NEGATIVE_CACHE_TTL)
+        }
+    };
+    cache().lock().unwrap().insert(
+        addr.clone(),
+        CacheEntry {
+            addrs,
+            expires: Instant::now() + ttl,
+        },
+    );
+    res
+}
+
+/// Resolves the DNS names of all registered hosts, so that peers can be matched against them.
+pub(crate) async fn refresh_registered() {
+    let dns_addrs = global::config_store().await.read().await.dns_addrs();
+    for addr in &dns_addrs {
+        let _ = resolve(addr).await;
+    }
+}
+
+/// Connects to the first reachable address of a host, trying every resolved address in order.
+pub(crate) async fn connect(addrs: &[HostAddr]) -> std::io::Result<TcpStream> {
+    let mut last_err = std::io::Error::from(std::io::ErrorKind::AddrNotAvailable);
+    for addr in addrs {
+        let resolved = match resolve(addr).await {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                last_err = e;
+                continue;
+            }
+        };
+        for a in resolved {
+            match tokio::time::timeout(consts::HOST_CHECK_TIMEOUT, TcpStream::connect(a)).await {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => last_err = e,
+                Err(_) => last_err = std::io::Error::from(std::io::ErrorKind::TimedOut),
+            }
+        }
+    }
+    Err(last_err)
+}
+
+#[cfg(test)]
+mod resolve_tests {
+    use crate::config::HostAddr;
+
+    #[tokio::test]
+    async fn resolve_localhost_test() {
+        let addr = HostAddr::Dns("localhost".into(), 10020);
+        assert!(super::cached(&addr).is_empty());
+        let resolved = super::resolve(&addr).await.unwrap();
+        assert!(!resolved.is_empty());
+        assert!(resolved
+            .iter()
+            .all(|a| a.ip().is_loopback() && a.port() == 10020));
+        assert_eq!(super::cached(&addr), resolved);
+        assert!(resolved.iter().all(|a| super::matches_ip(&addr, a.ip())));
+    }
+}
@@ -0,0 +1,698 @@
+//! JSON-RPC 2.0 over the IPC socket. A connection whose first line is a JSON object speaks this
+//! protocol: every line is a request, requests are answered in order and a request may be
+//! followed by notifications about its progress, carrying the request id as `request`. After
+//! `subscribe` the connection only streams `event` notifications.
+//!
+//! Requests are translated into the line protocol and answered by the same handlers, so both
+//! protocols always behave the same.
+
+use std::{net::SocketAddr, path::PathBuf};
+
+use fshare_proto::{Hostname, LocalRequest};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use smol_str::{SmolStr, ToSmolStr};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::broadcast,
+};
+
+use crate::{
+    common::{LocalResponse, RemoteResponse, Response},
+    config::HostAddr,
+    events::{self, Event},
+    handler::{self, WriteLine},
+    request_tag,
+};
+
+/// Bumped whenever a method or a result changes incompatibly, see the `version` method.
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+const RPC_LINE_LIMIT: u64 = 64 * 1024;
+const BRIDGE_BUFFER_SIZE: usize = 16 * 1024;
+
+pub(crate) const PARSE_ERROR: i64 = -32700;
+pub(crate) const INVALID_REQUEST: i64 = -32600;
+pub(crate) const METHOD_NOT_FOUND: i64 = -32601;
+pub(crate) const INVALID_PARAMS: i64 = -32602;
+pub(crate) const INTERNAL_ERROR: i64 = -32603;
+/// The host, peer or registration request is not known.
+pub(crate) const NOT_FOUND: i64 = -32001;
+pub(crate) const UNREACHABLE: i64 = -32002;
+/// The peer refused the request.
+pub(crate) const REJECTED: i64 = -32003;
+/// The request was well formed but its content is not acceptable, e.g. a missing file.
+pub(crate) const INVALID_INPUT: i64 = -32004;
+pub(crate) const FAILED: i64 = -32005;
+pub(crate) const CANCELLED: i64 = -32006;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: SmolStr,
+    /// Requests without an id are notifications and are not answered.
+    #[serde(default)]
+    id: Option<Value>,
+    method: SmolStr,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse<'a> {
+    jsonrpc: &'static str,
+    id: &'a Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcNotification<'a> {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: Value,
+    /// The id of the request the notification belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request: Option<&'a Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct RpcError {
+    pub code: i64,
+    pub message: SmolStr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<ErrorData>,
+}
+
+/// The line the daemon answered with, for clients which want to tell errors of a category apart.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ErrorData {
+    pub response: SmolStr,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<SmolStr>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn from_response(resp: &Response) -> Self {
+        let (code, message) = match resp {
+            Response::InvalidHostname => (INVALID_INPUT, "Invalid host name"),
+            Response::Remote(RemoteResponse::InvalidRequest) => {
+                (INVALID_PARAMS, "The daemon rejected the request")
+            }
+            Response::Local(resp) => describe(resp),
+            _ => (FAILED, "Unexpected response"),
+        };
+        Self {
+            code,
+            message: message.into(),
+            data: Some(ErrorData {
+                response: resp.to_smolstr(),
+            }),
+        }
+    }
+}
+
+fn describe(resp: &LocalResponse) -> (i64, &'static str) {
+    let code = match resp {
+        LocalResponse::RemoteUnregistered
+        | LocalResponse::PairFailed
+        | LocalResponse::RemoteNotAccepted
+        | LocalResponse::RemoteQuotaExceeded(_)
+        | LocalResponse::RemoteRegRejected => REJECTED,
+        LocalResponse::UnreachableAddress(_) | LocalResponse::RelayTargetUnreachable => UNREACHABLE,
+        LocalResponse::UnregisteredHostname
+        | LocalResponse::UnknownPeer
+        | LocalResponse::RelayUnknownTarget
+        | LocalResponse::UnknownRegRequest
+        | LocalResponse::UnknownTransfer => NOT_FOUND,
+        LocalResponse::AnyPathInvalid | LocalResponse::HostnameTaken => INVALID_INPUT,
+        LocalResponse::Cancelled => CANCELLED,
+        _ => FAILED,
+    };
+    (code, resp.description())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ShareParams {
+    /// Registered host names, `<target>@<relay>` sends through a relay.
+    hosts: Vec<SmolStr>,
+    paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegisterParams {
+    name: Hostname,
+    addr: HostAddr,
+    /// Ask the peer to confirm the registration first.
+    #[serde(default)]
+    remote: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NameParams {
+    name: Hostname,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RenameParams {
+    name: Hostname,
+    new_name: Hostname,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetAddrParams {
+    name: Hostname,
+    addrs: Vec<HostAddr>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CancelParams {
+    transfer: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyncParams {
+    host: Hostname,
+    dir: PathBuf,
+    /// Also delete the files the directory no longer has on the host.
+    #[serde(default)]
+    delete: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PairParams {
+    code: SmolStr,
+    addr: SocketAddr,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Methods without parameters may get `null` or nothing at all.
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_smolstr()))
+}
+
+/// Translates a call into a request of the line protocol.
+fn text_request(method: &str, params_value: Value) -> Result<String, RpcError> {
+    let request = match method {
+        "status" => LocalRequest::Status,
+        "hosts" => LocalRequest::Hosts,
+        "peers" => LocalRequest::Peers,
+        "registration_requests" => LocalRequest::RegistrationRequests,
+        "pair_offer" => LocalRequest::PairOffer,
+        "share" => {
+            let p: ShareParams = params(params_value)?;
+            LocalRequest::Share {
+                hosts: p.hosts,
+                paths: p.paths,
+            }
+        }
+        "register" => {
+            let p: RegisterParams = params(params_value)?;
+            LocalRequest::Register {
+                name: p.name,
+                addr: p.addr,
+                remote: p.remote,
+            }
+        }
+        "unregister" | "register_peer" | "approve_registration" | "reject_registration" => {
+            let p: NameParams = params(params_value)?;
+            match method {
+                "unregister" => LocalRequest::Unregister(p.name),
+                "register_peer" => LocalRequest::RegisterPeer(p.name),
+                "approve_registration" => LocalRequest::ApproveRegistration(p.name),
+                _ => LocalRequest::RejectRegistration(p.name),
+            }
+        }
+        "rename" => {
+            let p: RenameParams = params(params_value)?;
+            LocalRequest::Rename(p.name, p.new_name)
+        }
+        "set_addr" => {
+            let p: SetAddrParams = params(params_value)?;
+            LocalRequest::SetAddr(p.name, p.addrs)
+        }
+        "pair" => {
+            let p: PairParams = params(params_value)?;
+            LocalRequest::Pair {
+                code: p.code,
+                addr: p.addr,
+            }
+        }
+        "sync" => {
+            let p: SyncParams = params(params_value)?;
+            LocalRequest::Sync {
+                host: p.host,
+                dir: p.dir,
+                delete: p.delete,
+            }
+        }
+        "cancel" => {
+            let p: CancelParams = params(params_value)?;
+            LocalRequest::Cancel(p.transfer)
+        }
+        _ => return Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+    };
+    request
+        .check()
+        .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_smolstr()))?;
+    Ok(request.encode())
+}
+
+enum Step {
+    Continue,
+    Notify(&'static str, Value),
+    Done(Result<Value, RpcError>),
+}
+
+/// Turns the lines answering one request into notifications and a result.
+struct Reply {
+    status: bool,
+    /// A `CANCELLED` answers `cancel`, anywhere else the request was cancelled.
+    cancel: bool,
+    /// The destination of a `share` to a single host.
+    share_host: Option<SmolStr>,
+    sending: bool,
+    items: Vec<Value>,
+    status_values: serde_json::Map<String, Value>,
+}
+
+impl Reply {
+    fn new(method: &str, params: &Value) -> Self {
+        let share_host = match (method, params["hosts"].as_array()) {
+            ("share", Some(hosts)) if hosts.len() == 1 => hosts[0].as_str().map(Into::into),
+            _ => None,
+        };
+        Self {
+            status: method == "status",
+            cancel: method == "cancel",
+            share_host,
+            sending: false,
+            items: vec![],
+            status_values: Default::default(),
+        }
+    }
+
+    fn step(&mut self, line: &str) -> Step {
+        let line = line.trim();
+        if line.is_empty() || line == request_tag::send_flag::SEND_END {
+            return Step::Continue;
+        }
+        if line == request_tag::send_flag::SEND_START {
+            self.sending = true;
+            return Step::Continue;
+        }
+        let resp = match line.parse::<Response>() {
+            Ok(Response::Local(resp)) => resp,
+            Ok(Response::RegisterSucceeded) => return Step::Done(Ok(json!({ "replaced": null }))),
+            Ok(resp) => return Step::Done(Err(RpcError::from_response(&resp))),
+            Err(_) => {
+                log::error!("Unexpected line `{}` from the request handler", line);
+                return Step::Done(Err(RpcError::new(INTERNAL_ERROR, "Unexpected response")));
+            }
+        };
+        match resp {
+            LocalResponse::Progress(fraction) => {
+                Step::Notify("progress", json!({ "fraction": fraction }))
+            }
+            LocalResponse::FileInfo(file, size) => {
+                Step::Notify("file_started", json!({ "file": file, "size": size }))
+            }
+            LocalResponse::FileSent(file) => Step::Notify("file_sent", json!({ "file": file })),
+            LocalResponse::FileFailed(file) => Step::Notify("file_failed", json!({ "file": file })),
+            LocalResponse::Host(host, inner) => match *inner {
+                LocalResponse::FileSent(file) => {
+                    Step::Notify("file_sent", json!({ "host": host, "file": file }))
+                }
+                LocalResponse::FileFailed(file) => {
+                    Step::Notify("file_failed", json!({ "host": host, "file": file }))
+                }
+                inner => {
+                    self.items.push(host_result(&host, inner));
+                    Step::Continue
+                }
+            },
+            LocalResponse::TransferId(id) => Step::Notify("transfer", json!({ "transfer": id })),
+            LocalResponse::Cancelled if self.cancel => Step::Done(Ok(json!({}))),
+            LocalResponse::PairCode(code) => Step::Notify("pair_code", json!({ "code": code })),
+            LocalResponse::RemoteRegPending => Step::Notify("registration_pending", json!({})),
+            LocalResponse::Peer(name, addr, fingerprint) => {
+                self.items.push(json!({
+                    "name": name,
+                    "addr": addr,
+                    "fingerprint": fingerprint,
+                }));
+                Step::Continue
+            }
+            LocalResponse::RegRequest(name, addr) => {
+                self.items.push(json!({ "name": name, "addr": addr }));
+                Step::Continue
+            }
+            LocalResponse::RegHost(host) => {
+                self.items.push(json!(host));
+                Step::Continue
+            }
+            LocalResponse::Status(key, value) => {
+                let value = value
+                    .parse::<u64>()
+                    .map_or_else(|_| json!(value), |n| json!(n));
+                self.status_values.insert(key.into(), value);
+                Step::Continue
+            }
+            LocalResponse::Transfer(info) => {
+                self.items.push(json!(info));
+                Step::Continue
+            }
+            LocalResponse::ListEnd => {
+                let items = Value::Array(std::mem::take(&mut self.items));
+                if self.status {
+                    let mut status = std::mem::take(&mut self.status_values);
+                    status.insert("transfers".into(), items);
+                    return Step::Done(Ok(Value::Object(status)));
+                }
+                if self.sending {
+                    return Step::Done(Ok(json!({ "hosts": items })));
+                }
+                Step::Done(Ok(items))
+            }
+            resp => match &self.share_host {
+                // Once files are being sent, the outcome belongs to the host.
+                Some(host) if self.sending => {
+                    Step::Done(Ok(json!({ "hosts": [host_result(host, resp)] })))
+                }
+                _ => Step::Done(outcome(resp)),
+            },
+        }
+    }
+}
+
+fn outcome(resp: LocalResponse) -> Result<Value, RpcError> {
+    match resp {
+        LocalResponse::AllFilesSucceeded => Ok(json!({ "all_succeeded": true })),
+        LocalResponse::FilesSucceeded(count) => {
+            Ok(json!({ "all_succeeded": false, "files_succeeded": count }))
+        }
+        LocalResponse::ReplacedAddress(addr) => Ok(json!({ "replaced": addr })),
+        LocalResponse::Paired(name, addr) => Ok(json!({ "name": name, "addr": addr })),
+        LocalResponse::Synced(sent, deleted) => Ok(json!({ "sent": sent, "deleted": deleted })),
+        LocalResponse::HostUpdated | LocalResponse::RegRequestRejected => Ok(json!({})),
+        resp => Err(RpcError::from_response(&Response::Local(resp))),
+    }
+}
+
+fn host_result(host: &str, resp: LocalResponse) -> Value {
+    match outcome(resp) {
+        Ok(result) => json!({ "host": host, "result": result }),
+        Err(error) => json!({ "host": host, "error": error }),
+    }
+}
+
+/// Answers the request by running it through the line protocol handler.
+async fn call<W>(
+    writer: &mut W,
+    id: Option<&Value>,
+    method: &str,
+    params: Value,
+) -> std::io::Result<Result<Value, RpcError>>
+where
+    W: AsyncWrite + Unpin,
+{
+    if method == "version" {
+        return Ok(Ok(json!({
+            "protocol": PROTOCOL_VERSION,
+            "version": env!("CARGO_PKG_VERSION"),
+        })));
+    }
+    let mut reply = Reply::new(method, &params);
+    let request = match text_request(method, params) {
+        Ok(request) => request,
+        Err(e) => return Ok(Err(e)),
+    };
+    let (mut client, server) = tokio::io::duplex(BRIDGE_BUFFER_SIZE);
+    // The handler runs in this task, it is boxed as it may serve JSON-RPC itself.
+    let handle = Box::pin(handler::handle_local(server));
+    let answer = async move {
+        client.write_all(request.as_bytes()).await?;
+        let mut lines = BufReader::new(client).lines();
+        while let Some(line) = lines.next_line().await? {
+            match reply.step(&line) {
+                Step::Continue => (),
+                Step::Notify(method, params) => {
+                    if id.is_some() {
+                        notify(writer, method, params, id).await?;
+                    }
+                }
+                Step::Done(res) => return Ok(res),
+            }
+        }
+        Ok(Err(RpcError::new(INTERNAL_ERROR, "No response")))
+    };
+    let (handled, answered) = tokio::join!(handle, answer);
+    if let Err(e) = handled {
+        log::error!("Handle JSON-RPC request failed! Detail: {}", e);
+    }
+    answered
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+async fn respond<W>(writer: &mut W, id: &Value, res: Result<Value, RpcError>) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let (result, error) = match res {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    let resp = RpcResponse {
+        jsonrpc: "2.0",
+        id,
+        result,
+        error,
+    };
+    writer.write_line(to_json(&resp)).await
+}
+
+async fn notify<W>(
+    writer: &mut W,
+    method: &'static str,
+    params: Value,
+    request: Option<&Value>,
+) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let notification = RpcNotification {
+        jsonrpc: "2.0",
+        method,
+        params,
+        request,
+    };
+    writer.write_line(to_json(&notification)).await
+}
+
+/// An answered `subscribe` request, the connection serves no further requests. The events are
+/// streamed by the caller of [`serve`], on a task of its own since this lasts as long as the
+/// client wants.
+pub(crate) struct Subscription {
+    id: Option<Value>,
+    events: broadcast::Receiver<Event>,
+}
+
+impl Subscription {
+    /// Answers `subscribe`, events from now on are streamed.
+    async fn start<W>(writer: &mut W, id: Option<Value>) -> std::io::Result<Self>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let events = events::subscribe();
+        if let Some(id) = &id {
+            respond(writer, id, Ok(json!({}))).await?;
+        }
+        Ok(Self { id, events })
+    }
+
+    /// Sends an `event` notification for every event until the client hangs up.
+    pub(crate) async fn stream<S>(mut self, stream: &mut S) -> std::io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut hang_up_buf = [0_u8; 64];
+        loop {
+            let event = tokio::select! {
+                event = events::next(&mut self.events) => event,
+                read = stream.read(&mut hang_up_buf) => match read {
+                    Ok(0) | Err(_) => return Ok(()),
+                    Ok(_) => continue,
+                },
+            };
+            notify(stream, "event", json!(event), self.id.as_ref()).await?;
+        }
+    }
+}
+
+async fn handle_line<S>(
+    reader: &mut BufReader<&mut S>,
+    line: &str,
+) -> std::io::Result<Option<Subscription>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = match serde_json::from_str::<Value>(line) {
+        Ok(value) => serde_json::from_value::<RpcRequest>(value)
+            .ok()
+            .filter(|req| req.jsonrpc == "2.0")
+            .ok_or_else(|| RpcError::new(INVALID_REQUEST, "Invalid request")),
+        Err(_) => Err(RpcError::new(PARSE_ERROR, "Parse error")),
+    };
+    match request {
+        Ok(req) if req.method == "subscribe" => Subscription::start(reader.get_mut(), req.id)
+            .await
+            .map(Some),
+        Ok(req) => {
+            let writer = reader.get_mut();
+            let res = call(writer, req.id.as_ref(), &req.method, req.params).await?;
+            if let Some(id) = req.id {
+                respond(writer, &id, res).await?;
+            }
+            Ok(None)
+        }
+        Err(e) => respond(reader.get_mut(), &Value::Null, Err(e))
+            .await
+            .map(|_| None),
+    }
+}
+
+/// Serves JSON-RPC requests until the client hangs up or subscribes, `line` is the first one as
+/// read so far.
+pub(crate) async fn serve<S>(
+    mut reader: BufReader<&mut S>,
+    mut line: String,
+) -> std::io::Result<Option<Subscription>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        if !line.ends_with('\n') {
+            // The first line may have been cut at the limit of the line protocol.
+            let limit = RPC_LINE_LIMIT.saturating_sub(line.len() as u64);
+            (&mut reader).take(limit).read_line(&mut line).await?;
+            if line.len() as u64 >= RPC_LINE_LIMIT && !line.ends_with('\n') {
+                let err = RpcError::new(INVALID_REQUEST, "Request too long");
+                return respond(reader.get_mut(), &Value::Null, Err(err))
+                    .await
+                    .map(|_| None);
+            }
+        }
+        if !line.trim().is_empty() {
+            if let Some(subscription) = handle_line(&mut reader, line.trim()).await? {
+                return Ok(Some(subscription));
+            }
+        }
+        line.clear();
+        if (&mut reader)
+            .take(RPC_LINE_LIMIT)
+            .read_line(&mut line)
+            .await?
+            == 0
+        {
+            return Ok(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod rpc_tests {
+    use serde_json::json;
+
+    use super::{Reply, Step};
+
+    fn run(reply: &mut Reply, lines: &[&str]) -> Vec<Step> {
+        lines.iter().map(|line| reply.step(line)).collect()
+    }
+
+    #[test]
+    fn share_reply_test() {
+        let request = super::text_request(
+            "share",
+            json!({ "hosts": ["one", "two"], "paths": ["/tmp/a.txt"] }),
+        )
+        .unwrap();
+        assert_eq!(request, "SHARE one,two\r\n/tmp/a.txt\r\n\r\n");
+        let params = json!({ "hosts": ["one", "two"], "paths": ["/tmp/a.txt"] });
+        let mut reply = Reply::new("share", &params);
+        let steps = run(
+            &mut reply,
+            &[
+                "SEND_START",
+                "",
+                "HOST two UNREACHABLE 127.0.0.1:13000",
+                "PROGRESS 0.5",
+                "HOST one FILE_SENT a.txt",
+                "SEND_END",
+                "HOST one ALL_FILES_SUCCEEDED",
+                "LIST_END",
+            ],
+        );
+        let Step::Notify("progress", progress) = &steps[3] else {
+            panic!("expected a progress notification");
+        };
+        assert_eq!(progress, &json!({ "fraction": 0.5 }));
+        assert!(matches!(&steps[4], Step::Notify("file_sent", _)));
+        let Step::Done(Ok(result)) = &steps[7] else {
+            panic!("expected a result");
+        };
+        assert_eq!(result["hosts"][0]["host"], "two");
+        assert_eq!(result["hosts"][0]["error"]["code"], super::UNREACHABLE);
+        assert_eq!(
+            result["hosts"][1],
+            json!({ "host": "one", "result": { "all_succeeded": true } })
+        );
+    }
+
+    #[test]
+    fn sync_reply_test() {
+        let params = json!({ "host": "pc", "dir": "/tmp/photos", "delete": true });
+        let request = super::text_request("sync", params.clone()).unwrap();
+        assert_eq!(request, "SYNC pc delete\r\n/tmp/photos\r\n");
+        let mut reply = Reply::new("sync", &params);
+        let steps = run(&mut reply, &["TRANSFER_ID 3", "PROGRESS 0.5", "SYNCED 2 1"]);
+        assert!(matches!(&steps[0], Step::Notify("transfer", _)));
+        let Step::Done(Ok(result)) = &steps[2] else {
+            panic!("expected a result");
+        };
+        assert_eq!(result, &json!({ "sent": 2, "deleted": 1 }));
+    }
+
+    #[test]
+    fn invalid_params_test() {
+        let err =
+            super::text_request("rename", json!({ "name": "a b", "new_name": "c" })).unwrap_err();
+        assert_eq!(err.code, super::INVALID_PARAMS);
+        let err = super::text_request("share", json!({ "hosts": ["a"] })).unwrap_err();
+        assert_eq!(err.code, super::INVALID_PARAMS);
+        let err = super::text_request("nope", json!(null)).unwrap_err();
+        assert_eq!(err.code, super::METHOD_NOT_FOUND);
+        let mut reply = Reply::new("unregister", &json!({ "name": "pc" }));
+        let Step::Done(Err(err)) = reply.step("UNREG_HOSTNAME") else {
+            panic!("expected an error");
+        };
+        assert_eq!(err.code, super::NOT_FOUND);
+        assert_eq!(err.data.unwrap().response, "UNREG_HOSTNAME");
+    }
+}
@@ -1,240 +1,369 @@
-use std::{
-    net::{IpAddr, SocketAddr},
-    path::{Path, PathBuf},
-};
-
-use interprocess::local_socket::{
-    traits::tokio::Listener, GenericNamespaced, ListenerOptions, ToNsName,
-};
-use smol_str::SmolStr;
-use tokio::{net::TcpListener, task::JoinSet};
-
-use crate::{
-    config::Config,
-    consts, global, handler,
-};
-
-fn join_set() -> &'static mut JoinSet<()> {
-    static mut JOIN_SET: Option<JoinSet<()>> = None;
-    unsafe { JOIN_SET.get_or_insert(JoinSet::new()) }
-}
-
-pub(crate) fn init_global_logger(
-    log_target: env_logger::Target,
-    max_log_level: log::LevelFilter,
-) -> anyhow::Result<()> {
-    let mut log_builder = env_logger::builder();
-    log_builder
-        .target(log_target)
-        .filter_level(max_log_level)
-        .format_level(true)
-        .format_module_path(true)
-        .init();
-    Ok(())
-}
-
-pub struct Server {
-    log_target: env_logger::Target,
-    max_log_level: log::LevelFilter,
-    config: Config,
-    config_path: PathBuf,
-}
-
-impl Default for Server {
-    fn default() -> Self {
-        Self {
-            max_log_level: log::LevelFilter::Info,
-            log_target: env_logger::Target::Stdout,
-            config: Config::default(),
-            config_path: Config::default_config_path(),
-        }
-    }
-}
-
-impl Server {
-
-
-    pub fn set_max_log_level(&mut self, level: log::LevelFilter) {
-        self.max_log_level = level
-    }
-
-    pub fn max_log_level(&mut self, level: log::LevelFilter) -> &mut Self {
-        self.max_log_level = level;
-        self
-    }
-
-    pub fn log_target(&mut self, target: env_logger::Target) -> &mut Self {
-        self.log_target = target;
-        self
-    }
-
-
-    pub fn listener_port(&mut self, port: u16) -> &mut Self {
-        self.config.set_listener_port(port);
-        self
-    }
-
-    pub fn set_log_target(&mut self, target: env_logger::Target) {
-        self.log_target = target;
-    }
-
-    pub fn set_ipc_socket_name(&mut self, ipc_socket_name: SmolStr) {
-        self.config.set_ipc_socket_name(ipc_socket_name);
-    }
-
-    pub fn set_listener_port(&mut self, port: u16) {
-        self.config.set_listener_port(port);
-    }
-
-    pub fn set_listener_addr(&mut self, addr: SocketAddr) {
-        self.config.set_listener_addr(addr);
-    }
-
-    pub fn set_listener_ip(&mut self, ip: IpAddr) {
-        self.config.set_listener_ip(ip);
-    }
-
-    pub fn load_config_file<P: AsRef<Path>>(&mut self, config_file_path: P) -> anyhow::Result<()> {
-        let p = config_file_path.as_ref();
-        let (ok, c) = Config::from_file(p)?.0.checked();
-        if !ok {
-            c.write_to_file(p)?;
-        }
-        self.config = c;
-        p.clone_into(&mut self.config_path);
-        Ok(())
-    }
-
-    pub fn set_num_workers(&mut self, n: u8) {
-        self.config.set_num_workers(n);
-    }
-
-    pub fn set_save_dir<P: Into<PathBuf>>(&mut self, save_dir: P) {
-        self.config.set_save_dir(save_dir);
-    }
-
-    pub fn register_host(&mut self, hostname: &str, host: SocketAddr) -> anyhow::Result<()> {
-        if Config::check_hostname_valid(hostname) {
-            self.config.register_host(hostname, host);
-        }
-        Err(anyhow::anyhow!("Invalid hostname!"))
-    }
-
-    pub fn start(self) -> anyhow::Result<()> {
-        tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(self.config.num_workers() as usize + 1)
-            .enable_io()
-            .build()
-            .unwrap()
-            .block_on(self.start_inner())
-    }
-
-    async fn try_join() {
-        while join_set().len() > global::config_store().await.read().await.num_workers() as usize {
-            if let Some(Err(e)) = join_set().join_next().await {
-                log::error!("A local request handler task join failed: {}", e);
-            }
-        }
-    }
-
-    async fn try_create_default_ipc_server(
-    ) -> std::io::Result<interprocess::local_socket::tokio::Listener> {
-        let ipc_name = consts::DEFAULT_IPC_SOCK_NAME.to_ns_name::<GenericNamespaced>()?;
-        global::config_store()
-            .await
-            .write()
-            .await
-            .set_ipc_socket_name(consts::DEFAULT_IPC_SOCK_NAME.into());
-        ListenerOptions::new().name(ipc_name).create_tokio()
-    }
-
-    async fn start_local_listener() {
-        let config_reader = global::config_store().await.read().await;
-        let ipc_sock_name_str = config_reader.ipc_socket_name();
-        let mut listener_res = ListenerOptions::new()
-            .name(ipc_sock_name_str.to_ns_name::<GenericNamespaced>().unwrap())
-            .create_tokio();
-        if listener_res.is_err() && ipc_sock_name_str != consts::DEFAULT_IPC_SOCK_NAME {
-            log::warn!("Create local listener failed by using specified IPC socket name: `{}`. Try to create it using default name...", ipc_sock_name_str);
-            listener_res = Self::try_create_default_ipc_server().await;
-        }
-        if let Ok(local_listener) = listener_res {
-            log::info!("Local process listener start finished!");
-            loop {
-                let conn = match local_listener.accept().await {
-                    Ok(c) => c,
-                    Err(e) => {
-                        log::warn!("There was an error with an incoming connection: {}", e);
-                        continue;
-                    }
-                };
-                Self::try_join().await;
-                join_set().spawn(async move {
-                    if let Err(e) = handler::handle_local(conn).await {
-                        log::error!(
-                            "Error occurred while handling a local process connection: {}",
-                            e
-                        );
-                    }
-                });
-            }
-        } else {
-            let err = listener_res.unwrap_err();
-            if err.kind() == tokio::io::ErrorKind::AddrInUse {
-                log::error!("Error: could not start server because the socket file is occupied. Please check if {} is in use by another process and try again.", global::config_store().await.read().await.ipc_socket_name());
-            } else {
-                log::error!("Error occurred while create ipc listener: {}", err);
-            }
-            std::process::exit(1);
-        }
-    }
-
-
-    async fn start_inner(self) -> anyhow::Result<()> {
-        init_global_logger(self.log_target, self.max_log_level)?;
-        let mut config = self.config;
-        let preset_listener_addr = config.listener_addr();
-        let remote_listener: TcpListener;
-        let listen_res = TcpListener::bind(preset_listener_addr).await;
-        if let Err(e) = listen_res {
-            if preset_listener_addr == consts::DEFAULT_LISTENER_ADDR {
-                return Err(e.into());
-            }
-            remote_listener = TcpListener::bind(consts::DEFAULT_LISTENER_ADDR).await?;
-        } else {
-            remote_listener = listen_res.unwrap();
-        }
-        let local_addr = remote_listener.local_addr()?;
-        log::info!("Server start at {}\n", local_addr);
-        config.set_listener_addr(local_addr);
-        let mut config_store = global::config_store().await.write().await;
-        config_store.set_config(config);
-        config_store.update_to_file()?;        
-        if let Err(e) = ctrlc::set_handler(|| {
-            println!("CtrlC Pressed, Exiting forced now!");
-            std::process::exit(0);
-        }) {
-            log::warn!("Set CtrlC event failed! detail: {e}");
-        }
-
-        tokio::spawn(Self::start_local_listener());
-        loop {
-            match remote_listener.accept().await {
-                Ok((stream, addr)) => {
-                    Self::try_join().await;
-                    join_set().spawn(async move {
-                        if let Err(e) = handler::handle_remote(stream, addr).await {
-                            log::error!("Error occurred while handling a remote connection: {}", e);
-                        }
-                    });
-                }
-                Err(e) => {
-                    log::error!("Accept connection error: {}", e);
-                }
-            }
-        }
-    }
-}
-
-#[cfg(test)]
-mod tests {}
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use interprocess::local_socket::{
+    tokio::Listener as LocalListener, traits::tokio::Listener, GenericNamespaced, ListenerOptions,
+    ToNsName,
+};
+use smol_str::SmolStr;
+use tokio::{sync::Mutex, task::JoinSet};
+
+use crate::{
+    config::{Config, DiscoveryConfig, HooksConfig, HostRecord, WatchFolder},
+    consts,
+    discovery::{self, Announcement, Discovery},
+    error::{self, Error},
+    global, handler, identity, status,
+};
+
+fn join_set() -> &'static Mutex<JoinSet<()>> {
+    static JOIN_SET: OnceLock<Mutex<JoinSet<()>>> = OnceLock::new();
+    JOIN_SET.get_or_init(|| Mutex::new(JoinSet::new()))
+}
+
+/// Sets up the logger, unless the program embedding the server already set up one.
+pub(crate) fn init_global_logger(log_target: env_logger::Target, max_log_level: log::LevelFilter) {
+    let mut log_builder = env_logger::builder();
+    let res = log_builder
+        .target(log_target)
+        .filter_level(max_log_level)
+        .format_level(true)
+        .format_module_path(true)
+        .try_init();
+    if res.is_err() {
+        log::debug!("A logger is already set, keep using it");
+    }
+}
+
+pub struct Server {
+    log_target: env_logger::Target,
+    max_log_level: log::LevelFilter,
+    config: Config,
+    config_path: PathBuf,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            max_log_level: log::LevelFilter::Info,
+            log_target: env_logger::Target::Stdout,
+            config: Config::default(),
+            config_path: Config::default_config_path(),
+        }
+    }
+}
+
+impl Server {
+    pub fn set_max_log_level(&mut self, level: log::LevelFilter) {
+        self.max_log_level = level
+    }
+
+    pub fn max_log_level(&mut self, level: log::LevelFilter) -> &mut Self {
+        self.max_log_level = level;
+        self
+    }
+
+    pub fn log_target(&mut self, target: env_logger::Target) -> &mut Self {
+        self.log_target = target;
+        self
+    }
+
+    pub fn listener_port(&mut self, port: u16) -> &mut Self {
+        self.config.set_listener_port(port);
+        self
+    }
+
+    pub fn set_log_target(&mut self, target: env_logger::Target) {
+        self.log_target = target;
+    }
+
+    pub fn set_ipc_socket_name(&mut self, ipc_socket_name: SmolStr) {
+        self.config.set_ipc_socket_name(ipc_socket_name);
+    }
+
+    pub fn set_listener_port(&mut self, port: u16) {
+        self.config.set_listener_port(port);
+    }
+
+    pub fn set_listener_addr(&mut self, addr: SocketAddr) {
+        self.config.set_listener_addr(addr);
+    }
+
+    pub fn set_listener_ip(&mut self, ip: IpAddr) {
+        self.config.set_listener_ip(ip);
+    }
+
+    pub fn load_config_file<P: AsRef<Path>>(&mut self, config_file_path: P) -> error::Result<()> {
+        let p = config_file_path.as_ref();
+        let (ok, c) = Config::from_file(p)?.0.checked();
+        if !ok {
+            c.write_to_file(p)?;
+        }
+        self.config = c;
+        p.clone_into(&mut self.config_path);
+        Ok(())
+    }
+
+    /// Loads the config file at the default path, if there is one.
+    pub fn load_default_config_file(&mut self) -> error::Result<()> {
+        let p = Config::default_config_path();
+        if p.is_file() {
+            self.load_config_file(p)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_num_workers(&mut self, n: u8) {
+        self.config.set_num_workers(n);
+    }
+
+    pub fn set_save_dir<P: Into<PathBuf>>(&mut self, save_dir: P) {
+        self.config.set_save_dir(save_dir);
+    }
+
+    /// Sets the receive path template, such as `{save_dir}/{host}/{date}`.
+    pub fn set_receive_path(&mut self, template: SmolStr) {
+        self.config.set_receive_path(template);
+    }
+
+    /// Sets the subdirectory of the save directory synced directories are mirrored to.
+    pub fn set_mirror_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.config.set_mirror_dir(dir.into());
+    }
+
+    /// Sets the directory of files named by their content hash, checked before a file is
+    /// received. A relative path is inside the save directory, `None` turns the store off.
+    pub fn set_content_store(&mut self, dir: Option<PathBuf>) {
+        self.config.set_content_store(dir);
+    }
+
+    /// Overrides the receive path template for a registered host, `None` removes the override.
+    pub fn set_host_receive_path(&mut self, hostname: &str, template: Option<SmolStr>) {
+        self.config.set_host_receive_path(hostname, template);
+    }
+
+    /// Defines a group of registered hosts which can be shared to at once, `None` removes it.
+    pub fn set_host_group(&mut self, group: &str, members: Option<Vec<SmolStr>>) {
+        self.config.set_host_group(group, members);
+    }
+
+    /// Sets the hooks run after files are received.
+    pub fn set_hooks(&mut self, hooks: HooksConfig) {
+        self.config.set_hooks(hooks);
+    }
+
+    /// Sets the name this daemon announces itself with.
+    pub fn set_host_name(&mut self, name: SmolStr) {
+        self.config.set_host_name(name);
+    }
+
+    pub fn set_discovery(&mut self, discovery: DiscoveryConfig) {
+        self.config.set_discovery(discovery);
+    }
+
+    pub fn enable_discovery(&mut self) {
+        let mut discovery = self.config.discovery().clone();
+        discovery.enabled = true;
+        self.config.set_discovery(discovery);
+    }
+
+    /// Sets the outbox folders whose files are shared automatically, only watched on Linux.
+    pub fn set_watch_folders(&mut self, folders: Vec<WatchFolder>) {
+        self.config.set_watch_folders(folders);
+    }
+
+    /// Registers a host with all of its settings, replacing any previous record.
+    pub fn set_host_record(&mut self, hostname: &str, record: HostRecord) {
+        self.config.set_host_record(hostname, record);
+    }
+
+    pub fn register_host(&mut self, hostname: &str, host: SocketAddr) -> error::Result<()> {
+        if !Config::check_hostname_valid(hostname) {
+            return Err(Error::InvalidHostname(hostname.into()));
+        }
+        self.config.register_host(hostname, host.into());
+        Ok(())
+    }
+
+    /// Runs the server until the process exits, errors only if it could not be started.
+    pub fn start(self) -> error::Result<()> {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(self.config.num_workers() as usize + 1)
+            .enable_all()
+            .build()?
+            .block_on(self.start_inner())
+    }
+
+    /// Runs `task` on a worker once one is free.
+    async fn spawn_worker<F>(task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        let queued = status::Queued::new();
+        let mut tasks = Self::try_join().await;
+        drop(queued);
+        tasks.spawn(async move {
+            let _busy = status::Busy::new();
+            task.await
+        });
+    }
+
+    async fn try_join() -> tokio::sync::MutexGuard<'static, JoinSet<()>> {
+        let num_workers = global::config_store().await.read().await.num_workers() as usize;
+        let mut tasks = join_set().lock().await;
+        while tasks.len() > num_workers {
+            if let Some(Err(e)) = tasks.join_next().await {
+                log::error!("A local request handler task join failed: {}", e);
+            }
+        }
+        tasks
+    }
+
+    async fn try_create_default_ipc_server() -> std::io::Result<LocalListener> {
+        let ipc_name = consts::DEFAULT_IPC_SOCK_NAME.to_ns_name::<GenericNamespaced>()?;
+        let listener = ListenerOptions::new().name(ipc_name).create_tokio()?;
+        global::config_store()
+            .await
+            .write()
+            .await
+            .set_ipc_socket_name(consts::DEFAULT_IPC_SOCK_NAME.into());
+        Ok(listener)
+    }
+
+    async fn create_local_listener() -> error::Result<LocalListener> {
+        let ipc_sock_name =
+            SmolStr::from(global::config_store().await.read().await.ipc_socket_name());
+        let ipc_sock_name_str = ipc_sock_name.as_str();
+        let mut listener_res = ipc_sock_name_str
+            .to_ns_name::<GenericNamespaced>()
+            .and_then(|name| ListenerOptions::new().name(name).create_tokio());
+        if listener_res.is_err() && ipc_sock_name_str != consts::DEFAULT_IPC_SOCK_NAME {
+            log::warn!("Create local listener failed by using specified IPC socket name: `{}`. Try to create it using default name...", ipc_sock_name_str);
+            listener_res = Self::try_create_default_ipc_server().await;
+        }
+        listener_res.map_err(|source| {
+            if source.kind() == std::io::ErrorKind::AddrInUse {
+                Error::IpcSocketInUse(ipc_sock_name)
+            } else {
+                Error::IpcSocket {
+                    name: ipc_sock_name,
+                    source,
+                }
+            }
+        })
+    }
+
+    async fn serve_local(local_listener: LocalListener) {
+        log::info!("Local process listener start finished!");
+        loop {
+            let conn = match local_listener.accept().await {
+                Ok(c) => c,
+                Err(e) => {
+                    log::warn!("There was an error with an incoming connection: {}", e);
+                    continue;
+                }
+            };
+            Self::spawn_worker(async move {
+                if let Err(e) = handler::handle_local(conn).await {
+                    log::error!(
+                        "Error occurred while handling a local process connection: {}",
+                        e
+                    );
+                }
+            })
+            .await;
+        }
+    }
+
+    async fn start_discovery(config: &Config, listener_port: u16) {
+        let own = Announcement {
+            name: config.host_name().into(),
+            port: listener_port,
+            fingerprint: identity::identity().await.fingerprint(),
+        };
+        match Discovery::bind(config.discovery().clone(), own) {
+            Ok(d) => {
+                log::info!("Peer discovery start at {}", config.discovery().group);
+                tokio::spawn(async move {
+                    if let Err(e) = d.run(discovery::peer_table()).await {
+                        log::error!("Peer discovery stopped! Detail: {}", e);
+                    }
+                });
+            }
+            Err(e) => log::error!("Start peer discovery failed! Detail: {}", e),
+        }
+    }
+
+    async fn start_inner(self) -> error::Result<()> {
+        init_global_logger(self.log_target, self.max_log_level);
+        let mut config = self.config;
+        let preset_listener_addr = config.listener_addr();
+        let mut listen_res = handler::bind_listener(preset_listener_addr);
+        for fallback_addr in [
+            consts::DEFAULT_LISTENER_ADDR,
+            consts::DEFAULT_V4_LISTENER_ADDR,
+        ] {
+            if listen_res.is_err() && fallback_addr != preset_listener_addr {
+                log::warn!(
+                    "Bind listener to {} failed, try {}",
+                    preset_listener_addr,
+                    fallback_addr
+                );
+                listen_res = handler::bind_listener(fallback_addr);
+            }
+        }
+        let remote_listener = listen_res.map_err(|source| Error::Bind {
+            addr: preset_listener_addr,
+            source,
+        })?;
+        let local_addr = remote_listener.local_addr()?;
+        log::info!("Server start at {}\n", local_addr);
+        config.set_listener_addr(local_addr);
+        {
+            let mut config_store = global::config_store().await.write().await;
+            config_store.set_config(config.clone());
+            config_store.set_config_path(self.config_path);
+            config_store.update_to_file()?;
+        }
+        if config.discovery().enabled {
+            Self::start_discovery(&config, local_addr.port()).await;
+        }
+        #[cfg(target_os = "linux")]
+        crate::watch::start(config.watch_folders());
+        #[cfg(not(target_os = "linux"))]
+        if !config.watch_folders().is_empty() {
+            log::warn!("Watch folders are only supported on Linux, they are ignored");
+        }
+        tokio::spawn(handler::save_contacts(consts::CONTACT_SAVE_INTERVAL));
+        status::mark_started();
+        if let Err(e) = ctrlc::set_handler(|| {
+            println!("CtrlC Pressed, Exiting forced now!");
+            std::process::exit(0);
+        }) {
+            log::warn!("Set CtrlC event failed! detail: {e}");
+        }
+
+        tokio::spawn(Self::serve_local(Self::create_local_listener().await?));
+        loop {
+            match remote_listener.accept().await {
+                Ok((stream, addr)) => {
+                    Self::spawn_worker(async move {
+                        if let Err(e) = handler::handle_remote(stream, addr).await {
+                            log::error!("Error occurred while handling a remote connection: {}", e);
+                        }
+                    })
+                    .await;
+                }
+                Err(e) => {
+                    log::error!("Accept connection error: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {}
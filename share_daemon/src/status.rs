@@ -0,0 +1,116 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+use smol_str::ToSmolStr;
+use tokio::io::AsyncWrite;
+
+use crate::{common::LocalResponse, global, handler::WriteLine, transfer};
+
+fn started_at() -> &'static OnceLock<Instant> {
+    static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+    &STARTED_AT
+}
+
+pub(crate) fn mark_started() {
+    let _ = started_at().set(Instant::now());
+}
+
+/// Connections accepted but waiting for a free worker.
+static QUEUED_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static BUSY_WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts a connection as queued for as long as it lives.
+pub(crate) struct Queued(());
+
+impl Queued {
+    pub(crate) fn new() -> Self {
+        QUEUED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        QUEUED_CONNECTIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts a worker as busy for as long as it lives.
+pub(crate) struct Busy(());
+
+impl Busy {
+    pub(crate) fn new() -> Self {
+        BUSY_WORKERS.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        BUSY_WORKERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Answers a `STATUS` request with `STATUS <key> <value>` lines, a `TRANSFER` line for every
+/// transfer in progress and `LIST_END`.
+pub(crate) async fn write_status<S>(local_stream: &mut S) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut lines = vec![
+        ("version", env!("CARGO_PKG_VERSION").to_smolstr()),
+        (
+            "uptime",
+            started_at()
+                .get()
+                .map(|t| t.elapsed().as_secs())
+                .unwrap_or_default()
+                .to_smolstr(),
+        ),
+    ];
+    {
+        let config_store = global::config_store().await.read().await;
+        lines.extend([
+            ("listener", config_store.listener_addr().to_smolstr()),
+            ("ipc_socket", config_store.ipc_socket_name().to_smolstr()),
+            (
+                "save_dir",
+                config_store.receive_dir().to_string_lossy().to_smolstr(),
+            ),
+            (
+                "config_path",
+                config_store.config_path().to_string_lossy().to_smolstr(),
+            ),
+            (
+                "workers",
+                smol_str::format_smolstr!(
+                    "{}/{}",
+                    BUSY_WORKERS.load(Ordering::Relaxed),
+                    config_store.num_workers()
+                ),
+            ),
+        ]);
+    }
+    lines.push((
+        "queued_connections",
+        QUEUED_CONNECTIONS.load(Ordering::Relaxed).to_smolstr(),
+    ));
+    for (key, value) in lines {
+        local_stream
+            .write_line(LocalResponse::Status(key.into(), value).to_smolstr())
+            .await?;
+    }
+    for info in transfer::transfers() {
+        local_stream
+            .write_line(LocalResponse::Transfer(info).to_smolstr())
+            .await?;
+    }
+    local_stream
+        .write_line(LocalResponse::ListEnd.to_str_unchecked())
+        .await
+}
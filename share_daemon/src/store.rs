@@ -0,0 +1,137 @@
+//! Content hashes of files, used to skip sending a file the receiver already has. The content
+//! store is a directory of files named by their hash, filled with every received file.
+
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use smol_str::SmolStr;
+
+use crate::{consts, handler};
+
+/// The hex encoded SHA-256 of the content of a file.
+pub(crate) fn hash_file(path: &Path) -> std::io::Result<SmolStr> {
+    let mut f = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0; consts::FILE_TRANS_BUF_SIZE];
+    loop {
+        let read_size = f.read(&mut buf)?;
+        if read_size == 0 {
+            return Ok(handler::hex_string(&hasher.finalize()));
+        }
+        hasher.update(&buf[..read_size]);
+    }
+}
+
+/// [`hash_file`] on a blocking thread, so hashing a large file does not stall other transfers.
+pub(crate) async fn hash_file_blocking(path: &Path) -> std::io::Result<SmolStr> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || hash_file(&path))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// Whether `hash` looks like what [`hash_file`] returns.
+pub(crate) fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn has_content(path: &Path, size: u64, hash: &str) -> bool {
+    path.metadata()
+        .is_ok_and(|m| m.is_file() && m.len() == size)
+        && hash_file(path).is_ok_and(|h| h == hash)
+}
+
+/// Makes sure `dest` holds the content of `hash` without receiving it, either since it already
+/// does or by taking it from the store. Returns false if the content has to be sent.
+pub(crate) fn place_identical(dest: &Path, store: Option<&Path>, size: u64, hash: &str) -> bool {
+    if has_content(dest, size, hash) {
+        return true;
+    }
+    let Some(stored) = store.map(|store| store.join(hash)) else {
+        return false;
+    };
+    // The stored file is checked too, it may have been edited through a hard link.
+    if !has_content(&stored, size, hash) {
+        return false;
+    }
+    match link_or_copy(&stored, dest) {
+        Ok(()) => true,
+        Err(e) => {
+            log::error!(
+                "Taking `{}` from the content store failed! Detail: {}",
+                dest.display(),
+                e
+            );
+            false
+        }
+    }
+}
+
+/// [`place_identical`] on a blocking thread, it may hash large files.
+pub(crate) async fn place_identical_blocking(
+    dest: PathBuf,
+    store: Option<PathBuf>,
+    size: u64,
+    hash: SmolStr,
+) -> bool {
+    tokio::task::spawn_blocking(move || place_identical(&dest, store.as_deref(), size, &hash))
+        .await
+        .unwrap_or(false)
+}
+
+/// Adds a received file to the store, unless the store already has its content.
+pub(crate) fn keep(store: &Path, path: &Path, hash: &str) {
+    let stored = store.join(hash);
+    if stored.exists() {
+        return;
+    }
+    if let Err(e) = std::fs::create_dir_all(store).and_then(|_| link_or_copy(path, &stored)) {
+        log::error!(
+            "Adding `{}` to the content store failed! Detail: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+/// Hard links `from` to `to`, replacing `to`, or copies it if they are on different devices.
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    let temp = consts::part_path(to);
+    let _ = std::fs::remove_file(&temp);
+    if std::fs::hard_link(from, &temp).is_err() {
+        std::fs::copy(from, &temp)?;
+    }
+    std::fs::rename(&temp, to)
+}
+
+#[cfg(test)]
+mod store_tests {
+    use super::{hash_file, is_valid_hash, keep, place_identical};
+
+    #[test]
+    fn place_identical_test() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let store = dir.join("store");
+        let (a, b) = (dir.join("a.bin"), dir.join("b.bin"));
+        std::fs::write(&a, "content").unwrap();
+        let hash = hash_file(&a).unwrap();
+        assert!(is_valid_hash(&hash));
+
+        assert!(place_identical(&a, None, 7, &hash));
+        assert!(!place_identical(&a, None, 8, &hash));
+        assert!(!place_identical(&b, Some(&store), 7, &hash));
+        keep(&store, &a, &hash);
+        assert!(place_identical(&b, Some(&store), 7, &hash));
+        assert_eq!(std::fs::read(&b).unwrap(), b"content");
+
+        // A stored file edited in place no longer counts.
+        std::fs::write(store.join(hash.as_str()), "changed").unwrap();
+        std::fs::remove_file(&b).unwrap();
+        assert!(!place_identical(&b, Some(&store), 7, &hash));
+    }
+}
@@ -0,0 +1,538 @@
+//! One-way directory mirroring. The receiver answers a `SYNC <name>` request with the manifest of
+//! its mirror, the sender then only sends the files which are new or changed and the paths which
+//! were removed:
+//!
+//! ```text
+//! -> SYNC <name>
+//! <- ENTRY <size> <mtime> <sha256> <path>
+//! <- MANIFEST_END
+//! -> FILE <size> <mtime> <path>
+//! <- SEND, or QUOTA_EXCEEDED <files> which ends the sync
+//! -> the content
+//! -> DELETE <path>
+//! -> SYNC_END
+//! <- SYNCED <files> <deleted>
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{Read, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+
+use fshare_proto::{
+    manifest::{self, ManifestEntry, SyncPlan},
+    request_tag::sync,
+    FileName,
+};
+use sha2::{Digest, Sha256};
+use smol_str::{SmolStr, ToSmolStr};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{
+    common::{LocalResponse, RemoteResponse},
+    config::HostAddr,
+    consts, error,
+    events::{self, Event},
+    global,
+    handler::{self, WriteLine},
+    history::{self, HistoryEntry},
+    policy::{self, Throttle},
+    request_tag, resolve, store,
+    transfer::{Direction, Transfer},
+};
+
+/// Long enough for an `ENTRY`, `FILE` or `DELETE` line.
+const LINE_LIMIT: u64 = consts::FILE_PATH_LIMIT + 120;
+
+/// Mirrors `dir` to the registered host and writes the progress and the result to the local
+/// process.
+pub(crate) async fn sync_to_host<S>(
+    mut local_stream: S,
+    host: &str,
+    dir: PathBuf,
+    delete: bool,
+) -> std::io::Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let addrs = global::config_store()
+        .await
+        .read()
+        .await
+        .get_addrs_by_name(host)
+        .map(<[_]>::to_vec);
+    let Some(addrs) = addrs else {
+        return local_stream
+            .write_line(LocalResponse::UnregisteredHostname.to_str_unchecked())
+            .await;
+    };
+    let transfer = Transfer::queue(Direction::Send, host);
+    local_stream
+        .write_line(LocalResponse::TransferId(transfer.id()).to_smolstr())
+        .await?;
+    let resp = match send_dir(&mut local_stream, &addrs, &dir, delete, &transfer).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            log::error!(
+                "Syncing `{}` to `{}` failed! Detail: {}",
+                dir.display(),
+                host,
+                e
+            );
+            LocalResponse::UnexpectedSendResp
+        }
+    };
+    local_stream.write_line(resp.to_smolstr()).await
+}
+
+async fn send_dir<S>(
+    local_stream: &mut S,
+    addrs: &[HostAddr],
+    dir: &Path,
+    delete: bool,
+    transfer: &Transfer,
+) -> std::io::Result<LocalResponse>
+where
+    S: AsyncWrite + Unpin,
+{
+    let Ok(stream) = resolve::connect(addrs).await else {
+        return Ok(LocalResponse::UnreachableAddress(addrs[0].clone()));
+    };
+    let peer_ip = stream.peer_addr()?.ip();
+    let (read_half, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(read_half).take(LINE_LIMIT);
+    let name = FileName::lossy(&dir.file_name().unwrap_or_default().to_string_lossy());
+    writer
+        .write_line(smol_str::format_smolstr!(
+            "{} {}",
+            request_tag::remote::SYNC,
+            name
+        ))
+        .await?;
+    let mut remote = vec![];
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.set_limit(LINE_LIMIT);
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(LocalResponse::UnexpectedRemoteResponse);
+        }
+        if line.trim_end() == sync::MANIFEST_END {
+            break;
+        }
+        match ManifestEntry::parse(&line) {
+            Some(entry) => remote.push(entry),
+            None => return Ok(refused(&line)),
+        }
+    }
+
+    let local = scan(dir)?;
+    let plan = SyncPlan::new(&local, &remote, delete, |l, r| {
+        store::hash_file(&dir.join(l.path.as_str())).is_ok_and(|hash| hash == r.hash)
+    });
+    let to_send: HashSet<&SmolStr> = plan.send.iter().collect();
+    for entry in local.iter().filter(|e| to_send.contains(&e.path)) {
+        let mut f = match File::open(dir.join(entry.path.as_str())) {
+            Ok(f) => f,
+            Err(e) => {
+                log::warn!("Skip `{}`, it cannot be read: {}", entry.path, e);
+                continue;
+            }
+        };
+        let size = f.metadata()?.len();
+        transfer.start_file(&entry.path, Some(size));
+        local_stream
+            .write_line(LocalResponse::FileInfo(entry.path.clone(), Some(size)).to_smolstr())
+            .await?;
+        writer
+            .write_line(smol_str::format_smolstr!(
+                "{} {} {} {}",
+                sync::FILE,
+                size,
+                entry.mtime,
+                entry.path
+            ))
+            .await?;
+        line.clear();
+        reader.set_limit(LINE_LIMIT);
+        reader.read_line(&mut line).await?;
+        if line.trim() != request_tag::send_flag::SEND {
+            return Ok(match line.parse::<RemoteResponse>() {
+                Ok(RemoteResponse::QuotaExceeded(count)) => {
+                    LocalResponse::RemoteQuotaExceeded(count)
+                }
+                _ => LocalResponse::UnexpectedSendResp,
+            });
+        }
+        let mut sent = 0;
+        while sent < size {
+            if transfer.is_cancelled() {
+                return Ok(LocalResponse::Cancelled);
+            }
+            let mut buf = [0_u8; consts::FILE_TRANS_BUF_SIZE];
+            let max = buf.len().min((size - sent) as usize);
+            let read_size = f.read(&mut buf[..max])?;
+            if read_size == 0 {
+                // The receiver expects `size` bytes, a file which shrank cannot be mirrored now.
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            writer.write_all(&buf[..read_size]).await?;
+            writer.flush().await?;
+            sent += read_size as u64;
+            transfer.progress(read_size as u64);
+            local_stream
+                .write_line(LocalResponse::Progress(sent as f64 / size as f64).to_smolstr())
+                .await?;
+        }
+    }
+    for path in &plan.delete {
+        writer
+            .write_line(smol_str::format_smolstr!("{} {}", sync::DELETE, path))
+            .await?;
+    }
+    writer.write_line(sync::SYNC_END).await?;
+
+    line.clear();
+    reader.set_limit(LINE_LIMIT);
+    reader.read_line(&mut line).await?;
+    Ok(match line.parse::<RemoteResponse>() {
+        Ok(RemoteResponse::Synced(sent, deleted)) => {
+            transfer.succeed();
+            handler::record_contact(peer_ip, true).await;
+            LocalResponse::Synced(sent, deleted)
+        }
+        _ => LocalResponse::UnexpectedSendResp,
+    })
+}
+
+/// Maps the line a receiver refused a sync with to the response for the local process.
+fn refused(line: &str) -> LocalResponse {
+    match line.parse::<RemoteResponse>() {
+        Ok(RemoteResponse::UnregisteredHost) => LocalResponse::RemoteUnregistered,
+        Ok(RemoteResponse::NotAccepted) => LocalResponse::RemoteNotAccepted,
+        _ => LocalResponse::UnexpectedRemoteResponse,
+    }
+}
+
+/// Answers a `SYNC <name>` request, the directory is mirrored to `<mirror root>/<sender>/<name>`.
+pub(crate) async fn handle_sync_request<S>(
+    remote_stream: &mut S,
+    peer_addr: SocketAddr,
+    name: &str,
+) -> error::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if let Err(resp) = handler::authorize_sender(peer_addr).await {
+        remote_stream.write_line(resp.to_str_unchecked()).await?;
+        return Ok(());
+    }
+    let Ok(name) = name.parse::<FileName>() else {
+        remote_stream
+            .write_line(RemoteResponse::InvalidRequest.to_str_unchecked())
+            .await?;
+        return Ok(());
+    };
+    let (sender, root, policy) = {
+        let config = global::config_store().await.read().await;
+        let (sender, policy) = config
+            .get_host_by_ip(peer_addr.ip())
+            .map(|(name, host)| (SmolStr::from(name), host.policy.clone()))
+            .unwrap_or_default();
+        let root = config.mirror_root(&sender).join(name.as_str());
+        (sender, root, policy)
+    };
+    tokio::fs::create_dir_all(&root).await?;
+    for mut entry in scan(&root)? {
+        entry.hash = mirror_hash(&root.join(entry.path.as_str()), &entry)?;
+        remote_stream.write_line(entry.to_line()).await?;
+    }
+    remote_stream.write_line(sync::MANIFEST_END).await?;
+
+    let transfer = Transfer::queue(Direction::Receive, &sender);
+    let mut throttle = Throttle::new(policy.bandwidth_limit);
+    let mut reader = BufReader::new(&mut *remote_stream).take(LINE_LIMIT);
+    let mut line = String::new();
+    let (mut received, mut deleted) = (0_u32, 0_u32);
+    let resp = loop {
+        line.clear();
+        reader.set_limit(LINE_LIMIT);
+        if reader.read_line(&mut line).await? == 0 {
+            log::info!("Syncing `{}` from `{}` stopped early", name, sender);
+            return Ok(());
+        }
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+        let (tag, args) = trimmed
+            .split_once(consts::STARTLINE_SEP)
+            .unwrap_or((trimmed, ""));
+        match tag {
+            sync::FILE => {
+                let Some((size, mtime, path)) = parse_file_header(args) else {
+                    break RemoteResponse::InvalidRequest;
+                };
+                let Some(reservation) = policy::reserve_quota(&sender, policy.daily_quota, size)
+                else {
+                    log::warn!("Host `{}` exceeded its daily quota", sender);
+                    break RemoteResponse::QuotaExceeded(received.min(u8::MAX.into()) as u8);
+                };
+                // The sender waits for this before it streams the content.
+                reader
+                    .get_mut()
+                    .get_mut()
+                    .write_line(request_tag::send_flag::SEND)
+                    .await?;
+                let path = SmolStr::from(path);
+                transfer.start_file(&path, Some(size));
+                reader.set_limit(size);
+                let target = root.join(path.as_str());
+                let Some(checksum) =
+                    receive_file(&mut reader, &target, size, &transfer, &mut throttle).await?
+                else {
+                    log::info!("Syncing `{}` from `{}` stopped early", name, sender);
+                    return Ok(());
+                };
+                reservation.keep();
+                File::options()
+                    .write(true)
+                    .open(&target)?
+                    .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime))?;
+                cache_hash(target.clone(), size, mtime, checksum.clone());
+                history::record(HistoryEntry::FileReceived {
+                    sender: &sender,
+                    path: &target,
+                    size,
+                    checksum: &checksum,
+                })
+                .await;
+                events::emit(Event::FileReceived {
+                    host: sender.clone(),
+                    size,
+                    path: target.to_string_lossy().into(),
+                });
+                received += 1;
+            }
+            sync::DELETE if manifest::is_safe_relative(args) => {
+                let target = root.join(args);
+                if target.is_file() {
+                    hash_cache().lock().unwrap().remove(&target);
+                    std::fs::remove_file(&target)?;
+                    remove_empty_parents(&target, &root);
+                    deleted += 1;
+                }
+            }
+            sync::SYNC_END => {
+                transfer.succeed();
+                handler::record_contact(peer_addr.ip(), true).await;
+                history::record(HistoryEntry::Synced {
+                    sender: &sender,
+                    dir: &root,
+                    files_count: received,
+                    deleted,
+                })
+                .await;
+                break RemoteResponse::Synced(received, deleted);
+            }
+            _ => break RemoteResponse::InvalidRequest,
+        }
+    };
+    reader
+        .into_inner()
+        .into_inner()
+        .write_line(resp.to_smolstr())
+        .await?;
+    Ok(())
+}
+
+struct CachedHash {
+    size: u64,
+    mtime: u64,
+    hash: SmolStr,
+}
+
+/// The hashes of mirrored files with the size and mtime they had, so a `SYNC` only hashes the
+/// files which changed since the last one.
+fn hash_cache() -> &'static Mutex<HashMap<PathBuf, CachedHash>> {
+    static HASH_CACHE: OnceLock<Mutex<HashMap<PathBuf, CachedHash>>> = OnceLock::new();
+    HASH_CACHE.get_or_init(Default::default)
+}
+
+/// The hash of the mirrored file at `path`, `entry` is what [`scan`] found there.
+fn mirror_hash(path: &Path, entry: &ManifestEntry) -> std::io::Result<SmolStr> {
+    if let Some(cached) = hash_cache()
+        .lock()
+        .unwrap()
+        .get(path)
+        .filter(|c| c.size == entry.size && c.mtime == entry.mtime)
+    {
+        return Ok(cached.hash.clone());
+    }
+    let hash = store::hash_file(path)?;
+    cache_hash(path.to_path_buf(), entry.size, entry.mtime, hash.clone());
+    Ok(hash)
+}
+
+fn cache_hash(path: PathBuf, size: u64, mtime: u64, hash: SmolStr) {
+    hash_cache()
+        .lock()
+        .unwrap()
+        .insert(path, CachedHash { size, mtime, hash });
+}
+
+/// Parses the arguments of `FILE <size> <mtime> <path>`, the path may contain spaces.
+fn parse_file_header(args: &str) -> Option<(u64, u64, &str)> {
+    let mut fields = args.splitn(3, consts::STARTLINE_SEP);
+    let size = fields.next()?.parse().ok()?;
+    let mtime = fields.next()?.parse().ok()?;
+    let path = fields.next().filter(|p| manifest::is_safe_relative(p))?;
+    Some((size, mtime, path))
+}
+
+/// Copies `size` bytes to a part file which replaces `target` once complete, returns the checksum
+/// or `None` if the transfer stopped early.
+async fn receive_file<R>(
+    reader: &mut R,
+    target: &Path,
+    size: u64,
+    transfer: &Transfer,
+    throttle: &mut Throttle,
+) -> std::io::Result<Option<SmolStr>>
+where
+    R: AsyncRead + Unpin,
+{
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let part = consts::part_path(target);
+    let mut f = File::create(&part)?;
+    let mut hasher = Sha256::new();
+    let mut received = 0;
+    while received < size && !transfer.is_cancelled() {
+        let mut buf = [0; consts::FILE_TRANS_BUF_SIZE];
+        let read_size = reader.read(&mut buf).await?;
+        if read_size == 0 {
+            break;
+        }
+        let data = &buf[..read_size];
+        hasher.update(data);
+        f.write_all(data)?;
+        received += read_size as u64;
+        transfer.progress(read_size as u64);
+        throttle.consume(read_size).await;
+    }
+    drop(f);
+    if received < size {
+        let _ = std::fs::remove_file(&part);
+        return Ok(None);
+    }
+    std::fs::rename(&part, target)?;
+    Ok(Some(handler::hex_string(&hasher.finalize())))
+}
+
+/// Removes the directories a deleted file leaves empty, up to `root`.
+fn remove_empty_parents(path: &Path, root: &Path) {
+    let mut dir = path.parent();
+    while let Some(d) = dir.filter(|d| *d != root && d.starts_with(root)) {
+        if std::fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// The files below `root` sorted by path, without their hashes. Symbolic links, part files and
+/// paths which cannot be mirrored are left out.
+pub(crate) fn scan(root: &Path) -> std::io::Result<Vec<ManifestEntry>> {
+    let mut entries = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for dir_entry in std::fs::read_dir(&dir)? {
+            let dir_entry = dir_entry?;
+            let file_type = dir_entry.file_type()?;
+            let path = dir_entry.path();
+            if file_type.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            if !file_type.is_file() || path.to_string_lossy().ends_with(consts::PART_SUFFIX) {
+                continue;
+            }
+            let relative = path
+                .strip_prefix(root)
+                .unwrap_or(&path)
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !manifest::is_safe_relative(&relative) {
+                log::warn!("Skip `{}`, its path cannot be mirrored", path.display());
+                continue;
+            }
+            let metadata = dir_entry.metadata()?;
+            let mtime = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            entries.push(ManifestEntry {
+                path: relative.into(),
+                size: metadata.len(),
+                mtime,
+                hash: SmolStr::default(),
+            });
+        }
+    }
+    entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod sync_tests {
+    use super::{mirror_hash, remove_empty_parents, scan};
+    use crate::store;
+
+    #[test]
+    fn scan_test() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        for file in ["b.txt", "a/c d.txt", "a/e/f.bin", "a/g.txt.fshare-part"] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file).unwrap();
+        }
+        let entries = scan(root).unwrap();
+        let paths: Vec<_> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a/c d.txt", "a/e/f.bin", "b.txt"]);
+        assert_eq!(entries[2].size, 5);
+
+        std::fs::remove_file(root.join("a/e/f.bin")).unwrap();
+        remove_empty_parents(&root.join("a/e/f.bin"), root);
+        assert!(!root.join("a/e").exists());
+        assert!(root.join("a").exists());
+    }
+
+    #[test]
+    fn mirror_hash_test() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("a.txt");
+        std::fs::write(&path, "a").unwrap();
+        let entry = scan(temp.path()).unwrap().remove(0);
+        let hash = mirror_hash(&path, &entry).unwrap();
+        assert_eq!(hash, store::hash_file(&path).unwrap());
+
+        // Unchanged size and mtime count as unchanged content.
+        std::fs::write(&path, "b").unwrap();
+        assert_eq!(mirror_hash(&path, &entry).unwrap(), hash);
+        std::fs::write(&path, "bc").unwrap();
+        let entry = scan(temp.path()).unwrap().remove(0);
+        assert_eq!(
+            mirror_hash(&path, &entry).unwrap(),
+            store::hash_file(&path).unwrap()
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Instant,
+};
+
+use smol_str::SmolStr;
+use tokio::sync::Notify;
+
+use crate::events::{self, Event};
+
+pub(crate) use fshare_proto::{Direction, TransferInfo};
+
+#[derive(Default)]
+struct Cancel {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+struct Entry {
+    info: TransferInfo,
+    cancel: Arc<Cancel>,
+}
+
+fn registry() -> &'static Mutex<BTreeMap<u64, Entry>> {
+    static REGISTRY: OnceLock<Mutex<BTreeMap<u64, Entry>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// The transfers in progress, oldest first.
+pub(crate) fn transfers() -> Vec<TransferInfo> {
+    registry()
+        .lock()
+        .unwrap()
+        .values()
+        .map(|entry| entry.info.clone())
+        .collect()
+}
+
+/// Asks the transfer to stop, returns whether it is in progress.
+pub(crate) fn cancel(id: u64) -> bool {
+    let registry = registry().lock().unwrap();
+    let Some(entry) = registry.get(&id) else {
+        return false;
+    };
+    entry.cancel.cancelled.store(true, Ordering::Relaxed);
+    entry.cancel.notify.notify_waiters();
+    true
+}
+
+/// A transfer listed in the registry until it is dropped, which emits whether it succeeded.
+pub(crate) struct Transfer {
+    id: u64,
+    succeeded: AtomicBool,
+    cancel: Arc<Cancel>,
+    last_progress_event: Mutex<Option<Instant>>,
+}
+
+impl Transfer {
+    pub(crate) fn queue(direction: Direction, host: &str) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let cancel = Arc::new(Cancel::default());
+        registry().lock().unwrap().insert(
+            id,
+            Entry {
+                info: TransferInfo {
+                    id,
+                    direction,
+                    host: host.into(),
+                    active: false,
+                    file: SmolStr::default(),
+                    done: 0,
+                    total: None,
+                },
+                cancel: cancel.clone(),
+            },
+        );
+        Self {
+            id,
+            succeeded: AtomicBool::new(false),
+            cancel,
+            last_progress_event: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub(crate) fn succeed(&self) {
+        self.succeeded.store(true, Ordering::Relaxed);
+    }
+
+    /// Copy loops check this between chunks and stop.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once the transfer is cancelled, for what waits without a loop.
+    pub(crate) async fn cancelled(&self) {
+        loop {
+            let notified = self.cancel.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut TransferInfo)) {
+        if let Some(entry) = registry().lock().unwrap().get_mut(&self.id) {
+            f(&mut entry.info);
+        }
+    }
+
+    pub(crate) fn start_file(&self, file: &str, total: Option<u64>) {
+        self.update(|info| {
+            info.active = true;
+            info.file = file.into();
+            info.done = 0;
+            info.total = total;
+        });
+        self.emit_progress(true);
+    }
+
+    pub(crate) fn progress(&self, bytes: u64) {
+        self.update(|info| info.done += bytes);
+        self.emit_progress(false);
+    }
+
+    fn emit_progress(&self, force: bool) {
+        let mut last_event = self.last_progress_event.lock().unwrap();
+        if !force && last_event.is_some_and(|t| t.elapsed() < events::PROGRESS_EVENT_INTERVAL) {
+            return;
+        }
+        *last_event = Some(Instant::now());
+        let info = registry()
+            .lock()
+            .unwrap()
+            .get(&self.id)
+            .map(|entry| entry.info.clone());
+        if let Some(info) = info {
+            events::emit(Event::Progress {
+                transfer: info.id,
+                direction: info.direction,
+                host: info.host,
+                done: info.done,
+                total: info.total,
+                file: info.file,
+            });
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        let removed = registry().lock().unwrap().remove(&self.id);
+        if let Some(Entry { info, .. }) = removed {
+            let (transfer, direction, host) = (info.id, info.direction, info.host);
+            events::emit(if *self.succeeded.get_mut() {
+                Event::TransferFinished {
+                    transfer,
+                    direction,
+                    host,
+                }
+            } else {
+                Event::TransferFailed {
+                    transfer,
+                    direction,
+                    host,
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod transfer_tests {
+    use super::{transfers, Direction, Transfer};
+
+    #[test]
+    fn registry_test() {
+        let transfer = Transfer::queue(Direction::Send, "pc");
+        let info = |t: &Transfer| {
+            transfers()
+                .into_iter()
+                .find(|info| info.id == t.id)
+                .unwrap()
+        };
+        assert!(!info(&transfer).active);
+        transfer.start_file("a.txt", Some(10));
+        transfer.progress(4);
+        transfer.progress(4);
+        let started = info(&transfer);
+        assert!(started.active);
+        assert_eq!((started.done, started.total), (8, Some(10)));
+        let id = transfer.id;
+        assert!(!transfer.is_cancelled());
+        assert!(super::cancel(id));
+        assert!(transfer.is_cancelled());
+        drop(transfer);
+        assert!(transfers().iter().all(|info| info.id != id));
+        assert!(!super::cancel(id));
+    }
+}
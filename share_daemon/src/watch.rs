@@ -0,0 +1,217 @@
+//! Outbox folders watched with inotify, each file placed there is shared to the host of the
+//! folder on its own request.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use futures::StreamExt;
+use inotify::{EventMask, EventStream, Inotify, WatchMask};
+use tokio::time::Instant;
+
+use crate::{
+    common::LocalResponse,
+    config::{AfterSent, WatchFolder},
+    fanout, global, handler,
+};
+
+/// The subfolder sent files are moved to.
+pub(crate) const SENT_DIR_NAME: &str = "sent";
+/// How long a file which could not be sent waits before it is tried again.
+const RETRY_DELAY: Duration = Duration::from_secs(60);
+const TICK: Duration = Duration::from_secs(1);
+
+type Events = EventStream<[u8; 4096]>;
+
+/// Starts watching every folder, a folder which cannot be watched is skipped.
+pub(crate) fn start(folders: &[WatchFolder]) {
+    for folder in folders {
+        match watch(&folder.path) {
+            Ok(events) => {
+                log::info!(
+                    "Watching `{}` for files to share to `{}`",
+                    folder.path.display(),
+                    folder.host
+                );
+                tokio::spawn(run(folder.clone(), events));
+            }
+            Err(e) => log::error!(
+                "Watch folder `{}` failed! Detail: {}",
+                folder.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+fn watch(dir: &Path) -> std::io::Result<Events> {
+    let inotify = Inotify::init()?;
+    inotify.watches().add(
+        dir,
+        WatchMask::CREATE | WatchMask::MODIFY | WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO,
+    )?;
+    inotify.into_event_stream([0; 4096])
+}
+
+async fn run(folder: WatchFolder, mut events: Events) {
+    let mut pending = Pending::default();
+    // Files placed there while the daemon was not running.
+    if let Ok(entries) = std::fs::read_dir(&folder.path) {
+        let ready_at = Instant::now() + folder.settle();
+        for entry in entries.flatten() {
+            pending.touch(entry.path(), ready_at);
+        }
+    }
+    let mut tick = tokio::time::interval(TICK);
+    loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(event)) => {
+                    if let Some(name) = event.name.filter(|_| !event.mask.contains(EventMask::ISDIR)) {
+                        pending.touch(folder.path.join(name), Instant::now() + folder.settle());
+                    }
+                }
+                Some(Err(e)) => {
+                    log::error!("Watching `{}` stopped! Detail: {}", folder.path.display(), e);
+                    return;
+                }
+                None => return,
+            },
+            _ = tick.tick() => {
+                for path in pending.settled(Instant::now(), folder.settle()) {
+                    if !send(&folder, &path).await {
+                        pending.touch(path, Instant::now() + RETRY_DELAY);
+                    } else if let Err(e) = finish(&folder, &path) {
+                        log::error!("Cleaning up the sent `{}` failed! Detail: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Shares the file through the same path as a local `SHARE` request, returns whether it arrived.
+async fn send(folder: &WatchFolder, path: &Path) -> bool {
+    let resolved = {
+        let config = global::config_store().await.read().await;
+        fanout::resolve_destinations(&config, &folder.host)
+    };
+    let dest = match resolved {
+        Ok((false, mut dests)) => dests.remove(0),
+        Ok((true, _)) => {
+            log::error!(
+                "Watch folder `{}` has to name a single host, not `{}`",
+                folder.path.display(),
+                folder.host
+            );
+            return false;
+        }
+        Err(resp) => {
+            log::error!("Sharing `{}` failed: {}", path.display(), resp);
+            return false;
+        }
+    };
+    let mut output = Vec::new();
+    if let Err(e) = handler::handle_file_send(&dest, &mut output, vec![path.to_owned()]).await {
+        log::error!("Sharing `{}` failed! Detail: {}", path.display(), e);
+        return false;
+    }
+    // The last line is the result of the batch.
+    let output = String::from_utf8_lossy(&output);
+    let result = output.lines().last().unwrap_or_default();
+    if result != LocalResponse::AllFilesSucceeded.to_str_unchecked() {
+        log::warn!("Sharing `{}` failed: {}", path.display(), result);
+        return false;
+    }
+    log::info!("Shared `{}` to `{}`", path.display(), dest.name);
+    true
+}
+
+fn finish(folder: &WatchFolder, path: &Path) -> std::io::Result<()> {
+    match folder.after_sent {
+        AfterSent::Move => {
+            let sent_dir = folder.path.join(SENT_DIR_NAME);
+            std::fs::create_dir_all(&sent_dir)?;
+            let name = path.file_name().unwrap_or_default();
+            std::fs::rename(path, sent_dir.join(name))
+        }
+        AfterSent::Delete => std::fs::remove_file(path),
+    }
+}
+
+/// The files waiting until they stopped changing, with when they are ready and their size then.
+#[derive(Default)]
+struct Pending(HashMap<PathBuf, (Instant, u64)>);
+
+impl Pending {
+    /// Hidden files, such as the temporary files of downloads, and directories are left alone.
+    fn touch(&mut self, path: PathBuf, ready_at: Instant) {
+        let hidden = path
+            .file_name()
+            .is_none_or(|name| name.to_string_lossy().starts_with('.'));
+        match path.metadata() {
+            Ok(metadata) if metadata.is_file() && !hidden => {
+                self.0.insert(path, (ready_at, metadata.len()));
+            }
+            _ => (),
+        }
+    }
+
+    /// Takes the files which are ready, a file whose size still changes waits `settle` again.
+    fn settled(&mut self, now: Instant, settle: Duration) -> Vec<PathBuf> {
+        let mut ready = vec![];
+        self.0.retain(|path, (ready_at, size)| {
+            if *ready_at > now {
+                return true;
+            }
+            match path.metadata() {
+                Ok(metadata) if metadata.len() != *size => {
+                    *ready_at = now + settle;
+                    *size = metadata.len();
+                    true
+                }
+                Ok(metadata) if metadata.is_file() => {
+                    ready.push(path.clone());
+                    false
+                }
+                _ => false,
+            }
+        });
+        ready.sort();
+        ready
+    }
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::Pending;
+
+    #[test]
+    fn pending_test() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let (file, hidden) = (dir.join("a.txt"), dir.join(".a.txt.part"));
+        std::fs::write(&file, "a").unwrap();
+        std::fs::write(&hidden, "a").unwrap();
+
+        let now = Instant::now();
+        let mut pending = Pending::default();
+        pending.touch(file.clone(), now + Duration::from_secs(2));
+        pending.touch(hidden, now);
+        pending.touch(dir.to_path_buf(), now);
+        let settle = Duration::from_secs(2);
+        assert!(pending.settled(now, settle).is_empty());
+
+        std::fs::write(&file, "ab").unwrap();
+        let later = now + Duration::from_secs(3);
+        assert!(pending.settled(later, settle).is_empty());
+        assert_eq!(pending.settled(later + settle, settle), [file]);
+        assert!(pending.0.is_empty());
+    }
+}
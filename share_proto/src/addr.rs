@@ -0,0 +1,128 @@
+use std::net::SocketAddr;
+
+use smol_str::SmolStr;
+
+use crate::consts;
+
+/// Where a registered host is reached, a literal socket address or a DNS name and a port which is
+/// resolved on each connection.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HostAddr {
+    Socket(SocketAddr),
+    Dns(SmolStr, u16),
+}
+
+impl HostAddr {
+    /// Parses a link-local address with an interface name as scope, like `[fe80::1%eth0]:10020`.
+    #[cfg(unix)]
+    fn parse_scope_name(s: &str) -> Option<SocketAddr> {
+        let (host, port) = s.strip_prefix('[')?.split_once("]:")?;
+        let (ip, interface) = host.split_once('%')?;
+        let interface = std::ffi::CString::new(interface).ok()?;
+        let scope_id = unsafe { libc::if_nametoindex(interface.as_ptr()) };
+        if scope_id == 0 {
+            return None;
+        }
+        Some(SocketAddr::V6(std::net::SocketAddrV6::new(
+            ip.parse().ok()?,
+            port.parse().ok()?,
+            0,
+            scope_id,
+        )))
+    }
+
+    #[cfg(not(unix))]
+    fn parse_scope_name(_: &str) -> Option<SocketAddr> {
+        None
+    }
+
+    fn check_dns_name(name: &str) -> bool {
+        name.len() <= 253
+            && name.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+            })
+    }
+}
+
+impl From<SocketAddr> for HostAddr {
+    fn from(addr: SocketAddr) -> Self {
+        HostAddr::Socket(addr)
+    }
+}
+
+impl std::str::FromStr for HostAddr {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let socket_err = match s.parse::<SocketAddr>() {
+            Ok(addr) => return Ok(HostAddr::Socket(addr)),
+            Err(e) => e,
+        };
+        if let Some(addr) = Self::parse_scope_name(s) {
+            return Ok(HostAddr::Socket(addr));
+        }
+        match s.rsplit_once(consts::PAIR_SEP) {
+            Some((name, port)) if Self::check_dns_name(name) => port
+                .parse::<u16>()
+                .map(|port| HostAddr::Dns(name.to_ascii_lowercase().into(), port))
+                .map_err(|_| socket_err),
+            _ => Err(socket_err),
+        }
+    }
+}
+
+impl std::fmt::Display for HostAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostAddr::Socket(addr) => addr.fmt(f),
+            HostAddr::Dns(name, port) => write!(f, "{}:{}", name, port),
+        }
+    }
+}
+
+impl serde::Serialize for HostAddr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for HostAddr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <SmolStr as serde::Deserialize>::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod addr_tests {
+    use super::HostAddr;
+
+    #[test]
+    fn parse_host_addr_test() {
+        assert_eq!(
+            "Pc.LAN:10020".parse::<HostAddr>(),
+            Ok(HostAddr::Dns("pc.lan".into(), 10020))
+        );
+        assert!("pc.lan".parse::<HostAddr>().is_err());
+        assert!("-pc.lan:10020".parse::<HostAddr>().is_err());
+    }
+
+    /// The loopback interface is only named `lo` on Linux.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parse_link_local_test() {
+        use std::net::SocketAddr;
+
+        let Ok(HostAddr::Socket(SocketAddr::V6(link_local))) = "[fe80::1%lo]:10020".parse() else {
+            panic!("interface scope not parsed");
+        };
+        assert_ne!(link_local.scope_id(), 0);
+        assert!("[fe80::1%nosuchif0]:10020".parse::<HostAddr>().is_err());
+    }
+}
@@ -0,0 +1,825 @@
+use std::net::SocketAddr;
+
+use smol_str::{SmolStr, ToSmolStr};
+
+use crate::{consts, Event, HostAddr, TransferInfo};
+
+#[derive(Debug, Clone)]
+pub enum RequestCommand {
+    Local(LocalCommand),
+    PortCheck,
+}
+
+impl RequestCommand {
+    const PORT_CHECK: &'static str = "PORT";
+    pub fn as_str(&self) -> &str {
+        match self {
+            RequestCommand::Local(c) => c.as_str(),
+            RequestCommand::PortCheck => Self::PORT_CHECK,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum LocalCommand {
+    Share,
+    Register,
+}
+
+impl LocalCommand {
+    const SHARE: &'static str = "SHARE";
+    const REG: &'static str = "REG";
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.as_str().as_bytes()
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            LocalCommand::Share => Self::SHARE,
+            LocalCommand::Register => Self::REG,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Response {
+    InvalidHostname,
+    RegisterSucceeded,
+    UnexpectedResponse,
+    Remote(RemoteResponse),
+    Local(LocalResponse),
+}
+
+impl Response {
+    const REG_SUCCEEDED: &'static str = "REG_SUCCEEDED";
+    const UNEXPECTED_RESP: &'static str = "UNEXPECTED_RESPONSE";
+    const INVALID_HOSTNAME: &'static str = "INVALID_HOSTNAME";
+}
+
+impl ToSmolStr for Response {
+    fn to_smolstr(&self) -> SmolStr {
+        match self {
+            Response::InvalidHostname => Self::INVALID_HOSTNAME.into(),
+            Response::RegisterSucceeded => Self::REG_SUCCEEDED.into(),
+            Response::UnexpectedResponse => Self::UNEXPECTED_RESP.into(),
+            Response::Remote(r_resp) => r_resp.to_smolstr(),
+            Response::Local(l_resp) => l_resp.to_smolstr(),
+        }
+    }
+}
+
+impl std::str::FromStr for Response {
+    type Err = Response;
+
+    /// Parses any line the daemon answers a local request with.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            Self::INVALID_HOSTNAME => Ok(Self::InvalidHostname),
+            Self::REG_SUCCEEDED => Ok(Self::RegisterSucceeded),
+            Self::UNEXPECTED_RESP => Ok(Self::UnexpectedResponse),
+            line => line
+                .parse()
+                .map(Self::Local)
+                .or_else(|_| line.parse().map(Self::Remote)),
+        }
+    }
+}
+
+impl Response {
+    /// Explains a failure for the user.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Response::InvalidHostname => "The host name is invalid",
+            Response::RegisterSucceeded => "The host was registered",
+            Response::Remote(RemoteResponse::InvalidRequest) => {
+                "The daemon did not understand the request"
+            }
+            Response::Local(l) => l.description(),
+            _ => "Unexpected response",
+        }
+    }
+
+    pub fn to_str_unchecked(self) -> &'static str {
+        match self {
+            Response::InvalidHostname => Self::INVALID_HOSTNAME,
+            Response::RegisterSucceeded => Self::REG_SUCCEEDED,
+            Response::UnexpectedResponse => Self::UNEXPECTED_RESP,
+            Response::Remote(r) => r.to_str_unchecked(),
+            Response::Local(l) => l.to_str_unchecked(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum RemoteResponse {
+    UnregisteredHost,
+    NoAvailablePort,
+    PortConfirm(u16),
+    InvalidPort,
+    FilesReceived(u8),
+    UnexpectedEndFlag(u8),
+    InvalidRequest,
+    UnknownTarget,
+    TargetUnreachable,
+    NotAccepted,
+    QuotaExceeded(u8),
+    /// A file did not match its hash after it was rebuilt, with the files received before.
+    ChecksumMismatch(u8),
+    RegPending,
+    RegAccepted,
+    RegRejected,
+    /// The files received and deleted by a sync.
+    Synced(u32, u32),
+}
+
+impl RemoteResponse {
+    const UNREGISTERED_HOST: &'static str = "UNREG_HOST";
+    const NO_AVAILABLE_PORT: &'static str = "NO_AVAILABLE_PORT";
+    const PORT_CONFIRM: &'static str = "PORT_CONFIRM";
+    const INVALID_PORT: &'static str = "INVALID_PORT";
+    const FILES_RECEIVED: &'static str = "FILES_RECEIVED";
+    const UNEXPECTED_END_FLAG: &'static str = "UNEXPECTED_END_FLAG";
+    const INVALID_REQUEST: &'static str = "INVALID_REQUEST";
+    const UNKNOWN_TARGET: &'static str = "UNKNOWN_TARGET";
+    const TARGET_UNREACHABLE: &'static str = "TARGET_UNREACHABLE";
+    const NOT_ACCEPTED: &'static str = "NOT_ACCEPTED";
+    const QUOTA_EXCEEDED: &'static str = "QUOTA_EXCEEDED";
+    const CHECKSUM_MISMATCH: &'static str = "CHECKSUM_MISMATCH";
+    const REG_PENDING: &'static str = "REG_PENDING";
+    const REG_ACCEPTED: &'static str = "REG_ACCEPTED";
+    const REG_REJECTED: &'static str = "REG_REJECTED";
+    const SYNCED: &'static str = "SYNCED";
+}
+
+impl std::str::FromStr for RemoteResponse {
+    type Err = Response;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(Response::UnexpectedResponse);
+        }
+        let mut maybe_pair = s.trim().split(consts::STARTLINE_SEP);
+        match unsafe { maybe_pair.next().unwrap_unchecked() } {
+            Self::PORT_CONFIRM => {
+                if let Some(port_str) = maybe_pair.next() {
+                    if let Ok(p) = port_str.parse::<u16>() {
+                        return Ok(Self::PortConfirm(p));
+                    }
+                }
+                Err(Response::UnexpectedResponse)
+            }
+            Self::FILES_RECEIVED => {
+                if let Some(count_str) = maybe_pair.next() {
+                    if let Ok(count) = count_str.parse::<u8>() {
+                        return Ok(Self::FilesReceived(count));
+                    }
+                }
+                Err(Response::UnexpectedResponse)
+            }
+            Self::UNEXPECTED_END_FLAG => {
+                if let Some(count_str) = maybe_pair.next() {
+                    if let Ok(count) = count_str.parse::<u8>() {
+                        return Ok(Self::UnexpectedEndFlag(count));
+                    }
+                }
+                Err(Response::UnexpectedResponse)
+            }
+            Self::QUOTA_EXCEEDED => {
+                if let Some(count_str) = maybe_pair.next() {
+                    if let Ok(count) = count_str.parse::<u8>() {
+                        return Ok(Self::QuotaExceeded(count));
+                    }
+                }
+                Err(Response::UnexpectedResponse)
+            }
+            Self::CHECKSUM_MISMATCH => {
+                if let Some(count_str) = maybe_pair.next() {
+                    if let Ok(count) = count_str.parse::<u8>() {
+                        return Ok(Self::ChecksumMismatch(count));
+                    }
+                }
+                Err(Response::UnexpectedResponse)
+            }
+            Self::SYNCED => match (maybe_pair.next(), maybe_pair.next()) {
+                (Some(sent), Some(deleted)) => match (sent.parse(), deleted.parse()) {
+                    (Ok(sent), Ok(deleted)) => Ok(Self::Synced(sent, deleted)),
+                    _ => Err(Response::UnexpectedResponse),
+                },
+                _ => Err(Response::UnexpectedResponse),
+            },
+            Self::NO_AVAILABLE_PORT => Ok(Self::NoAvailablePort),
+            Self::UNREGISTERED_HOST => Ok(Self::UnregisteredHost),
+            Self::INVALID_PORT => Ok(Self::InvalidPort),
+            Self::INVALID_REQUEST => Ok(Self::InvalidRequest),
+            Self::UNKNOWN_TARGET => Ok(Self::UnknownTarget),
+            Self::TARGET_UNREACHABLE => Ok(Self::TargetUnreachable),
+            Self::NOT_ACCEPTED => Ok(Self::NotAccepted),
+            Self::REG_PENDING => Ok(Self::RegPending),
+            Self::REG_ACCEPTED => Ok(Self::RegAccepted),
+            Self::REG_REJECTED => Ok(Self::RegRejected),
+            _ => Err(Response::UnexpectedResponse),
+        }
+    }
+}
+
+impl ToSmolStr for RemoteResponse {
+    fn to_smolstr(&self) -> SmolStr {
+        match self {
+            RemoteResponse::UnregisteredHost => Self::UNREGISTERED_HOST.to_smolstr(),
+            RemoteResponse::NoAvailablePort => Self::NO_AVAILABLE_PORT.to_smolstr(),
+            RemoteResponse::PortConfirm(p) => {
+                smol_str::format_smolstr!("{} {}", Self::PORT_CONFIRM, p)
+            }
+            RemoteResponse::InvalidPort => Self::INVALID_PORT.to_smolstr(),
+            RemoteResponse::FilesReceived(n) => {
+                smol_str::format_smolstr!("{} {}", Self::FILES_RECEIVED, *n)
+            }
+            RemoteResponse::UnexpectedEndFlag(count) => {
+                smol_str::format_smolstr!("{} {}", Self::UNEXPECTED_END_FLAG, *count)
+            }
+            RemoteResponse::InvalidRequest => Self::INVALID_REQUEST.to_smolstr(),
+            RemoteResponse::UnknownTarget => Self::UNKNOWN_TARGET.to_smolstr(),
+            RemoteResponse::TargetUnreachable => Self::TARGET_UNREACHABLE.to_smolstr(),
+            RemoteResponse::NotAccepted => Self::NOT_ACCEPTED.to_smolstr(),
+            RemoteResponse::QuotaExceeded(count) => {
+                smol_str::format_smolstr!("{} {}", Self::QUOTA_EXCEEDED, *count)
+            }
+            RemoteResponse::ChecksumMismatch(count) => {
+                smol_str::format_smolstr!("{} {}", Self::CHECKSUM_MISMATCH, *count)
+            }
+            RemoteResponse::RegPending => Self::REG_PENDING.to_smolstr(),
+            RemoteResponse::RegAccepted => Self::REG_ACCEPTED.to_smolstr(),
+            RemoteResponse::RegRejected => Self::REG_REJECTED.to_smolstr(),
+            RemoteResponse::Synced(sent, deleted) => {
+                smol_str::format_smolstr!("{} {} {}", Self::SYNCED, sent, deleted)
+            }
+        }
+    }
+}
+
+impl RemoteResponse {
+    pub fn to_str_unchecked(self) -> &'static str {
+        match self {
+            RemoteResponse::UnregisteredHost => Self::UNREGISTERED_HOST,
+            RemoteResponse::NoAvailablePort => Self::NO_AVAILABLE_PORT,
+            RemoteResponse::InvalidPort => Self::INVALID_PORT,
+            RemoteResponse::InvalidRequest => Self::INVALID_REQUEST,
+            RemoteResponse::UnknownTarget => Self::UNKNOWN_TARGET,
+            RemoteResponse::TargetUnreachable => Self::TARGET_UNREACHABLE,
+            RemoteResponse::NotAccepted => Self::NOT_ACCEPTED,
+            RemoteResponse::RegPending => Self::REG_PENDING,
+            RemoteResponse::RegAccepted => Self::REG_ACCEPTED,
+            RemoteResponse::RegRejected => Self::REG_REJECTED,
+            _ => "",
+        }
+    }
+}
+
+/// A registered host as listed in `REG_HOST` lines.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HostInfo {
+    pub name: SmolStr,
+    pub addrs: Vec<HostAddr>,
+    /// The fingerprint of the key the host was paired with.
+    pub fingerprint: Option<SmolStr>,
+    pub auto_accept: bool,
+    pub last_seen: Option<u64>,
+    pub last_transfer: Option<u64>,
+    pub description: SmolStr,
+}
+
+#[derive(Debug, Clone)]
+pub enum LocalResponse {
+    RemoteUnregistered,
+    RemoteNoAvailablePort,
+    UnreachableAddress(HostAddr),
+    AllFilesSucceeded,
+    FileInfo(SmolStr, Option<u64>),
+    Progress(f64),
+    FilesSucceeded(u8),
+    LocalRegisterFailed,
+    UnexpectedSendResp,
+    ReplacedAddress(HostAddr),
+    UnregisteredHostname,
+    AnyPathInvalid,
+    UnexpectedRemoteResponse,
+    Peer(SmolStr, SocketAddr, SmolStr),
+    UnknownPeer,
+    ListEnd,
+    PairCode(SmolStr),
+    Paired(SmolStr, SocketAddr),
+    PairFailed,
+    RelayUnknownTarget,
+    RelayTargetUnreachable,
+    Host(SmolStr, Box<LocalResponse>),
+    RemoteNotAccepted,
+    RemoteQuotaExceeded(u8),
+    RemoteChecksumMismatch(u8),
+    FileSent(SmolStr),
+    FileFailed(SmolStr),
+    RemoteRegPending,
+    RemoteRegRejected,
+    RegRequest(SmolStr, SocketAddr),
+    UnknownRegRequest,
+    RegRequestRejected,
+    RegHost(Box<HostInfo>),
+    HostUpdated,
+    HostnameTaken,
+    Status(SmolStr, SmolStr),
+    Transfer(TransferInfo),
+    Subscribed,
+    Event(Event),
+    TransferId(u64),
+    Cancelled,
+    UnknownTransfer,
+    /// The files sent and deleted by a sync.
+    Synced(u32, u32),
+}
+
+impl ToSmolStr for LocalResponse {
+    fn to_smolstr(&self) -> SmolStr {
+        match self {
+            LocalResponse::RemoteUnregistered => Self::R_UNREG_HOST.to_smolstr(),
+            LocalResponse::RemoteNoAvailablePort => Self::R_NO_AVAILABLE_PORT.to_smolstr(),
+            LocalResponse::UnreachableAddress(a) => {
+                smol_str::format_smolstr!("{} {}", Self::UNREACHABLE, a)
+            }
+            LocalResponse::AllFilesSucceeded => Self::ALL_FILES_SUCCEEDED.to_smolstr(),
+            LocalResponse::FileInfo(name, size) => smol_str::format_smolstr!(
+                "{} {}:{}",
+                Self::FILE_INFO,
+                name,
+                size.map(|u| u as i64).unwrap_or(-1)
+            ),
+            LocalResponse::Progress(p) => smol_str::format_smolstr!("{} {}", Self::PROGRESS, *p),
+            LocalResponse::FilesSucceeded(files_count) => {
+                smol_str::format_smolstr!("{} {}", Self::FILES_SUCCEEDED, *files_count)
+            }
+            LocalResponse::LocalRegisterFailed => Self::L_REG_FAILED.to_smolstr(),
+            LocalResponse::UnexpectedSendResp => Self::UNEXPECTED_SEND_RESP.to_smolstr(),
+            LocalResponse::ReplacedAddress(addr) => {
+                smol_str::format_smolstr!("{} {}", Self::REPLACED, addr)
+            }
+            LocalResponse::UnregisteredHostname => Self::UNREGISTERED_HOSTNAME.to_smolstr(),
+            LocalResponse::AnyPathInvalid => Self::ANY_PATH_INVALID.to_smolstr(),
+            LocalResponse::UnexpectedRemoteResponse => Self::UNEXPECTED_REMOTE_RESP.to_smolstr(),
+            LocalResponse::Peer(name, addr, fingerprint) => {
+                smol_str::format_smolstr!("{} {} {} {}", Self::PEER, name, addr, fingerprint)
+            }
+            LocalResponse::UnknownPeer => Self::UNKNOWN_PEER.to_smolstr(),
+            LocalResponse::ListEnd => Self::LIST_END.to_smolstr(),
+            LocalResponse::PairCode(code) => {
+                smol_str::format_smolstr!("{} {}", Self::PAIR_CODE, code)
+            }
+            LocalResponse::Paired(name, addr) => {
+                smol_str::format_smolstr!("{} {} {}", Self::PAIRED, name, addr)
+            }
+            LocalResponse::PairFailed => Self::PAIR_FAILED.to_smolstr(),
+            LocalResponse::RelayUnknownTarget => Self::R_UNKNOWN_TARGET.to_smolstr(),
+            LocalResponse::RelayTargetUnreachable => Self::R_TARGET_UNREACHABLE.to_smolstr(),
+            LocalResponse::Host(name, resp) => {
+                smol_str::format_smolstr!("{} {} {}", Self::HOST, name, resp.to_smolstr())
+            }
+            LocalResponse::RemoteNotAccepted => Self::R_NOT_ACCEPTED.to_smolstr(),
+            LocalResponse::RemoteQuotaExceeded(count) => {
+                smol_str::format_smolstr!("{} {}", Self::R_QUOTA_EXCEEDED, *count)
+            }
+            LocalResponse::RemoteChecksumMismatch(count) => {
+                smol_str::format_smolstr!("{} {}", Self::R_CHECKSUM_MISMATCH, *count)
+            }
+            LocalResponse::FileSent(name) => {
+                smol_str::format_smolstr!("{} {}", Self::FILE_SENT, name)
+            }
+            LocalResponse::FileFailed(name) => {
+                smol_str::format_smolstr!("{} {}", Self::FILE_FAILED, name)
+            }
+            LocalResponse::RemoteRegPending => Self::R_REG_PENDING.to_smolstr(),
+            LocalResponse::RemoteRegRejected => Self::R_REG_REJECTED.to_smolstr(),
+            LocalResponse::RegRequest(name, addr) => {
+                smol_str::format_smolstr!("{} {} {}", Self::REG_REQUEST, name, addr)
+            }
+            LocalResponse::UnknownRegRequest => Self::UNKNOWN_REG_REQUEST.to_smolstr(),
+            LocalResponse::RegRequestRejected => Self::REG_REQUEST_REJECTED.to_smolstr(),
+            LocalResponse::RegHost(host) => Self::reg_host_line(host),
+            LocalResponse::HostUpdated => Self::HOST_UPDATED.to_smolstr(),
+            LocalResponse::HostnameTaken => Self::HOSTNAME_TAKEN.to_smolstr(),
+            LocalResponse::Status(key, value) => {
+                smol_str::format_smolstr!("{} {} {}", Self::STATUS, key, value)
+            }
+            LocalResponse::Transfer(info) => smol_str::format_smolstr!(
+                "{} {} {} {} {} {} {} {}",
+                Self::TRANSFER,
+                info.id,
+                info.direction.as_str(),
+                if info.active { "active" } else { "queued" },
+                info.host,
+                info.done,
+                optional_field(info.total),
+                info.file
+            ),
+            LocalResponse::Subscribed => Self::SUBSCRIBED.to_smolstr(),
+            LocalResponse::Event(event) => smol_str::format_smolstr!("{} {}", Self::EVENT, event),
+            LocalResponse::TransferId(id) => {
+                smol_str::format_smolstr!("{} {}", Self::TRANSFER_ID, id)
+            }
+            LocalResponse::Cancelled => Self::CANCELLED.to_smolstr(),
+            LocalResponse::UnknownTransfer => Self::UNKNOWN_TRANSFER.to_smolstr(),
+            LocalResponse::Synced(sent, deleted) => {
+                smol_str::format_smolstr!("{} {} {}", Self::SYNCED, sent, deleted)
+            }
+        }
+    }
+}
+
+impl LocalResponse {
+    pub fn to_str_unchecked(self) -> &'static str {
+        match self {
+            LocalResponse::RemoteUnregistered => Self::R_UNREG_HOST,
+            LocalResponse::RemoteNoAvailablePort => Self::R_NO_AVAILABLE_PORT,
+            LocalResponse::AllFilesSucceeded => Self::ALL_FILES_SUCCEEDED,
+            LocalResponse::LocalRegisterFailed => Self::L_REG_FAILED,
+            LocalResponse::UnexpectedSendResp => Self::UNEXPECTED_SEND_RESP,
+            LocalResponse::UnregisteredHostname => Self::UNREGISTERED_HOSTNAME,
+            LocalResponse::AnyPathInvalid => Self::ANY_PATH_INVALID,
+            LocalResponse::UnexpectedRemoteResponse => Self::UNEXPECTED_REMOTE_RESP,
+            LocalResponse::UnknownPeer => Self::UNKNOWN_PEER,
+            LocalResponse::ListEnd => Self::LIST_END,
+            LocalResponse::PairFailed => Self::PAIR_FAILED,
+            LocalResponse::RelayUnknownTarget => Self::R_UNKNOWN_TARGET,
+            LocalResponse::RelayTargetUnreachable => Self::R_TARGET_UNREACHABLE,
+            LocalResponse::RemoteNotAccepted => Self::R_NOT_ACCEPTED,
+            LocalResponse::RemoteRegPending => Self::R_REG_PENDING,
+            LocalResponse::RemoteRegRejected => Self::R_REG_REJECTED,
+            LocalResponse::UnknownRegRequest => Self::UNKNOWN_REG_REQUEST,
+            LocalResponse::RegRequestRejected => Self::REG_REQUEST_REJECTED,
+            LocalResponse::HostUpdated => Self::HOST_UPDATED,
+            LocalResponse::HostnameTaken => Self::HOSTNAME_TAKEN,
+            LocalResponse::Subscribed => Self::SUBSCRIBED,
+            LocalResponse::Cancelled => Self::CANCELLED,
+            LocalResponse::UnknownTransfer => Self::UNKNOWN_TRANSFER,
+            _ => "",
+        }
+    }
+}
+
+impl LocalResponse {
+    /// Explains the response for the user, a `HOST` line by its inner response.
+    pub fn description(&self) -> &'static str {
+        match self {
+            LocalResponse::RemoteUnregistered => "The remote host has not registered this host",
+            LocalResponse::RemoteNoAvailablePort => "The remote host has no port available",
+            LocalResponse::UnreachableAddress(_) => "The host is unreachable",
+            LocalResponse::AllFilesSucceeded => "All files were received",
+            LocalResponse::FileInfo(..) => "A file is announced",
+            LocalResponse::Progress(_) => "A file is being sent",
+            LocalResponse::FilesSucceeded(_) => "Only some of the files were received",
+            LocalResponse::LocalRegisterFailed => "Saving the registration failed",
+            LocalResponse::UnexpectedSendResp => {
+                "The remote host answered the transfer unexpectedly"
+            }
+            LocalResponse::ReplacedAddress(_) => "The host was registered with a new address",
+            LocalResponse::UnregisteredHostname => "No host is registered under this name",
+            LocalResponse::AnyPathInvalid => "A path is not a file",
+            LocalResponse::UnexpectedRemoteResponse => "The remote host answered unexpectedly",
+            LocalResponse::Peer(..) => "A daemon was discovered on the LAN",
+            LocalResponse::UnknownPeer => "No such peer was discovered",
+            LocalResponse::ListEnd => "The list is complete",
+            LocalResponse::PairCode(_) => "A pairing code is waiting to be typed",
+            LocalResponse::Paired(..) => "The hosts are paired",
+            LocalResponse::PairFailed => "Pairing failed",
+            LocalResponse::RelayUnknownTarget => "The relay does not know the target host",
+            LocalResponse::RelayTargetUnreachable => "The relay cannot reach the target host",
+            LocalResponse::Host(_, resp) => resp.description(),
+            LocalResponse::RemoteNotAccepted => {
+                "The remote host does not accept files from this host"
+            }
+            LocalResponse::RemoteQuotaExceeded(_) => "The quota of the remote host is exceeded",
+            LocalResponse::RemoteChecksumMismatch(_) => {
+                "A file arrived at the remote host corrupted"
+            }
+            LocalResponse::FileSent(_) => "The file was sent",
+            LocalResponse::FileFailed(_) => "The file could not be sent",
+            LocalResponse::RemoteRegPending => "Waiting for the remote host to confirm",
+            LocalResponse::RemoteRegRejected => "The remote host rejected the registration",
+            LocalResponse::RegRequest(..) => "A host asks to be registered",
+            LocalResponse::UnknownRegRequest => "No such registration request is pending",
+            LocalResponse::RegRequestRejected => "The registration request was rejected",
+            LocalResponse::RegHost(_) => "A registered host",
+            LocalResponse::HostUpdated => "The host was updated",
+            LocalResponse::HostnameTaken => "The name is taken by another host",
+            LocalResponse::Status(..) => "A status value of the daemon",
+            LocalResponse::Transfer(_) => "A transfer in progress",
+            LocalResponse::Subscribed => "Subscribed to the events of the daemon",
+            LocalResponse::Event(_) => "Something happened in the daemon",
+            LocalResponse::TransferId(_) => "The transfer is queued",
+            LocalResponse::Cancelled => "The transfer was cancelled",
+            LocalResponse::UnknownTransfer => "No such transfer is in progress",
+            LocalResponse::Synced(..) => "The directory is mirrored",
+        }
+    }
+
+    /// Parses a `FILE_INFO name:size` header, a negative size means the size is unknown.
+    pub fn parse_file_info(line: &str) -> Option<(&str, Option<u64>)> {
+        let (tag, pair) = line.trim().split_once(consts::STARTLINE_SEP)?;
+        if tag != Self::FILE_INFO {
+            return None;
+        }
+        let (name, size_str) = pair.rsplit_once(consts::PAIR_SEP)?;
+        let size = size_str.parse::<i64>().ok()?;
+        Some((name, u64::try_from(size).ok()))
+    }
+}
+
+impl LocalResponse {
+    /// `REG_HOST <name> <addrs> <key fingerprint> <accept|deny> <last seen> <last transfer>
+    /// [description]`, the addresses are comma separated, times are unix seconds and `-` stands
+    /// for a missing value.
+    fn reg_host_line(host: &HostInfo) -> SmolStr {
+        use std::fmt::Write as _;
+        let mut line = String::from(Self::REG_HOST);
+        let _ = write!(line, " {} ", host.name);
+        for (i, addr) in host.addrs.iter().enumerate() {
+            if i != 0 {
+                line.push(consts::HOSTS_SEP);
+            }
+            let _ = write!(line, "{}", addr);
+        }
+        let _ = write!(
+            line,
+            " {} {} {} {}",
+            host.fingerprint.as_deref().unwrap_or("-"),
+            if host.auto_accept { "accept" } else { "deny" },
+            optional_field(host.last_seen),
+            optional_field(host.last_transfer)
+        );
+        if !host.description.is_empty() {
+            let _ = write!(line, " {}", host.description);
+        }
+        line.into()
+    }
+
+    fn parse_reg_host(args: &str) -> Option<HostInfo> {
+        let mut fields = args.splitn(7, consts::STARTLINE_SEP);
+        let name = fields.next()?.into();
+        let addrs = fields
+            .next()?
+            .split(consts::HOSTS_SEP)
+            .map(|a| a.parse().ok())
+            .collect::<Option<Vec<_>>>()?;
+        let fingerprint = Some(fields.next()?).filter(|f| *f != "-").map(Into::into);
+        let auto_accept = match fields.next()? {
+            "accept" => true,
+            "deny" => false,
+            _ => return None,
+        };
+        Some(HostInfo {
+            name,
+            addrs,
+            fingerprint,
+            auto_accept,
+            last_seen: parse_optional_field(fields.next()?)?,
+            last_transfer: parse_optional_field(fields.next()?)?,
+            description: fields.next().unwrap_or_default().into(),
+        })
+    }
+
+    fn parse_transfer(args: &str) -> Option<TransferInfo> {
+        let mut fields = args.splitn(7, consts::STARTLINE_SEP);
+        Some(TransferInfo {
+            id: fields.next()?.parse().ok()?,
+            direction: fields.next()?.parse().ok()?,
+            active: match fields.next()? {
+                "active" => true,
+                "queued" => false,
+                _ => return None,
+            },
+            host: fields.next()?.into(),
+            done: fields.next()?.parse().ok()?,
+            total: parse_optional_field(fields.next()?)?,
+            file: fields.next().unwrap_or_default().into(),
+        })
+    }
+}
+
+/// `-` for a missing value, as used by list lines.
+fn optional_field(value: Option<u64>) -> SmolStr {
+    value.map_or_else(|| "-".to_smolstr(), |v| v.to_smolstr())
+}
+
+fn parse_optional_field(field: &str) -> Option<Option<u64>> {
+    if field == "-" {
+        return Some(None);
+    }
+    field.parse().ok().map(Some)
+}
+
+impl std::str::FromStr for LocalResponse {
+    type Err = Response;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (tag, args) = s.split_once(consts::STARTLINE_SEP).unwrap_or((s, ""));
+        let name_and_addr = || {
+            let (name, addr) = args.split_once(consts::STARTLINE_SEP)?;
+            Some((name.to_smolstr(), addr.parse::<SocketAddr>().ok()?))
+        };
+        let resp = match tag {
+            Self::R_UNREG_HOST => Some(Self::RemoteUnregistered),
+            Self::R_NO_AVAILABLE_PORT => Some(Self::RemoteNoAvailablePort),
+            Self::UNREACHABLE => args.parse().ok().map(Self::UnreachableAddress),
+            Self::ALL_FILES_SUCCEEDED => Some(Self::AllFilesSucceeded),
+            Self::FILE_INFO => {
+                Self::parse_file_info(s).map(|(name, size)| Self::FileInfo(name.into(), size))
+            }
+            Self::PROGRESS => args.parse().ok().map(Self::Progress),
+            Self::FILES_SUCCEEDED => args.parse().ok().map(Self::FilesSucceeded),
+            Self::L_REG_FAILED => Some(Self::LocalRegisterFailed),
+            Self::UNEXPECTED_SEND_RESP => Some(Self::UnexpectedSendResp),
+            Self::REPLACED => args.parse().ok().map(Self::ReplacedAddress),
+            Self::UNREGISTERED_HOSTNAME => Some(Self::UnregisteredHostname),
+            Self::ANY_PATH_INVALID => Some(Self::AnyPathInvalid),
+            Self::UNEXPECTED_REMOTE_RESP => Some(Self::UnexpectedRemoteResponse),
+            Self::PEER => {
+                let mut fields = args.split(consts::STARTLINE_SEP);
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(name), Some(addr), Some(fingerprint)) => addr
+                        .parse()
+                        .ok()
+                        .map(|addr| Self::Peer(name.into(), addr, fingerprint.into())),
+                    _ => None,
+                }
+            }
+            Self::UNKNOWN_PEER => Some(Self::UnknownPeer),
+            Self::LIST_END => Some(Self::ListEnd),
+            Self::PAIR_CODE if !args.is_empty() => Some(Self::PairCode(args.into())),
+            Self::PAIRED => name_and_addr().map(|(name, addr)| Self::Paired(name, addr)),
+            Self::PAIR_FAILED => Some(Self::PairFailed),
+            Self::R_UNKNOWN_TARGET => Some(Self::RelayUnknownTarget),
+            Self::R_TARGET_UNREACHABLE => Some(Self::RelayTargetUnreachable),
+            Self::HOST => {
+                let (name, inner) = args
+                    .split_once(consts::STARTLINE_SEP)
+                    .ok_or(Response::UnexpectedResponse)?;
+                Some(Self::Host(name.into(), Box::new(inner.parse()?)))
+            }
+            Self::R_NOT_ACCEPTED => Some(Self::RemoteNotAccepted),
+            Self::R_QUOTA_EXCEEDED => args.parse().ok().map(Self::RemoteQuotaExceeded),
+            Self::R_CHECKSUM_MISMATCH => args.parse().ok().map(Self::RemoteChecksumMismatch),
+            Self::FILE_SENT => Some(Self::FileSent(args.into())),
+            Self::FILE_FAILED => Some(Self::FileFailed(args.into())),
+            Self::R_REG_PENDING => Some(Self::RemoteRegPending),
+            Self::R_REG_REJECTED => Some(Self::RemoteRegRejected),
+            Self::REG_REQUEST => name_and_addr().map(|(name, addr)| Self::RegRequest(name, addr)),
+            Self::UNKNOWN_REG_REQUEST => Some(Self::UnknownRegRequest),
+            Self::REG_REQUEST_REJECTED => Some(Self::RegRequestRejected),
+            Self::REG_HOST => Self::parse_reg_host(args).map(|host| Self::RegHost(Box::new(host))),
+            Self::HOST_UPDATED => Some(Self::HostUpdated),
+            Self::HOSTNAME_TAKEN => Some(Self::HostnameTaken),
+            Self::STATUS => args
+                .split_once(consts::STARTLINE_SEP)
+                .map(|(key, value)| Self::Status(key.into(), value.into())),
+            Self::TRANSFER => Self::parse_transfer(args).map(Self::Transfer),
+            Self::SUBSCRIBED => Some(Self::Subscribed),
+            Self::EVENT => args.parse().ok().map(Self::Event),
+            Self::TRANSFER_ID => args.parse().ok().map(Self::TransferId),
+            Self::CANCELLED => Some(Self::Cancelled),
+            Self::UNKNOWN_TRANSFER => Some(Self::UnknownTransfer),
+            Self::SYNCED => args
+                .split_once(consts::STARTLINE_SEP)
+                .and_then(|(sent, deleted)| {
+                    Some(Self::Synced(sent.parse().ok()?, deleted.parse().ok()?))
+                }),
+            _ => None,
+        };
+        resp.ok_or(Response::UnexpectedResponse)
+    }
+}
+
+impl LocalResponse {
+    const FILE_INFO: &'static str = "FILE_INFO";
+    const R_UNREG_HOST: &'static str = "R_UNREG_HOST";
+    const L_REG_FAILED: &'static str = "L_REG_FAILED";
+    const R_NO_AVAILABLE_PORT: &'static str = "R_NO_AVAILABLE_PORT";
+
+    const UNEXPECTED_REMOTE_RESP: &'static str = "UNEXPECTED_R_RESP";
+    const UNREACHABLE: &'static str = "UNREACHABLE";
+    const ALL_FILES_SUCCEEDED: &'static str = "ALL_FILES_SUCCEEDED";
+    const PROGRESS: &'static str = "PROGRESS";
+    const UNEXPECTED_SEND_RESP: &'static str = "UNEXPECTED_SEND_RESP";
+    const FILES_SUCCEEDED: &'static str = "FILES_SUCCEEDED";
+    const REPLACED: &'static str = "REPLACED";
+    const UNREGISTERED_HOSTNAME: &'static str = "UNREG_HOSTNAME";
+    const ANY_PATH_INVALID: &'static str = "ANY_PATH_INVALID";
+    const PEER: &'static str = "PEER";
+    const UNKNOWN_PEER: &'static str = "UNKNOWN_PEER";
+    const LIST_END: &'static str = "LIST_END";
+    const PAIR_CODE: &'static str = "PAIR_CODE";
+    const PAIRED: &'static str = "PAIRED";
+    const PAIR_FAILED: &'static str = "PAIR_FAILED";
+    const R_UNKNOWN_TARGET: &'static str = "R_UNKNOWN_TARGET";
+    const R_TARGET_UNREACHABLE: &'static str = "R_TARGET_UNREACHABLE";
+    const R_NOT_ACCEPTED: &'static str = "R_NOT_ACCEPTED";
+    const R_QUOTA_EXCEEDED: &'static str = "R_QUOTA_EXCEEDED";
+    const R_CHECKSUM_MISMATCH: &'static str = "R_CHECKSUM_MISMATCH";
+    const HOST: &'static str = "HOST";
+    const FILE_SENT: &'static str = "FILE_SENT";
+    const FILE_FAILED: &'static str = "FILE_FAILED";
+    const R_REG_PENDING: &'static str = "R_REG_PENDING";
+    const R_REG_REJECTED: &'static str = "R_REG_REJECTED";
+    const REG_REQUEST: &'static str = "REG_REQUEST";
+    const UNKNOWN_REG_REQUEST: &'static str = "UNKNOWN_REG_REQUEST";
+    const REG_REQUEST_REJECTED: &'static str = "REG_REQUEST_REJECTED";
+    const REG_HOST: &'static str = "REG_HOST";
+    const HOST_UPDATED: &'static str = "HOST_UPDATED";
+    const HOSTNAME_TAKEN: &'static str = "HOSTNAME_TAKEN";
+    const STATUS: &'static str = "STATUS";
+    const TRANSFER: &'static str = "TRANSFER";
+    const SUBSCRIBED: &'static str = "SUBSCRIBED";
+    const EVENT: &'static str = "EVENT";
+    const TRANSFER_ID: &'static str = "TRANSFER_ID";
+    const CANCELLED: &'static str = "CANCELLED";
+    const UNKNOWN_TRANSFER: &'static str = "UNKNOWN_TRANSFER";
+    const SYNCED: &'static str = "SYNCED";
+}
+
+#[derive(Debug, Clone)]
+pub struct StartLine {
+    tag: RequestCommand,
+    extra_args: Option<SmolStr>,
+}
+
+impl StartLine {
+    pub const LENGTH_LIMIT: u64 = 50;
+
+    pub fn tag(&self) -> &RequestCommand {
+        &self.tag
+    }
+
+    pub fn extra_args(&self) -> Option<&str> {
+        self.extra_args.as_deref()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    start_line: StartLine,
+    extra_data: Option<SmolStr>,
+}
+
+impl Request {
+    pub fn start_line(&self) -> &StartLine {
+        &self.start_line
+    }
+
+    pub fn extra_data(&self) -> Option<&str> {
+        self.extra_data.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod number_tests {}
+
+#[cfg(test)]
+mod response_tests {
+    use smol_str::ToSmolStr;
+
+    use super::{LocalResponse, Response};
+
+    #[test]
+    fn parse_round_trip_test() {
+        for line in [
+            "UNREACHABLE [::1]:10020",
+            "UNREACHABLE localhost:10020",
+            "FILE_INFO a b.txt:12",
+            "PROGRESS 0.25",
+            "HOST pc@nas FILES_SUCCEEDED 2",
+            "HOST pc FILE_SENT a b.txt",
+            "PEER pc 192.168.1.2:10020 0011223344556677",
+            "REG_HOST pc 192.168.1.2:10020,pc.lan:10020 - accept 1700000000 - the desk pc",
+            "REG_HOST nas [fe80::1]:10020 0011223344556677 deny - -",
+            "STATUS workers 1/4",
+            "TRANSFER 3 receive active pc 40 - a b.txt",
+            "TRANSFER 4 relay queued pc->nas 0 100 ",
+            "TRANSFER_ID 4",
+            "HOST pc CANCELLED",
+            "SYNCED 3 1",
+            "HOST nas R_CHECKSUM_MISMATCH 1",
+        ] {
+            let resp = line.parse::<LocalResponse>().unwrap();
+            assert_eq!(resp.to_smolstr().trim_end(), line.trim_end());
+        }
+        assert!("HOST pc".parse::<LocalResponse>().is_err());
+        assert!("REG_HOST pc 1.2.3.4:1 - maybe - -"
+            .parse::<LocalResponse>()
+            .is_err());
+        assert!(matches!(
+            "REG_SUCCEEDED".parse::<Response>(),
+            Ok(Response::RegisterSucceeded)
+        ));
+        assert!(matches!(
+            "INVALID_REQUEST".parse::<Response>(),
+            Ok(Response::Remote(_))
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt::Display;
+
+use smol_str::{SmolStr, ToSmolStr};
+
+use crate::{consts, Direction, HostAddr};
+
+/// Something that happened in a daemon, streamed to the local processes which subscribed.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// A host is about to send files to this one.
+    ShareAnnounced {
+        transfer: u64,
+        host: SmolStr,
+    },
+    FileReceived {
+        host: SmolStr,
+        size: u64,
+        path: SmolStr,
+    },
+    Progress {
+        transfer: u64,
+        direction: Direction,
+        host: SmolStr,
+        done: u64,
+        total: Option<u64>,
+        file: SmolStr,
+    },
+    TransferFinished {
+        transfer: u64,
+        direction: Direction,
+        host: SmolStr,
+    },
+    TransferFailed {
+        transfer: u64,
+        direction: Direction,
+        host: SmolStr,
+    },
+    HostRegistered {
+        name: SmolStr,
+        addr: HostAddr,
+    },
+    ConfigReloaded,
+    /// The subscriber fell behind and missed this many events.
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl Event {
+    const SHARE_ANNOUNCED: &'static str = "share_announced";
+    const FILE_RECEIVED: &'static str = "file_received";
+    const PROGRESS: &'static str = "progress";
+    const TRANSFER_FINISHED: &'static str = "transfer_finished";
+    const TRANSFER_FAILED: &'static str = "transfer_failed";
+    const HOST_REGISTERED: &'static str = "host_registered";
+    const CONFIG_RELOADED: &'static str = "config_reloaded";
+    const LAGGED: &'static str = "lagged";
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::ShareAnnounced { .. } => Self::SHARE_ANNOUNCED,
+            Event::FileReceived { .. } => Self::FILE_RECEIVED,
+            Event::Progress { .. } => Self::PROGRESS,
+            Event::TransferFinished { .. } => Self::TRANSFER_FINISHED,
+            Event::TransferFailed { .. } => Self::TRANSFER_FAILED,
+            Event::HostRegistered { .. } => Self::HOST_REGISTERED,
+            Event::ConfigReloaded => Self::CONFIG_RELOADED,
+            Event::Lagged { .. } => Self::LAGGED,
+        }
+    }
+}
+
+/// `<kind> <fields>`, a path or file name is always the last field and may contain spaces.
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind())?;
+        match self {
+            Event::ShareAnnounced { transfer, host } => write!(f, " {} {}", transfer, host),
+            Event::FileReceived { host, size, path } => write!(f, " {} {} {}", host, size, path),
+            Event::Progress {
+                transfer,
+                direction,
+                host,
+                done,
+                total,
+                file,
+            } => write!(
+                f,
+                " {} {} {} {} {} {}",
+                transfer,
+                direction.as_str(),
+                host,
+                done,
+                total.map_or_else(|| "-".to_smolstr(), |t| t.to_smolstr()),
+                file
+            ),
+            Event::TransferFinished {
+                transfer,
+                direction,
+                host,
+            }
+            | Event::TransferFailed {
+                transfer,
+                direction,
+                host,
+            } => write!(f, " {} {} {}", transfer, direction.as_str(), host),
+            Event::HostRegistered { name, addr } => write!(f, " {} {}", name, addr),
+            Event::ConfigReloaded => Ok(()),
+            Event::Lagged { missed } => write!(f, " {}", missed),
+        }
+    }
+}
+
+impl std::str::FromStr for Event {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, args) = s.split_once(consts::STARTLINE_SEP).unwrap_or((s, ""));
+        let mut fields = args.splitn(6, consts::STARTLINE_SEP);
+        let mut next = || fields.next().ok_or(());
+        let event = match kind {
+            Self::SHARE_ANNOUNCED => Event::ShareAnnounced {
+                transfer: next()?.parse().map_err(|_| ())?,
+                host: next()?.into(),
+            },
+            Self::FILE_RECEIVED => {
+                let mut fields = args.splitn(3, consts::STARTLINE_SEP);
+                let mut next = || fields.next().ok_or(());
+                Event::FileReceived {
+                    host: next()?.into(),
+                    size: next()?.parse().map_err(|_| ())?,
+                    path: next()?.into(),
+                }
+            }
+            Self::PROGRESS => Event::Progress {
+                transfer: next()?.parse().map_err(|_| ())?,
+                direction: next()?.parse()?,
+                host: next()?.into(),
+                done: next()?.parse().map_err(|_| ())?,
+                total: match next()? {
+                    "-" => None,
+                    total => Some(total.parse().map_err(|_| ())?),
+                },
+                file: fields.next().unwrap_or_default().into(),
+            },
+            Self::TRANSFER_FINISHED | Self::TRANSFER_FAILED => {
+                let transfer = next()?.parse().map_err(|_| ())?;
+                let direction = next()?.parse()?;
+                let host = next()?.into();
+                if kind == Self::TRANSFER_FINISHED {
+                    Event::TransferFinished {
+                        transfer,
+                        direction,
+                        host,
+                    }
+                } else {
+                    Event::TransferFailed {
+                        transfer,
+                        direction,
+                        host,
+                    }
+                }
+            }
+            Self::HOST_REGISTERED => Event::HostRegistered {
+                name: next()?.into(),
+                addr: next()?.parse().map_err(|_| ())?,
+            },
+            Self::CONFIG_RELOADED => Event::ConfigReloaded,
+            Self::LAGGED => Event::Lagged {
+                missed: next()?.parse().map_err(|_| ())?,
+            },
+            _ => return Err(()),
+        };
+        Ok(event)
+    }
+}
@@ -0,0 +1,35 @@
+//! The line protocol spoken between share daemons and by local processes to their daemon.
+
+pub mod common;
+pub mod event;
+pub mod manifest;
+pub mod name;
+pub mod request;
+pub mod request_tag;
+
+mod addr;
+mod transfer;
+
+pub use addr::HostAddr;
+pub use event::Event;
+pub use name::{FileName, Hostname, Port, ValueError};
+pub use request::LocalRequest;
+pub use transfer::{Direction, TransferInfo};
+
+pub mod consts {
+    pub const DEFAULT_CONFIG_DIR_NAME: &str = ".tinyfileshare";
+    pub const DEFAULT_CONFIG_FILE_NAME: &str = "config.toml";
+    pub const MIN_PORT: u16 = 3000;
+    pub const DEFAULT_PORT: u16 = 10020;
+    pub const DEFAULT_IPC_SOCK_NAME: &str = "share.sock";
+    pub const HOST_NAME_LENGTH_LIMIT: usize = 20;
+    pub const LINE_SEP: &str = "\r\n";
+    pub const ASCII_SPACE: char = ' ';
+    pub const STARTLINE_SEP: char = ' ';
+    pub const PAIR_SEP: char = ':';
+    pub const RELAY_SEP: char = '@';
+    pub const HOSTS_SEP: char = ',';
+    pub const FILE_NAME_LENGTH_LIMIT: usize = 260;
+    pub const NUMBER_PATHS_PER_REQUEST: usize = 4;
+    pub const FILE_PATH_LIMIT: u64 = 500;
+}
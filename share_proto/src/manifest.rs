@@ -0,0 +1,156 @@
+//! The manifest of a mirrored directory, exchanged by `SYNC` so that only new or changed files
+//! are sent.
+
+use std::collections::{HashMap, HashSet};
+
+use smol_str::SmolStr;
+
+use crate::{consts, request_tag::sync, FileName};
+
+/// A file of a mirrored directory, `path` is relative to the directory with `/` separators.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: SmolStr,
+    pub size: u64,
+    /// Unix seconds of the last modification.
+    pub mtime: u64,
+    /// The hex encoded SHA-256 of the content.
+    pub hash: SmolStr,
+}
+
+impl ManifestEntry {
+    /// `ENTRY <size> <mtime> <hash> <path>`, the path comes last since it may contain spaces.
+    pub fn to_line(&self) -> SmolStr {
+        smol_str::format_smolstr!(
+            "{} {} {} {} {}",
+            sync::ENTRY,
+            self.size,
+            self.mtime,
+            self.hash,
+            self.path
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim_end().splitn(5, consts::STARTLINE_SEP);
+        if fields.next()? != sync::ENTRY {
+            return None;
+        }
+        let size = fields.next()?.parse().ok()?;
+        let mtime = fields.next()?.parse().ok()?;
+        let hash = fields.next()?.into();
+        let path = fields.next().filter(|p| is_safe_relative(p))?.into();
+        Some(Self {
+            path,
+            size,
+            mtime,
+            hash,
+        })
+    }
+}
+
+/// Whether a relative path stays inside the mirrored directory, every component has to be a
+/// valid file name.
+pub fn is_safe_relative(path: &str) -> bool {
+    path.len() < consts::FILE_PATH_LIMIT as usize && path.split('/').all(FileName::is_valid)
+}
+
+/// What the sender of a sync has to do so that the receiver mirrors its directory.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    /// The paths of the files to send.
+    pub send: Vec<SmolStr>,
+    /// The paths of the files the receiver removes.
+    pub delete: Vec<SmolStr>,
+}
+
+impl SyncPlan {
+    /// Compares the files of the sender with the manifest of the receiver. A file of the same size
+    /// but another modification time is only sent if `same_content` tells it changed, which
+    /// spares hashing the unchanged files. Files only the receiver has are deleted if `delete`.
+    pub fn new(
+        local: &[ManifestEntry],
+        remote: &[ManifestEntry],
+        delete: bool,
+        mut same_content: impl FnMut(&ManifestEntry, &ManifestEntry) -> bool,
+    ) -> Self {
+        let mut plan = Self::default();
+        let remote_by_path: HashMap<&SmolStr, &ManifestEntry> =
+            remote.iter().map(|r| (&r.path, r)).collect();
+        for entry in local {
+            let unchanged = remote_by_path.get(&entry.path).is_some_and(|r| {
+                r.size == entry.size && (r.mtime == entry.mtime || same_content(entry, r))
+            });
+            if !unchanged {
+                plan.send.push(entry.path.clone());
+            }
+        }
+        if delete {
+            let local_paths: HashSet<&SmolStr> = local.iter().map(|l| &l.path).collect();
+            plan.delete = remote
+                .iter()
+                .filter(|r| !local_paths.contains(&r.path))
+                .map(|r| r.path.clone())
+                .collect();
+        }
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.send.is_empty() && self.delete.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod manifest_tests {
+    use super::{is_safe_relative, ManifestEntry, SyncPlan};
+
+    fn entry(path: &str, size: u64, mtime: u64, hash: &str) -> ManifestEntry {
+        ManifestEntry {
+            path: path.into(),
+            size,
+            mtime,
+            hash: hash.into(),
+        }
+    }
+
+    #[test]
+    fn entry_line_test() {
+        let e = entry("docs/a b.txt", 12, 1_700_000_000, "00ff");
+        assert_eq!(e.to_line(), "ENTRY 12 1700000000 00ff docs/a b.txt");
+        assert_eq!(ManifestEntry::parse(&e.to_line()), Some(e));
+        assert_eq!(ManifestEntry::parse("ENTRY 1 2 00 ../etc/passwd"), None);
+        assert!(is_safe_relative("a/b"));
+        assert!(!is_safe_relative("/a"));
+        assert!(!is_safe_relative("a//b"));
+    }
+
+    #[test]
+    fn plan_test() {
+        let local = [
+            entry("same", 1, 10, ""),
+            entry("touched", 2, 11, ""),
+            entry("edited", 3, 12, ""),
+            entry("grown", 4, 13, ""),
+            entry("new", 5, 14, ""),
+        ];
+        let remote = [
+            entry("same", 1, 10, "a"),
+            entry("touched", 2, 1, "b"),
+            entry("edited", 3, 1, "c"),
+            entry("grown", 1, 13, "d"),
+            entry("gone", 6, 15, "e"),
+        ];
+        let mut hashed = vec![];
+        let plan = SyncPlan::new(&local, &remote, true, |l, r| {
+            hashed.push(l.path.clone());
+            r.hash == "b"
+        });
+        assert_eq!(plan.send, ["edited", "grown", "new"]);
+        assert_eq!(plan.delete, ["gone"]);
+        assert_eq!(hashed, ["touched", "edited"]);
+        assert!(SyncPlan::new(&local, &remote, false, |_, _| true)
+            .delete
+            .is_empty());
+    }
+}
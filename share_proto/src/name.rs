@@ -0,0 +1,225 @@
+use std::ops::Deref;
+
+use smol_str::SmolStr;
+
+use crate::consts;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum ValueError {
+    #[error(
+        "A host name must be 1..={} bytes long, without spaces, `,`, `@` or `:`",
+        consts::HOST_NAME_LENGTH_LIMIT
+    )]
+    Hostname,
+    #[error(
+        "A file name must be 1..={} bytes long, without path separators",
+        consts::FILE_NAME_LENGTH_LIMIT
+    )]
+    FileName,
+    #[error("A port must be at least {}", consts::MIN_PORT)]
+    Port,
+    #[error(
+        "A share needs at least one host and 1..={} paths",
+        consts::NUMBER_PATHS_PER_REQUEST
+    )]
+    Share,
+    #[error(
+        "A path must be shorter than {} bytes and on a single line",
+        consts::FILE_PATH_LIMIT
+    )]
+    Path,
+    #[error("A pairing code is a single word")]
+    PairCode,
+}
+
+/// The name a host is registered with, one word of a request line.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(try_from = "SmolStr", into = "SmolStr")]
+pub struct Hostname(SmolStr);
+
+impl Hostname {
+    pub fn is_valid(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= consts::HOST_NAME_LENGTH_LIMIT
+            && !name.contains(|c: char| {
+                c.is_whitespace()
+                    || c.is_control()
+                    || [consts::HOSTS_SEP, consts::RELAY_SEP, consts::PAIR_SEP].contains(&c)
+            })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for Hostname {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !Self::is_valid(s) {
+            return Err(ValueError::Hostname);
+        }
+        Ok(Self(s.into()))
+    }
+}
+
+impl TryFrom<SmolStr> for Hostname {
+    type Error = ValueError;
+
+    fn try_from(s: SmolStr) -> Result<Self, Self::Error> {
+        if !Self::is_valid(&s) {
+            return Err(ValueError::Hostname);
+        }
+        Ok(Self(s))
+    }
+}
+
+impl From<Hostname> for SmolStr {
+    fn from(name: Hostname) -> Self {
+        name.0
+    }
+}
+
+impl Deref for Hostname {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for Hostname {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The name a file is announced and saved with, never a path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileName(SmolStr);
+
+impl FileName {
+    pub fn is_valid(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= consts::FILE_NAME_LENGTH_LIMIT
+            && name != "."
+            && name != ".."
+            && !name.contains(|c: char| c == '/' || c == '\\' || c.is_control())
+    }
+
+    /// Makes a valid name of any file name, truncating it to the length limit and replacing what
+    /// is not allowed with `_`.
+    pub fn lossy(name: &str) -> Self {
+        let mut end = name.len().min(consts::FILE_NAME_LENGTH_LIMIT);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        let name: SmolStr = name[..end]
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+        match name.as_str() {
+            "" => Self("_".into()),
+            "." | ".." => Self(name.replace('.', "_").into()),
+            _ => Self(name),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for FileName {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !Self::is_valid(s) {
+            return Err(ValueError::FileName);
+        }
+        Ok(Self(s.into()))
+    }
+}
+
+impl Deref for FileName {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for FileName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A port a daemon listens on, the low ones are left to well-known services.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Port(u16);
+
+impl Port {
+    pub fn new(port: u16) -> Result<Self, ValueError> {
+        if port < consts::MIN_PORT {
+            return Err(ValueError::Port);
+        }
+        Ok(Self(port))
+    }
+
+    pub fn get(self) -> u16 {
+        self.0
+    }
+}
+
+impl std::str::FromStr for Port {
+    type Err = ValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s.parse().map_err(|_| ValueError::Port)?)
+    }
+}
+
+impl std::fmt::Display for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod name_tests {
+    use super::{FileName, Hostname, Port, ValueError};
+
+    #[test]
+    fn validate_test() {
+        assert!("desk-pc".parse::<Hostname>().is_ok());
+        for name in [
+            "",
+            "a b",
+            "pc@nas",
+            "pc,nas",
+            "pc:1",
+            "a-very-long-host-name",
+        ] {
+            assert_eq!(name.parse::<Hostname>(), Err(ValueError::Hostname));
+        }
+        assert!("a b.txt".parse::<FileName>().is_ok());
+        for name in ["", ".", "..", "a/b", "a\\b", &"x".repeat(261)] {
+            assert_eq!(name.parse::<FileName>(), Err(ValueError::FileName));
+        }
+        assert_eq!(FileName::lossy("a/b").as_str(), "a_b");
+        assert_eq!(FileName::lossy("..").as_str(), "__");
+        let long = "é".repeat(200);
+        assert!(FileName::lossy(&long).len() <= 260);
+        assert_eq!("10020".parse::<Port>().map(Port::get), Ok(10020));
+        assert_eq!("80".parse::<Port>(), Err(ValueError::Port));
+        assert_eq!("port".parse::<Port>(), Err(ValueError::Port));
+    }
+}
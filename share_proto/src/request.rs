@@ -0,0 +1,218 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use smol_str::SmolStr;
+
+use crate::{consts, request_tag::local, HostAddr, Hostname, ValueError};
+
+/// A request of a local process to its daemon.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LocalRequest {
+    /// Sends the files to registered hosts or host groups, `<target>@<relay>` sends through a
+    /// relay.
+    Share {
+        hosts: Vec<SmolStr>,
+        paths: Vec<PathBuf>,
+    },
+    /// Registers a host, a remote registration waits until the peer's user confirmed it.
+    Register {
+        name: Hostname,
+        addr: HostAddr,
+        remote: bool,
+    },
+    Peers,
+    RegisterPeer(Hostname),
+    PairOffer,
+    Pair {
+        code: SmolStr,
+        addr: SocketAddr,
+    },
+    RegistrationRequests,
+    ApproveRegistration(Hostname),
+    RejectRegistration(Hostname),
+    Hosts,
+    Unregister(Hostname),
+    Rename(Hostname, Hostname),
+    SetAddr(Hostname, Vec<HostAddr>),
+    Status,
+    Subscribe,
+    /// Stops a transfer in progress, the id is announced by `TRANSFER_ID` and listed by `STATUS`.
+    Cancel(u64),
+    /// Mirrors the directory to a registered host, only new or changed files are sent. With
+    /// `delete` the files removed from the directory are removed on the host as well.
+    Sync {
+        host: Hostname,
+        dir: PathBuf,
+        delete: bool,
+    },
+}
+
+impl LocalRequest {
+    pub fn tag(&self) -> &'static str {
+        match self {
+            LocalRequest::Share { .. } => local::SHARE,
+            LocalRequest::Register { remote: false, .. } => local::REG,
+            LocalRequest::Register { remote: true, .. } => local::REG_REMOTE,
+            LocalRequest::Peers => local::PEERS,
+            LocalRequest::RegisterPeer(_) => local::REG_PEER,
+            LocalRequest::PairOffer => local::PAIR_OFFER,
+            LocalRequest::Pair { .. } => local::PAIR,
+            LocalRequest::RegistrationRequests => local::REG_PENDING,
+            LocalRequest::ApproveRegistration(_) => local::REG_APPROVE,
+            LocalRequest::RejectRegistration(_) => local::REG_REJECT,
+            LocalRequest::Hosts => local::HOSTS,
+            LocalRequest::Unregister(_) => local::UNREG,
+            LocalRequest::Rename(..) => local::RENAME,
+            LocalRequest::SetAddr(..) => local::SET_ADDR,
+            LocalRequest::Status => local::STATUS,
+            LocalRequest::Subscribe => local::SUBSCRIBE,
+            LocalRequest::Cancel(_) => local::CANCEL,
+            LocalRequest::Sync { .. } => local::SYNC,
+        }
+    }
+
+    /// Checks what the types do not, the hosts and paths of a share and the pairing code.
+    pub fn check(&self) -> Result<(), ValueError> {
+        let check_path = |path: &PathBuf| {
+            let path = path.to_string_lossy();
+            if path.is_empty()
+                || path.contains(['\r', '\n'])
+                || path.len() as u64 >= consts::FILE_PATH_LIMIT
+            {
+                return Err(ValueError::Path);
+            }
+            Ok(())
+        };
+        match self {
+            LocalRequest::Share { hosts, paths } => {
+                if hosts.is_empty() || paths.is_empty() {
+                    return Err(ValueError::Share);
+                }
+                if paths.len() > consts::NUMBER_PATHS_PER_REQUEST {
+                    return Err(ValueError::Share);
+                }
+                for host in hosts {
+                    if !host.split(consts::RELAY_SEP).all(Hostname::is_valid) {
+                        return Err(ValueError::Hostname);
+                    }
+                }
+                paths.iter().try_for_each(check_path)
+            }
+            LocalRequest::Sync { dir, .. } => check_path(dir),
+            LocalRequest::Pair { code, .. }
+                if code.is_empty() || code.contains(char::is_whitespace) =>
+            {
+                Err(ValueError::PairCode)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The request as sent to the daemon, every line ends with a line separator and the paths of
+    /// a share are ended by an empty line.
+    pub fn encode(&self) -> String {
+        let mut request = String::from(self.tag());
+        let mut arg = |a: &dyn std::fmt::Display| {
+            request.push(consts::STARTLINE_SEP);
+            request.push_str(&a.to_string());
+        };
+        match self {
+            LocalRequest::Share { hosts, .. } => {
+                arg(&hosts.join(&consts::HOSTS_SEP.to_string()));
+            }
+            LocalRequest::Register { name, addr, .. } => {
+                arg(&format_args!("{}{}{}", name, consts::PAIR_SEP, addr));
+            }
+            LocalRequest::RegisterPeer(name)
+            | LocalRequest::ApproveRegistration(name)
+            | LocalRequest::RejectRegistration(name)
+            | LocalRequest::Unregister(name) => arg(name),
+            LocalRequest::Cancel(id) => arg(id),
+            LocalRequest::Sync { host, delete, .. } => {
+                arg(host);
+                if *delete {
+                    arg(&local::SYNC_DELETE);
+                }
+            }
+            LocalRequest::Pair { code, addr } => {
+                arg(code);
+                arg(addr);
+            }
+            LocalRequest::Rename(name, new_name) => {
+                arg(name);
+                arg(new_name);
+            }
+            LocalRequest::SetAddr(name, addrs) => {
+                arg(name);
+                let addrs: Vec<_> = addrs.iter().map(ToString::to_string).collect();
+                arg(&addrs.join(&consts::HOSTS_SEP.to_string()));
+            }
+            LocalRequest::Peers
+            | LocalRequest::PairOffer
+            | LocalRequest::RegistrationRequests
+            | LocalRequest::Hosts
+            | LocalRequest::Status
+            | LocalRequest::Subscribe => (),
+        }
+        request.push_str(consts::LINE_SEP);
+        if let LocalRequest::Share { paths, .. } = self {
+            for path in paths {
+                request.push_str(&path.to_string_lossy());
+                request.push_str(consts::LINE_SEP);
+            }
+            request.push_str(consts::LINE_SEP);
+        }
+        if let LocalRequest::Sync { dir, .. } = self {
+            request.push_str(&dir.to_string_lossy());
+            request.push_str(consts::LINE_SEP);
+        }
+        request
+    }
+}
+
+#[cfg(test)]
+mod request_tests {
+    use super::LocalRequest;
+    use crate::ValueError;
+
+    #[test]
+    fn encode_test() {
+        let share = LocalRequest::Share {
+            hosts: vec!["pc".into(), "nas@desk".into()],
+            paths: vec!["/tmp/a b.txt".into(), "/tmp/c".into()],
+        };
+        assert_eq!(share.check(), Ok(()));
+        assert_eq!(
+            share.encode(),
+            "SHARE pc,nas@desk\r\n/tmp/a b.txt\r\n/tmp/c\r\n\r\n"
+        );
+        let reg = LocalRequest::Register {
+            name: "pc".parse().unwrap(),
+            addr: "pc.lan:10020".parse().unwrap(),
+            remote: true,
+        };
+        assert_eq!(reg.encode(), "REG_REMOTE pc:pc.lan:10020\r\n");
+        let set_addr = LocalRequest::SetAddr(
+            "pc".parse().unwrap(),
+            vec![
+                "pc.lan:10020".parse().unwrap(),
+                "[::1]:10020".parse().unwrap(),
+            ],
+        );
+        assert_eq!(
+            set_addr.encode(),
+            "SET_ADDR pc pc.lan:10020,[::1]:10020\r\n"
+        );
+        assert_eq!(LocalRequest::Status.encode(), "STATUS\r\n");
+        let sync = LocalRequest::Sync {
+            host: "pc".parse().unwrap(),
+            dir: "/tmp/photos".into(),
+            delete: true,
+        };
+        assert_eq!(sync.encode(), "SYNC pc delete\r\n/tmp/photos\r\n");
+        let share = LocalRequest::Share {
+            hosts: vec!["a b".into()],
+            paths: vec!["/tmp/c".into()],
+        };
+        assert_eq!(share.check(), Err(ValueError::Hostname));
+    }
+}
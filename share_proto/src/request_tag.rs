@@ -0,0 +1,76 @@
+pub mod local {
+    pub const SHARE: &str = "SHARE";
+    pub const REG: &str = "REG";
+    pub const PEERS: &str = "PEERS";
+    pub const REG_PEER: &str = "REG_PEER";
+    pub const PAIR_OFFER: &str = "PAIR_OFFER";
+    pub const PAIR: &str = "PAIR";
+    pub const REG_REMOTE: &str = "REG_REMOTE";
+    pub const REG_PENDING: &str = "REG_PENDING";
+    pub const REG_APPROVE: &str = "REG_APPROVE";
+    pub const REG_REJECT: &str = "REG_REJECT";
+    pub const HOSTS: &str = "HOSTS";
+    pub const UNREG: &str = "UNREG";
+    pub const RENAME: &str = "RENAME";
+    pub const SET_ADDR: &str = "SET_ADDR";
+    pub const STATUS: &str = "STATUS";
+    pub const SUBSCRIBE: &str = "SUBSCRIBE";
+    pub const CANCEL: &str = "CANCEL";
+    pub const SYNC: &str = "SYNC";
+    /// The argument of `SYNC` which removes what was removed from the directory.
+    pub const SYNC_DELETE: &str = "delete";
+}
+
+pub mod remote {
+    pub const PORT: &str = "PORT";
+    pub const RELAY: &str = "RELAY";
+    pub const REG: &str = "REG";
+    pub const SYNC: &str = "SYNC";
+}
+
+/// The lines of a `SYNC` exchange after its start line.
+pub mod sync {
+    pub const ENTRY: &str = "ENTRY";
+    pub const MANIFEST_END: &str = "MANIFEST_END";
+    pub const FILE: &str = "FILE";
+    pub const DELETE: &str = "DELETE";
+    pub const SYNC_END: &str = "SYNC_END";
+}
+
+pub mod send_flag {
+    pub const SEND_START: &str = "SEND_START";
+    pub const SEND_END: &str = "SEND_END";
+    /// Follows `PORT_CONFIRM` with the optional parts of the protocol the receiver supports, such
+    /// as `HASH`. The sender lists the ones it uses after `SEND_START`.
+    pub const CAPS: &str = "CAPS";
+    /// Follows a file header with the content hash, answered with `HAVE` or `SEND`. Only sent if
+    /// both sides listed it.
+    pub const HASH: &str = "HASH";
+    /// The receiver already has the content, the file is not sent.
+    pub const HAVE: &str = "HAVE";
+    pub const SEND: &str = "SEND";
+}
+
+/// The lines of a delta transfer, which both sides list as `DELTA` in their `CAPS` and
+/// `SEND_START` lines.
+pub mod delta {
+    /// Answers `HASH` with `DELTA <block size> <size>` and a signature line per block.
+    pub const DELTA: &str = "DELTA";
+    pub const COPY: &str = "COPY";
+    pub const DATA: &str = "DATA";
+    pub const DELTA_END: &str = "DELTA_END";
+}
+
+pub mod pair {
+    pub const PAIR: &str = "PAIR";
+    pub const PAIR_MSG: &str = "PAIR_MSG";
+    pub const PAIR_ID: &str = "PAIR_ID";
+    pub const PAIRED: &str = "PAIRED";
+    pub const PAIR_FAILED: &str = "PAIR_FAILED";
+    /// Sent instead of the next step when the peer's name is registered for another host.
+    pub const NAME_TAKEN: &str = "PAIR_NAME_TAKEN";
+}
+
+pub mod discovery {
+    pub const ANNOUNCE: &str = "FSHARE_ANNOUNCE";
+}
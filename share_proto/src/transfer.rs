@@ -0,0 +1,46 @@
+use smol_str::SmolStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Send,
+    Receive,
+    Relay,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Send => "send",
+            Direction::Receive => "receive",
+            Direction::Relay => "relay",
+        }
+    }
+}
+
+impl std::str::FromStr for Direction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "send" => Ok(Direction::Send),
+            "receive" => Ok(Direction::Receive),
+            "relay" => Ok(Direction::Relay),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A snapshot of a transfer in progress.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TransferInfo {
+    pub id: u64,
+    pub direction: Direction,
+    pub host: SmolStr,
+    /// Whether data is flowing, a queued transfer still waits for its peer.
+    pub active: bool,
+    /// The file being transferred, empty for relays.
+    pub file: SmolStr,
+    pub done: u64,
+    pub total: Option<u64>,
+}
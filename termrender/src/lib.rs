@@ -1,31 +1,73 @@
 use std::{
     io::{Stdout, Write},
-    sync::OnceLock,
     time::Duration,
 };
 
 use crossterm::{cursor, style::Print, QueueableCommand};
 
-pub(crate) fn stdout() -> &'static mut Stdout {
-    static mut STDOUT: OnceLock<Stdout> = OnceLock::new();
-    unsafe {
-        match STDOUT.get_mut() {
-            Some(out) => out,
-            None => {
-                STDOUT.set(std::io::stdout()).unwrap();
-                STDOUT.get_mut().unwrap()
-            }
-        }
-    }
+/// Every handle shares the buffer of the process, so queued commands survive the handle.
+pub(crate) fn stdout() -> Stdout {
+    std::io::stdout()
 }
 
 pub fn progress_bar<S, E, F>(
+    handler: F,
+    show_percent: bool,
+    progress_len: u16,
+    render_dur: Duration,
+    title: Option<S>,
+    fill_symbol: Option<u8>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsRef<str>,
+    E: std::error::Error + 'static,
+    F: FnMut() -> Result<f64, E>,
+{
+    render_bar(
+        handler,
+        show_percent,
+        progress_len,
+        render_dur,
+        title,
+        fill_symbol,
+        false,
+    )
+}
+
+/// Like [`progress_bar`], but drawn on the current line and ended with a newline, so bars printed
+/// one after another stack up with the rest of the output.
+pub fn progress_bar_inline<S, E, F>(
+    handler: F,
+    show_percent: bool,
+    progress_len: u16,
+    render_dur: Duration,
+    title: Option<S>,
+    fill_symbol: Option<u8>,
+) -> Result<(), Box<dyn std::error::Error>>
+where
+    S: AsRef<str>,
+    E: std::error::Error + 'static,
+    F: FnMut() -> Result<f64, E>,
+{
+    render_bar(
+        handler,
+        show_percent,
+        progress_len,
+        render_dur,
+        title,
+        fill_symbol,
+        true,
+    )
+}
+
+fn render_bar<S, E, F>(
     mut handler: F,
     show_percent: bool,
     progress_len: u16,
     render_dur: Duration,
     title: Option<S>,
     fill_symbol: Option<u8>,
+    inline: bool,
 ) -> Result<(), Box<dyn std::error::Error>>
 where
     S: AsRef<str>,
@@ -48,11 +90,12 @@ where
     let mut rendered_len: u16 = 0;
     let mut head_size = 0;
 
-    stdout()
-        .queue(cursor::MoveToNextLine(1))
-        .unwrap()
-        .queue(cursor::Hide)
-        .unwrap();
+    if inline {
+        stdout().queue(cursor::MoveToColumn(0)).unwrap();
+    } else {
+        stdout().queue(cursor::MoveToNextLine(1)).unwrap();
+    }
+    stdout().queue(cursor::Hide).unwrap();
     if let Some(t) = title {
         let s = t.as_ref();
         let title_size = s.len() as u16 + 2;
@@ -70,11 +113,11 @@ where
             )))
             .unwrap();
     }
-    let num_screen_cols = crossterm::terminal::size().unwrap().0;
+    let num_screen_cols = crossterm::terminal::size().map_or(80, |(cols, _)| cols);
     let max_progress_len = if progress_len == 0 {
-        num_screen_cols - head_size - 1
+        num_screen_cols.saturating_sub(head_size + 1)
     } else {
-        std::cmp::min(progress_len, num_screen_cols - head_size - 1)
+        std::cmp::min(progress_len, num_screen_cols.saturating_sub(head_size + 1))
     };
 
     stdout()
@@ -83,7 +126,7 @@ where
         .flush()
         .unwrap();
 
-    let symbol = if let Some(c) = fill_symbol { c } else { b'#' };
+    let symbol = fill_symbol.unwrap_or(b'#');
 
     while current_progress <= 1.0 {
         if show_percent {
@@ -104,7 +147,10 @@ where
         stdout()
             .queue(cursor::MoveToColumn(rendered_len + head_size))
             .unwrap()
-            .queue(Print(repeat_byte(symbol, curr_len - rendered_len)))
+            .queue(Print(repeat_byte(
+                symbol,
+                curr_len.saturating_sub(rendered_len),
+            )))
             .unwrap()
             .flush()
             .unwrap();
@@ -112,17 +158,16 @@ where
         std::thread::sleep(render_dur);
         current_progress = handler()?;
     }
-    stdout()
-        .queue(cursor::MoveToNextLine(2))
-        .unwrap()
-        .queue(cursor::Show)
-        .unwrap()
-        .flush()
-        .unwrap();
+    if inline {
+        stdout().queue(Print('\n')).unwrap();
+    } else {
+        stdout().queue(cursor::MoveToNextLine(2)).unwrap();
+    }
+    stdout().queue(cursor::Show).unwrap().flush().unwrap();
     Ok(())
 }
 
 pub(crate) fn repeat_byte(ch: u8, times: u16) -> String {
-    let bytes: Vec<u8> = std::iter::repeat(ch).take(times as usize).collect();
+    let bytes: Vec<u8> = std::iter::repeat_n(ch, times as usize).collect();
     unsafe { String::from_utf8_unchecked(bytes) }
 }